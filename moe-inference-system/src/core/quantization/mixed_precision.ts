@@ -25,9 +25,26 @@ interface QuantizerWasmModule {
     qat_enabled: boolean,
     learning_rate: number
   ) => Float32Array;
+  QuantizedMatrix: new (
+    weights: Float32Array,
+    rows: number,
+    cols: number,
+    bit_depth: number,
+    group_size: number
+  ) => QuantizedMatrixHandle;
   free_memory: () => void;
 }
 
+// Expert weights quantized once on the Rust side; reuse the handle for every matmul and
+// call free() when the expert is evicted or requantized
+export interface QuantizedMatrixHandle {
+  readonly rows: number;
+  readonly cols: number;
+  readonly bitDepth: number;
+  matmul: (x: Float32Array, m: number) => Float32Array;
+  free: () => void;
+}
+
 // Global reference to the loaded WebAssembly module
 let wasmModule: QuantizerWasmModule | null = null;
 
@@ -148,6 +165,52 @@ export async function invokeRustQuantizer({
   }
 }
 
+// Quantize an expert's (rows, cols) weights for the Rust INT4/INT8 kernels
+export async function createQuantizedMatrix({
+  weights,
+  rows,
+  cols,
+  bitDepth,
+  groupSize = 32,
+}: {
+  weights: Float32Array;
+  rows: number;
+  cols: number;
+  bitDepth: 4 | 8 | 16;
+  groupSize?: number;
+}): Promise<QuantizedMatrixHandle> {
+  await initWasmModule();
+
+  if (!wasmModule) {
+    throw new Error('WebAssembly module not initialized');
+  }
+
+  try {
+    return new wasmModule.QuantizedMatrix(weights, rows, cols, bitDepth, groupSize);
+  } catch (error) {
+    console.error('Weight quantization error:', error);
+    throw new Error(`Failed to quantize matrix: ${error}`);
+  }
+}
+
+// Run m activation rows against a quantized expert, so its weights are not widened back to
+// f32 or requantized before the matmul
+export function invokeQuantizedMatmul(
+  matrix: QuantizedMatrixHandle,
+  x: Float32Array
+): Float32Array {
+  if (x.length % matrix.cols !== 0) {
+    throw new Error(`Activation length ${x.length} is not a multiple of ${matrix.cols} columns`);
+  }
+
+  try {
+    return matrix.matmul(x, x.length / matrix.cols);
+  } catch (error) {
+    console.error('Quantized matmul error:', error);
+    throw new Error(`Failed to run quantized matmul: ${error}`);
+  }
+}
+
 export class MixedPrecisionQuantizer {
   async quantizeBatch(
     experts: Expert[],
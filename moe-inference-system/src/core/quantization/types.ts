@@ -1,4 +1,4 @@
-export type BitDepth = 1 | 4 | 8;
+export type BitDepth = 1 | 4 | 8 | 16; // 16 keeps the expert in FP16
 export type QuantizationMode = 'symmetric' | 'asymmetric';
 export type QuantizationTechnique = 'gptq' | 'qlora' | 'awq' | 'qat';
 
@@ -1,11 +1,49 @@
 import { RLState } from './types';
 import { BitDepth } from '../quantization/types';
 
-// TODO: Replace with actual Rust implementation when available
-async function invokeRustPPOPolicy(_state: RLState): Promise<{ bitDepth: BitDepth }> {
-  // Placeholder implementation
-  console.warn('Using placeholder PPO implementation. Replace with actual Rust implementation.');
-  return { bitDepth: 8 }; // Default to 8-bit
+// Exports of the Rust WebAssembly module used by the PPO policy
+interface PPOWasmModule {
+  invoke_ppo_policy: (state: string) => number;
+}
+
+// Global reference to the loaded WebAssembly module
+let wasmModule: PPOWasmModule | null = null;
+
+// Initialize WebAssembly module
+async function initWasmModule(): Promise<void> {
+  if (wasmModule) return;
+
+  try {
+    const response = await fetch('/wasm/quantizer.wasm');
+    const buffer = await response.arrayBuffer();
+    const module = await WebAssembly.instantiate(buffer, {
+      env: {
+        memory: new WebAssembly.Memory({ initial: 256, maximum: 1024 }),
+      },
+    });
+    // Cast to unknown first, then to the target type to avoid TypeScript errors
+    wasmModule = module.instance.exports as unknown as PPOWasmModule;
+  } catch (error) {
+    console.error('Failed to initialize WebAssembly module:', error);
+    throw new Error(`WebAssembly initialization failed: ${error}`);
+  }
+}
+
+// Sample a bit depth from the shared Rust PPO policy (`invoke_ppo_policy`), which returns 4, 8 or 16
+async function invokeRustPPOPolicy(state: RLState): Promise<{ bitDepth: BitDepth }> {
+  await initWasmModule();
+
+  if (!wasmModule) {
+    throw new Error('WebAssembly module not initialized');
+  }
+
+  try {
+    const bitDepth = wasmModule.invoke_ppo_policy(JSON.stringify(state));
+    return { bitDepth: bitDepth as BitDepth };
+  } catch (error) {
+    console.error('PPO policy error:', error);
+    throw new Error(`Failed to invoke PPO policy: ${error}`);
+  }
 }
 
 export class PPO {
@@ -14,4 +52,4 @@ export class PPO {
     const result = await invokeRustPPOPolicy(state);
     return result.bitDepth;
   }
-}
\ No newline at end of file
+}
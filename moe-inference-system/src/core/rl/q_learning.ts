@@ -1,4 +1,4 @@
-import { RLState, RLAction, RLReward, BitDepth, RLTransition } from './types';
+import { RLState, RLAction, RLReward, BitDepth, RLTransition, QLearningConfig } from './types';
 
 type QTableKey = string;
 type QValues = [number, number, number]; // [increase, decrease, maintain]
@@ -10,11 +10,20 @@ export class QLearning {
   private qTable: Map<QTableKey, QValues> = new Map();
   private lambda: number = 0.1; // Weight for latency in reward calculation
   private learningRate: number = 0.1;
+  private discountFactor: number = 0.9;
   private explorationRate: number = 0.1;
+  private explorationDecay: number = 0.995;
+  private minExplorationRate: number = 0.01;
   private readonly actions: RLAction['type'][] = ['increase', 'decrease', 'maintain'];
   private readonly bitDepths: BitDepth[] = [1, 4, 8];
 
-  constructor() {
+  constructor(config: QLearningConfig = {}) {
+    this.lambda = config.lambda ?? this.lambda;
+    this.learningRate = config.learningRate ?? this.learningRate;
+    this.discountFactor = config.discountFactor ?? this.discountFactor;
+    this.explorationRate = config.explorationRate ?? this.explorationRate;
+    this.explorationDecay = config.explorationDecay ?? this.explorationDecay;
+    this.minExplorationRate = config.minExplorationRate ?? this.minExplorationRate;
     this.selectBitDepth = this.selectBitDepth.bind(this);
     this.updateQTable = this.updateQTable.bind(this);
   }
@@ -47,7 +56,7 @@ export class QLearning {
    * @param transition The transition object containing state, action, reward, and nextState
    */
   public async update(transition: RLTransition): Promise<void> {
-    const { state, action, reward } = transition;
+    const { state, action, reward, nextState } = transition;
     // Convert reward number to RLReward interface if needed
     const rlReward: RLReward = typeof reward === 'number' 
       ? {
@@ -56,7 +65,7 @@ export class QLearning {
           metadata: {}
         }
       : reward;
-    await this.updateQTable(state, action, rlReward);
+    await this.updateQTable(state, action, rlReward, nextState);
   }
 
   /**
@@ -64,9 +73,15 @@ export class QLearning {
    * @param state The state before taking the action
    * @param action The action taken
    * @param reward The observed reward
+   * @param nextState The state after taking the action (defaults to applying the action to `state`)
    * @private
    */
-  private async updateQTable(state: RLState, action: RLAction, reward: RLReward): Promise<void> {
+  private async updateQTable(
+    state: RLState,
+    action: RLAction,
+    reward: RLReward,
+    nextState?: RLState
+  ): Promise<void> {
     const stateKey = this.getStateKey(state);
     const qValues = this.getQValues(stateKey);
     const actionIdx = this.actions.indexOf(action.type);
@@ -78,10 +93,20 @@ export class QLearning {
     // Calculate reward: accuracy - (latency * lambda) - tokenDrop
     const rewardValue = reward.accuracy - (reward.latency * this.lambda) - (reward.tokenDrop || 0);
     
-    // Q-learning update rule: Q(s,a) = Q(s,a) + α * (r - Q(s,a))
-    qValues[actionIdx] = qValues[actionIdx] + this.learningRate * (rewardValue - qValues[actionIdx]);
-    
+    // Bellman update: Q(s,a) = Q(s,a) + α * (r + γ * max_a' Q(s',a') - Q(s,a))
+    const next = nextState ?? {
+      ...state,
+      currentBitDepth: this.applyAction(state.currentBitDepth, action.type),
+    };
+    const nextMax = Math.max(...this.getQValues(this.getStateKey(next)));
+    const target = rewardValue + this.discountFactor * nextMax;
+    qValues[actionIdx] = qValues[actionIdx] + this.learningRate * (target - qValues[actionIdx]);
+
     this.qTable.set(stateKey, qValues);
+    this.explorationRate = Math.max(
+      this.minExplorationRate,
+      this.explorationRate * this.explorationDecay
+    );
   }
 
   /**
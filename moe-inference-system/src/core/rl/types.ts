@@ -1,7 +1,7 @@
 /**
- * Supported bit depths for model quantization
+ * Supported bit depths for model quantization (16 keeps the expert in FP16)
  */
-export type BitDepth = 1 | 4 | 8;
+export type BitDepth = 1 | 4 | 8 | 16;
 
 /**
  * Represents the state in the RL environment
@@ -0,0 +1,400 @@
+use crate::checkpoint::{read_json, write_json, CheckpointHeader, PolicyCheckpoint};
+use crate::expert_registry::SharedRegistry;
+use crate::gating::GatingFunction;
+use crate::hardware::HARDWARE_FEATURES;
+use crate::policy_engine::{
+    bit_depth_index, hardware_index, BitDepth, BitPrecisionPolicy, ExpertId, ExpertStats, HardwareProfile,
+    BIT_DEPTHS, NUM_BIT_DEPTHS,
+};
+use crate::reward::SharedReward;
+use crate::rng::{default_rng, standard_normal, SharedRng};
+use crate::trace_buffer::InferenceTrace;
+use candle_core::{Error as CandleError, Tensor};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Context vector [bias | hardware one-hot | recent latency, accuracy, token loss | ln(1 + input size) |
+// hardware features]
+pub const CONTEXT_FEATURES: usize = 8 + HARDWARE_FEATURES;
+
+pub fn context_features(hardware: &HardwareProfile, stats: &ExpertStats, input_size: usize) -> Vec<f32> {
+    let mut features = vec![0.0f32; CONTEXT_FEATURES];
+    features[0] = 1.0;
+    features[1 + hardware_index(hardware)] = 1.0;
+    features[4] = stats.latency;
+    features[5] = stats.accuracy;
+    features[6] = stats.token_loss;
+    features[7] = (input_size as f32).ln_1p();
+    features[8..].copy_from_slice(&hardware.features());
+    features
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BanditConfig {
+    pub alpha: f32,           // LinUCB confidence width
+    pub prior_mean: f32,      // prior mean of every reward weight
+    pub prior_precision: f32, // ridge strength; the prior covariance is noise_variance / prior_precision
+    pub noise_variance: f32,  // Gaussian reward noise assumed by Thompson sampling
+    pub stats_decay: f32,
+}
+
+impl Default for BanditConfig {
+    fn default() -> Self {
+        BanditConfig {
+            alpha: 1.0,
+            prior_mean: 0.0,
+            prior_precision: 1.0,
+            noise_variance: 0.25,
+            stats_decay: 0.9,
+        }
+    }
+}
+
+// Bayesian linear reward model of one arm: A = λI + Σ x xᵀ, b = λμ₀ + Σ r x.
+// A⁻¹ is kept directly and updated with Sherman-Morrison.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LinearArm {
+    a_inv: Vec<f32>, // CONTEXT_FEATURES x CONTEXT_FEATURES, row-major
+    b: Vec<f32>,
+}
+
+impl LinearArm {
+    fn new(config: &BanditConfig) -> Self {
+        let d = CONTEXT_FEATURES;
+        let precision = config.prior_precision.max(1e-6);
+        let mut a_inv = vec![0.0f32; d * d];
+        for i in 0..d {
+            a_inv[i * d + i] = 1.0 / precision;
+        }
+        LinearArm {
+            a_inv,
+            b: vec![precision * config.prior_mean; d],
+        }
+    }
+
+    fn a_inv_times(&self, x: &[f32]) -> Vec<f32> {
+        self.a_inv.chunks(CONTEXT_FEATURES).map(|row| dot(row, x)).collect()
+    }
+
+    // Posterior mean of the weights
+    fn theta(&self) -> Vec<f32> {
+        self.a_inv_times(&self.b)
+    }
+
+    // xᵀ A⁻¹ x, the posterior variance of the predicted reward up to the noise scale
+    fn variance(&self, x: &[f32]) -> f32 {
+        dot(x, &self.a_inv_times(x)).max(0.0)
+    }
+
+    fn update(&mut self, x: &[f32], reward: f32) {
+        let d = CONTEXT_FEATURES;
+        let ax = self.a_inv_times(x);
+        let denom = 1.0 + dot(x, &ax);
+        for i in 0..d {
+            for j in 0..d {
+                self.a_inv[i * d + j] -= ax[i] * ax[j] / denom;
+            }
+        }
+        for (b, xi) in self.b.iter_mut().zip(x) {
+            *b += reward * xi;
+        }
+    }
+
+    // θ ~ N(θ̂, scale² A⁻¹), drawn through the Cholesky factor of A⁻¹
+    fn sample_theta(&self, scale: f32, rng: &mut StdRng) -> Vec<f32> {
+        let d = CONTEXT_FEATURES;
+        let l = cholesky(&self.a_inv, d);
+        let z: Vec<f32> = (0..d).map(|_| standard_normal(rng)).collect();
+        let mut theta = self.theta();
+        for i in 0..d {
+            theta[i] += scale * dot(&l[i * d..i * d + i + 1], &z[..i + 1]);
+        }
+        theta
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// Lower-triangular L with L Lᵀ = m; pivots are floored so round-off in A⁻¹ cannot break it
+fn cholesky(m: &[f32], d: usize) -> Vec<f32> {
+    let mut l = vec![0.0f32; d * d];
+    for i in 0..d {
+        for j in 0..=i {
+            let sum = m[i * d + j] - dot(&l[i * d..i * d + j], &l[j * d..j * d + j]);
+            l[i * d + j] = if i == j {
+                sum.max(1e-12).sqrt()
+            } else {
+                sum / l[j * d + j]
+            };
+        }
+    }
+    l
+}
+
+// State shared by both bandits: one linear arm per bit depth plus per-expert outcome averages
+struct ContextualBandit {
+    gate: Rc<dyn GatingFunction>,
+    registry: SharedRegistry,
+    arms: Vec<LinearArm>, // indexed like BIT_DEPTHS
+    expert_stats: HashMap<String, ExpertStats>,
+    config: BanditConfig,
+    reward: SharedReward,
+    rng: SharedRng,
+}
+
+// Tokens in a (..., hidden) input, the unit of `InferenceTrace::input_size`
+fn token_count(input: &Tensor) -> usize {
+    let hidden = input.dims().last().copied().unwrap_or(1).max(1);
+    input.elem_count() / hidden
+}
+
+impl ContextualBandit {
+    fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward, config: BanditConfig) -> Self {
+        ContextualBandit {
+            gate,
+            registry,
+            arms: BIT_DEPTHS.iter().map(|_| LinearArm::new(&config)).collect(),
+            expert_stats: HashMap::new(),
+            config,
+            reward,
+            rng: default_rng(),
+        }
+    }
+
+    fn context(&self, expert_id: &ExpertId, hardware: &HardwareProfile, input_size: usize) -> Vec<f32> {
+        let stats = self.expert_stats.get(&expert_id.0).copied().unwrap_or_default();
+        context_features(hardware, &stats, input_size)
+    }
+
+    // Route the input, then give every active routed expert the arm with the highest score
+    fn select(
+        &self,
+        input_tensor: &Tensor,
+        hardware: &HardwareProfile,
+        score: impl Fn(&LinearArm, &[f32]) -> f32,
+    ) -> Vec<(ExpertId, BitDepth)> {
+        let routed = match self.gate.route(input_tensor) {
+            Ok(routed) => routed,
+            Err(_) => return Vec::new(),
+        };
+        let registry = self.registry.borrow();
+        routed
+            .into_iter()
+            .filter(|(expert, _)| registry.is_active(expert))
+            .map(|(expert, _)| {
+                let x = self.context(&expert, hardware, token_count(input_tensor));
+                (expert, BIT_DEPTHS[self.best_arm(&x, &score)])
+            })
+            .collect()
+    }
+
+    // Highest-scoring arm, INT8 if no score is comparable
+    fn best_arm(&self, x: &[f32], score: impl Fn(&LinearArm, &[f32]) -> f32) -> usize {
+        (0..self.arms.len())
+            .map(|a| (a, score(&self.arms[a], x)))
+            .fold((bit_depth_index(BitDepth::INT8), f32::NEG_INFINITY), |best, cur| if cur.1 > best.1 { cur } else { best })
+            .0
+    }
+
+    fn update(&mut self, trace: &InferenceTrace) {
+        let x = self.context(&trace.expert_id, &trace.hardware_profile, trace.input_size);
+        let reward = self.reward.borrow().reward(trace);
+        self.arms[bit_depth_index(trace.bit_depth)].update(&x, reward);
+        self.expert_stats
+            .entry(trace.expert_id.0.clone())
+            .or_default()
+            .observe(trace, self.config.stats_decay);
+    }
+
+    fn save(&self, policy: &str) -> Result<Vec<u8>, CandleError> {
+        let header = CheckpointHeader::new(policy, &bandit_schema(), &self.config)?;
+        write_json(
+            header,
+            &BanditState {
+                arms: self.arms.clone(),
+                expert_stats: self.expert_stats.clone(),
+            },
+        )
+    }
+
+    fn load(&mut self, data: &[u8], policy: &str) -> Result<(), CandleError> {
+        let (header, state): (_, BanditState) = read_json(data, policy, &bandit_schema())?;
+        let arms_valid = state.arms.len() == BIT_DEPTHS.len()
+            && state.arms.iter().all(|arm| {
+                arm.a_inv.len() == CONTEXT_FEATURES * CONTEXT_FEATURES && arm.b.len() == CONTEXT_FEATURES
+            });
+        if !arms_valid {
+            return Err(CandleError::Msg("Checkpoint arms do not match the context layout".to_string()));
+        }
+        self.config = header.hyperparameters()?;
+        self.arms = state.arms;
+        self.expert_stats = state.expert_stats;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BanditState {
+    arms: Vec<LinearArm>,
+    expert_stats: HashMap<String, ExpertStats>,
+}
+
+fn bandit_schema() -> String {
+    format!("linear:context={}:arms={}", CONTEXT_FEATURES, BIT_DEPTHS.len())
+}
+
+// LinUCB: picks the bit depth maximizing θ̂ᵀx + α·sqrt(xᵀA⁻¹x)
+pub struct LinUcbPolicy {
+    bandit: ContextualBandit,
+}
+
+impl LinUcbPolicy {
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward) -> Self {
+        Self::with_config(gate, registry, reward, BanditConfig::default())
+    }
+
+    pub fn with_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        config: BanditConfig,
+    ) -> Self {
+        LinUcbPolicy {
+            bandit: ContextualBandit::new(gate, registry, reward, config),
+        }
+    }
+
+    // Upper confidence bound of every bit depth (ordered like BIT_DEPTHS) for one context
+    pub fn upper_bounds(&self, context: &[f32]) -> Vec<f32> {
+        let alpha = self.bandit.config.alpha;
+        self.bandit
+            .arms
+            .iter()
+            .map(|arm| dot(&arm.theta(), context) + alpha * arm.variance(context).sqrt())
+            .collect()
+    }
+}
+
+// Monte Carlo draws used to estimate Thompson sampling's selection probabilities
+const THOMPSON_PROBABILITY_SAMPLES: usize = 256;
+
+impl BitPrecisionPolicy for LinUcbPolicy {
+    fn select_experts(
+        &self,
+        input_tensor: &Tensor,
+        hardware_profile: &HardwareProfile,
+    ) -> Vec<(ExpertId, BitDepth)> {
+        let alpha = self.bandit.config.alpha;
+        self.bandit.select(input_tensor, hardware_profile, |arm, x| {
+            dot(&arm.theta(), x) + alpha * arm.variance(x).sqrt()
+        })
+    }
+
+    fn update_policy(&mut self, trace: InferenceTrace) {
+        self.bandit.update(&trace);
+    }
+
+    // Deterministic: all mass on the arm with the highest upper bound
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let x = self.bandit.context(&trace.expert_id, &trace.hardware_profile, trace.input_size);
+        let alpha = self.bandit.config.alpha;
+        let best = self
+            .bandit
+            .best_arm(&x, |arm, x| dot(&arm.theta(), x) + alpha * arm.variance(x).sqrt());
+        let mut probs = [0.0; NUM_BIT_DEPTHS];
+        probs[best] = 1.0;
+        probs
+    }
+}
+
+// Linear Thompson sampling: draws reward weights from each arm's Gaussian posterior
+// and picks the bit depth whose sample predicts the highest reward
+pub struct ThompsonSamplingPolicy {
+    bandit: ContextualBandit,
+}
+
+impl ThompsonSamplingPolicy {
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward) -> Self {
+        Self::with_config(gate, registry, reward, BanditConfig::default())
+    }
+
+    pub fn with_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        config: BanditConfig,
+    ) -> Self {
+        ThompsonSamplingPolicy {
+            bandit: ContextualBandit::new(gate, registry, reward, config),
+        }
+    }
+
+    // Replace the source of posterior draws, e.g. with a seeded one to replay a run
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.bandit.rng = rng;
+    }
+
+    // Posterior mean reward of every bit depth (ordered like BIT_DEPTHS) for one context
+    pub fn expected_rewards(&self, context: &[f32]) -> Vec<f32> {
+        self.bandit.arms.iter().map(|arm| dot(&arm.theta(), context)).collect()
+    }
+}
+
+impl BitPrecisionPolicy for ThompsonSamplingPolicy {
+    fn select_experts(
+        &self,
+        input_tensor: &Tensor,
+        hardware_profile: &HardwareProfile,
+    ) -> Vec<(ExpertId, BitDepth)> {
+        let scale = self.bandit.config.noise_variance.max(0.0).sqrt();
+        let rng = &self.bandit.rng;
+        self.bandit.select(input_tensor, hardware_profile, |arm, x| {
+            dot(&arm.sample_theta(scale, &mut rng.borrow_mut()), x)
+        })
+    }
+
+    fn update_policy(&mut self, trace: InferenceTrace) {
+        self.bandit.update(&trace);
+    }
+
+    // Probability that each arm's posterior draw wins, estimated by sampling
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let x = self.bandit.context(&trace.expert_id, &trace.hardware_profile, trace.input_size);
+        let scale = self.bandit.config.noise_variance.max(0.0).sqrt();
+        let mut probs = [0.0; NUM_BIT_DEPTHS];
+        for _ in 0..THOMPSON_PROBABILITY_SAMPLES {
+            let best = self
+                .bandit
+                .best_arm(&x, |arm, x| dot(&arm.sample_theta(scale, &mut self.bandit.rng.borrow_mut()), x));
+            probs[best] += 1.0 / THOMPSON_PROBABILITY_SAMPLES as f32;
+        }
+        probs
+    }
+}
+
+impl PolicyCheckpoint for LinUcbPolicy {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError> {
+        self.bandit.save("lin_ucb")
+    }
+
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError> {
+        self.bandit.load(data, "lin_ucb")
+    }
+}
+
+impl PolicyCheckpoint for ThompsonSamplingPolicy {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError> {
+        self.bandit.save("thompson")
+    }
+
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError> {
+        self.bandit.load(data, "thompson")
+    }
+}
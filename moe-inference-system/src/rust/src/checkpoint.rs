@@ -0,0 +1,145 @@
+use candle_core::{Device, Error as CandleError, Tensor};
+use safetensors::SafeTensors;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Bumped whenever the layout of a checkpoint changes incompatibly
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+// Save and restore everything a policy has learned, so it can warm start and keep learning
+pub trait PolicyCheckpoint {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError>;
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError>;
+}
+
+// Identifies what a checkpoint contains. `schema` describes the state/action layout the
+// learned values depend on; `hyperparameters` is the policy's serialized config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointHeader {
+    pub version: u32,
+    pub policy: String,
+    pub schema: String,
+    pub hyperparameters: serde_json::Value,
+}
+
+impl CheckpointHeader {
+    pub fn new<C: Serialize>(policy: &str, schema: &str, hyperparameters: &C) -> Result<Self, CandleError> {
+        Ok(CheckpointHeader {
+            version: CHECKPOINT_VERSION,
+            policy: policy.to_string(),
+            schema: schema.to_string(),
+            hyperparameters: serde_json::to_value(hyperparameters).map_err(|e| CandleError::Msg(e.to_string()))?,
+        })
+    }
+
+    fn validate(&self, policy: &str, schema: &str) -> Result<(), CandleError> {
+        if self.version != CHECKPOINT_VERSION {
+            return Err(CandleError::Msg(format!(
+                "Checkpoint version {} is not supported (expected {})",
+                self.version, CHECKPOINT_VERSION
+            )));
+        }
+        if self.policy != policy {
+            return Err(CandleError::Msg(format!(
+                "Checkpoint is for policy {}, not {}",
+                self.policy, policy
+            )));
+        }
+        if self.schema != schema {
+            return Err(CandleError::Msg(format!(
+                "Checkpoint schema {} does not match {}",
+                self.schema, schema
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn hyperparameters<C: DeserializeOwned>(&self) -> Result<C, CandleError> {
+        serde_json::from_value(self.hyperparameters.clone()).map_err(|e| CandleError::Msg(e.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonCheckpoint<S> {
+    header: CheckpointHeader,
+    state: S,
+}
+
+// JSON document {"header": ..., "state": ...}
+pub fn write_json<S: Serialize>(header: CheckpointHeader, state: &S) -> Result<Vec<u8>, CandleError> {
+    serde_json::to_vec(&JsonCheckpoint { header, state }).map_err(|e| CandleError::Msg(e.to_string()))
+}
+
+pub fn read_json<S: DeserializeOwned>(
+    data: &[u8],
+    policy: &str,
+    schema: &str,
+) -> Result<(CheckpointHeader, S), CandleError> {
+    let checkpoint: JsonCheckpoint<S> = serde_json::from_slice(data).map_err(|e| CandleError::Msg(e.to_string()))?;
+    checkpoint.header.validate(policy, schema)?;
+    Ok((checkpoint.header, checkpoint.state))
+}
+
+// Policy a JSON or safetensors checkpoint was saved from, read from its header without
+// validating or loading the rest
+pub fn checkpoint_policy(data: &[u8]) -> Result<String, CandleError> {
+    #[derive(Deserialize)]
+    struct Tagged {
+        header: CheckpointHeader,
+    }
+    if let Ok(tagged) = serde_json::from_slice::<Tagged>(data) {
+        return Ok(tagged.header.policy);
+    }
+    let (_, metadata) =
+        SafeTensors::read_metadata(data).map_err(|_| CandleError::Msg("Not a policy checkpoint".to_string()))?;
+    let header = metadata
+        .metadata()
+        .as_ref()
+        .and_then(|m| m.get("header"))
+        .ok_or_else(|| CandleError::Msg("Checkpoint metadata has no header".to_string()))?;
+    let header: CheckpointHeader = serde_json::from_str(header).map_err(|e| CandleError::Msg(e.to_string()))?;
+    Ok(header.policy)
+}
+
+// Safetensors file with the header and non-tensor state as JSON strings in its metadata
+pub fn write_safetensors<S: Serialize>(
+    header: CheckpointHeader,
+    tensors: Vec<(String, Tensor)>,
+    state: &S,
+) -> Result<Vec<u8>, CandleError> {
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        "header".to_string(),
+        serde_json::to_string(&header).map_err(|e| CandleError::Msg(e.to_string()))?,
+    );
+    metadata.insert(
+        "state".to_string(),
+        serde_json::to_string(state).map_err(|e| CandleError::Msg(e.to_string()))?,
+    );
+    safetensors::tensor::serialize(tensors, &Some(metadata)).map_err(|e| CandleError::Msg(e.to_string()))
+}
+
+pub fn read_safetensors<S: DeserializeOwned>(
+    data: &[u8],
+    policy: &str,
+    schema: &str,
+) -> Result<(CheckpointHeader, HashMap<String, Tensor>, S), CandleError> {
+    let (_, metadata) = SafeTensors::read_metadata(data).map_err(|e| CandleError::Msg(e.to_string()))?;
+    let metadata = metadata
+        .metadata()
+        .clone()
+        .ok_or_else(|| CandleError::Msg("Checkpoint has no metadata".to_string()))?;
+    let field = |key: &str| {
+        metadata
+            .get(key)
+            .ok_or_else(|| CandleError::Msg(format!("Checkpoint metadata has no {}", key)))
+    };
+    let header: CheckpointHeader =
+        serde_json::from_str(field("header")?).map_err(|e| CandleError::Msg(e.to_string()))?;
+    header.validate(policy, schema)?;
+    let state: S = serde_json::from_str(field("state")?).map_err(|e| CandleError::Msg(e.to_string()))?;
+    let tensors = candle_core::safetensors::load_buffer(data, &Device::Cpu)?;
+    Ok((header, tensors, state))
+}
@@ -0,0 +1,369 @@
+use crate::hardware::{HardwareClass, HardwareProfile};
+use crate::policy_engine::{bit_depth_index, BitDepth, BIT_DEPTHS, NUM_BIT_DEPTHS};
+use crate::trace_buffer::InferenceTrace;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// One cost model shared by the optimizer and whatever plans with it (e.g. the Petri net)
+pub type SharedCostModel = Rc<RefCell<CostModel>>;
+
+// Expected cost of one request to an expert
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostEstimate {
+    pub latency: f32,   // ms
+    pub memory_mb: f32, // weights, quantization scales and runtime overhead
+    pub energy: f32,    // joules
+}
+
+impl std::ops::Add for CostEstimate {
+    type Output = CostEstimate;
+
+    fn add(self, other: CostEstimate) -> CostEstimate {
+        CostEstimate {
+            latency: self.latency + other.latency,
+            memory_mb: self.memory_mb.max(other.memory_mb), // stages run one after another
+            energy: self.energy + other.energy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CostModelConfig {
+    pub prior_base_latency: f32,           // ms per request
+    pub prior_latency_per_token: [f32; NUM_BIT_DEPTHS], // ms per token per million weights (INT4, INT8, FP16)
+    pub prior_bandwidth_gbps: f32,         // for profiles whose bandwidth was never calibrated
+    pub prior_power: f32,                  // watts
+    pub prior_variance: f32,               // confidence in the priors; larger lets traces override them faster
+    pub forgetting_factor: f32,            // RLS forgetting, < 1 tracks drifting hardware
+    pub learning_rate: f32,                // EMA step of the power and memory overhead estimates
+    pub block_size: usize,                 // weights per quantization scale, as in the registry
+    pub min_samples: u64,                  // traces before an uncalibrated fit is trusted for planning
+}
+
+impl Default for CostModelConfig {
+    fn default() -> Self {
+        CostModelConfig {
+            prior_base_latency: 0.1,
+            prior_latency_per_token: [0.02, 0.03, 0.06],
+            prior_bandwidth_gbps: 10.0,
+            prior_power: 15.0,
+            prior_variance: 1.0,
+            forgetting_factor: 0.995,
+            learning_rate: 0.05,
+            block_size: 32,
+            min_samples: 20,
+        }
+    }
+}
+
+// Recursive least squares fit of latency = base + load·M + compute·tokens·M, where M is the
+// expert size in millions of weights: a fixed cost, streaming the weights, and the matmuls
+#[derive(Debug, Clone)]
+struct LatencyFit {
+    theta: [f64; 3],
+    p: [[f64; 3]; 3],
+    samples: u64,
+    calibrated: bool,
+}
+
+impl LatencyFit {
+    fn new(theta: [f64; 3], variance: f64) -> Self {
+        let mut p = [[0.0; 3]; 3];
+        for (i, row) in p.iter_mut().enumerate() {
+            row[i] = variance;
+        }
+        LatencyFit {
+            theta,
+            p,
+            samples: 0,
+            calibrated: false,
+        }
+    }
+
+    fn features(num_params: usize, tokens: usize) -> [f64; 3] {
+        let millions = num_params as f64 / 1e6;
+        [1.0, millions, tokens as f64 * millions]
+    }
+
+    fn predict(&self, x: &[f64; 3]) -> f64 {
+        (0..3).map(|i| self.theta[i] * x[i]).sum::<f64>().max(0.0)
+    }
+
+    // k = P·x / (λ + xᵀ·P·x), θ += k·(y - θᵀ·x), P = (P - k·xᵀ·P) / λ
+    fn update(&mut self, x: &[f64; 3], y: f64, lambda: f64) {
+        let px: [f64; 3] = std::array::from_fn(|i| (0..3).map(|j| self.p[i][j] * x[j]).sum());
+        let denom = lambda + (0..3).map(|i| x[i] * px[i]).sum::<f64>();
+        let error = y - (0..3).map(|i| self.theta[i] * x[i]).sum::<f64>();
+        for (theta, px) in self.theta.iter_mut().zip(&px) {
+            *theta += px / denom * error;
+        }
+        // P is symmetric, so xᵀ·P = (P·x)ᵀ
+        for (row, px_i) in self.p.iter_mut().zip(&px) {
+            for (p, px_j) in row.iter_mut().zip(&px) {
+                *p = (*p - px_i * px_j / denom) / lambda;
+            }
+        }
+        self.samples += 1;
+    }
+}
+
+// Predicts latency, memory and energy of running an expert at each bit depth on a hardware
+// class. Latency starts from priors or `calibrate` and is refined by every observed trace;
+// memory is the analytic weight footprint plus a learned runtime overhead per class;
+// energy is latency times a learned power draw.
+pub struct CostModel {
+    config: CostModelConfig,
+    latency: HashMap<(HardwareClass, usize), LatencyFit>,
+    power: HashMap<(HardwareClass, usize), f32>,
+    memory_overhead: HashMap<HardwareClass, f32>,
+}
+
+impl CostModel {
+    pub fn new(config: CostModelConfig) -> Self {
+        CostModel {
+            config,
+            latency: HashMap::new(),
+            power: HashMap::new(),
+            memory_overhead: HashMap::new(),
+        }
+    }
+
+    pub fn shared(self) -> SharedCostModel {
+        Rc::new(RefCell::new(self))
+    }
+
+    pub fn config(&self) -> &CostModelConfig {
+        &self.config
+    }
+
+    fn prior(&self, profile: &HardwareProfile, bit_depth: BitDepth) -> LatencyFit {
+        let bandwidth = if profile.memory_bandwidth_gbps > 0.0 {
+            profile.memory_bandwidth_gbps
+        } else {
+            self.config.prior_bandwidth_gbps
+        };
+        // MB / (GB/s) = ms
+        let load = bit_depth.bits() as f32 / 8.0 / bandwidth.max(1e-3);
+        LatencyFit::new(
+            [
+                self.config.prior_base_latency as f64,
+                load as f64,
+                self.config.prior_latency_per_token[bit_depth_index(bit_depth)] as f64,
+            ],
+            self.config.prior_variance as f64,
+        )
+    }
+
+    // Storage of the weights at a bit depth: codes plus one f32 scale per block when quantized
+    pub fn weight_memory_mb(&self, num_params: usize, bit_depth: BitDepth) -> f32 {
+        let codes = num_params as f32 * bit_depth.bits() as f32 / 8.0;
+        let scales = match bit_depth {
+            BitDepth::FP16 => 0.0,
+            _ => num_params.div_ceil(self.config.block_size.max(1)) as f32 * 4.0,
+        };
+        (codes + scales) / 1e6
+    }
+
+    pub fn predict(
+        &self,
+        num_params: usize,
+        tokens: usize,
+        bit_depth: BitDepth,
+        profile: &HardwareProfile,
+    ) -> CostEstimate {
+        let class = profile.class();
+        let key = (class, bit_depth_index(bit_depth));
+        let x = LatencyFit::features(num_params, tokens);
+        let latency = match self.latency.get(&key) {
+            Some(fit) => fit.predict(&x),
+            None => self.prior(profile, bit_depth).predict(&x),
+        } as f32;
+        let power = self.power.get(&key).copied().unwrap_or(self.config.prior_power);
+        CostEstimate {
+            latency,
+            memory_mb: self.weight_memory_mb(num_params, bit_depth)
+                + self.memory_overhead.get(&class).copied().unwrap_or(0.0),
+            energy: latency / 1000.0 * power,
+        }
+    }
+
+    // Predictions for INT4, INT8 and FP16, in `BIT_DEPTHS` order
+    pub fn predict_all(&self, num_params: usize, tokens: usize, profile: &HardwareProfile) -> [CostEstimate; NUM_BIT_DEPTHS] {
+        BIT_DEPTHS.map(|d| self.predict(num_params, tokens, d, profile))
+    }
+
+    // Whether latency predictions for this class and depth come from measurements rather than priors
+    pub fn is_fitted(&self, class: HardwareClass, bit_depth: BitDepth) -> bool {
+        self.latency
+            .get(&(class, bit_depth_index(bit_depth)))
+            .is_some_and(|fit| fit.calibrated || fit.samples >= self.config.min_samples)
+    }
+
+    pub fn samples(&self, class: HardwareClass, bit_depth: BitDepth) -> u64 {
+        self.latency
+            .get(&(class, bit_depth_index(bit_depth)))
+            .map_or(0, |fit| fit.samples)
+    }
+
+    // Refine the model with a measured trace of an expert of `num_params` weights
+    pub fn observe(&mut self, trace: &InferenceTrace, num_params: usize) {
+        if !trace.latency.is_finite() || trace.latency <= 0.0 {
+            return;
+        }
+        let class = trace.hardware_profile.class();
+        let key = (class, bit_depth_index(trace.bit_depth));
+        let rate = self.config.learning_rate;
+        let lambda = self.config.forgetting_factor.clamp(0.5, 1.0) as f64;
+
+        let prior = self.prior(&trace.hardware_profile, trace.bit_depth);
+        let x = LatencyFit::features(num_params, trace.input_size);
+        self.latency
+            .entry(key)
+            .or_insert(prior)
+            .update(&x, trace.latency as f64, lambda);
+
+        if trace.energy_usage > 0.0 {
+            let measured = trace.energy_usage / (trace.latency / 1000.0);
+            let power = self.power.entry(key).or_insert(measured);
+            *power += rate * (measured - *power);
+        }
+        if trace.memory_usage > 0.0 {
+            let measured = (trace.memory_usage - self.weight_memory_mb(num_params, trace.bit_depth)).max(0.0);
+            let overhead = self.memory_overhead.entry(class).or_insert(measured);
+            *overhead += rate * (measured - *overhead);
+        }
+    }
+
+    // Time the quantized matmul kernels on this machine and fit the latency of each bit depth for
+    // the profile's class (FP16 is timed on the f32 path, as there is no half-precision kernel).
+    // Uses the profile's memory bandwidth, measuring it first if unknown. Returns false where no
+    // monotonic clock is available, and an error if the benchmark shape cannot be quantized.
+    pub fn calibrate(&mut self, profile: &mut HardwareProfile, config: &CostCalibrationConfig) -> Result<bool, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if profile.memory_bandwidth_gbps <= 0.0 {
+                crate::hardware::calibrate(profile, &crate::hardware::CalibrationConfig::default());
+            }
+            let class = profile.class();
+            let num_params = config.rows * config.cols;
+            let (few, many) = (config.tokens.0.max(1), config.tokens.1.max(config.tokens.0 + 1));
+            for bit_depth in BIT_DEPTHS {
+                let t_few = benchmark(bit_depth, config, few)?;
+                let t_many = benchmark(bit_depth, config, many)?;
+                let millions = num_params as f64 / 1e6;
+                // Kernels run from cache, so the token slope is compute; streaming comes from bandwidth
+                let compute = ((t_many - t_few) / ((many - few) as f64 * millions)).max(0.0);
+                let mut fit = self.prior(profile, bit_depth);
+                fit.theta[2] = compute;
+                fit.theta[0] = (t_few - compute * few as f64 * millions).max(0.0);
+                fit.calibrated = true;
+                self.latency.insert((class, bit_depth_index(bit_depth)), fit);
+            }
+            Ok(true)
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = (profile, config);
+            Ok(false)
+        }
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel::new(CostModelConfig::default())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CostCalibrationConfig {
+    pub rows: usize, // benchmark expert shape; small enough to stay in cache
+    pub cols: usize,
+    pub group_size: usize, // INT4 scale group
+    pub tokens: (usize, usize),
+    pub repetitions: usize,
+}
+
+impl Default for CostCalibrationConfig {
+    fn default() -> Self {
+        CostCalibrationConfig {
+            rows: 256,
+            cols: 256,
+            group_size: 32,
+            tokens: (1, 16),
+            repetitions: 5,
+        }
+    }
+}
+
+// Best-of-`repetitions` wall time in ms of one expert matmul over `tokens` rows
+#[cfg(not(target_arch = "wasm32"))]
+fn benchmark(bit_depth: BitDepth, config: &CostCalibrationConfig, tokens: usize) -> Result<f64, String> {
+    use crate::qmatmul::{detect_backend, dot_f32, matmul_i4_f32, matmul_i8, QuantizedMatrixI4, QuantizedMatrixI8};
+    use std::hint::black_box;
+
+    // Round the shape up to one the INT4 kernel accepts: even groups that tile every row
+    let group_size = config.group_size.max(2).next_multiple_of(2);
+    let (rows, cols) = (config.rows.max(1), config.cols.max(1).next_multiple_of(group_size));
+    let weights: Vec<f32> = (0..rows * cols).map(|i| ((i * 37 % 101) as f32 - 50.0) / 50.0).collect();
+    let x: Vec<f32> = (0..tokens * cols).map(|i| ((i * 13 % 29) as f32 - 14.0) / 14.0).collect();
+    let i8_weights = QuantizedMatrixI8::from_f32(&weights, rows, cols)?;
+    let i4_weights = QuantizedMatrixI4::from_f32(&weights, rows, cols, group_size)?;
+    let backend = detect_backend();
+
+    let mut best = f64::INFINITY;
+    for _ in 0..config.repetitions.max(1) {
+        let start = std::time::Instant::now();
+        match bit_depth {
+            BitDepth::INT4 => {
+                black_box(matmul_i4_f32(black_box(&x), &i4_weights, tokens).ok());
+            }
+            BitDepth::INT8 => {
+                black_box(matmul_i8(black_box(&x), &i8_weights, tokens).ok());
+            }
+            BitDepth::FP16 => {
+                let mut out = vec![0.0f32; tokens * rows];
+                for (x_row, out_row) in x.chunks(cols).zip(out.chunks_mut(rows)) {
+                    for (y, w_row) in out_row.iter_mut().zip(weights.chunks(cols)) {
+                        *y = dot_f32(backend, x_row, w_row);
+                    }
+                }
+                black_box(out);
+            }
+        }
+        best = best.min(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    Ok(best)
+}
+
+thread_local! {
+    // Host profile and cost model calibrated by the first `estimate_expert_costs` call
+    static CALIBRATED: RefCell<Option<(HardwareProfile, CostModel)>> = const { RefCell::new(None) };
+}
+
+// Predicted INT4/INT8/FP16 costs of an expert on this machine as JSON. The model is calibrated
+// with micro-benchmarks on the first call and reused afterwards.
+#[wasm_bindgen]
+pub fn estimate_expert_costs(num_params: usize, tokens: usize) -> Result<String, JsValue> {
+    CALIBRATED.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let (profile, model) = match &mut *slot {
+            Some(calibrated) => calibrated,
+            None => {
+                let mut profile = HardwareProfile::detect();
+                let mut model = CostModel::default();
+                model
+                    .calibrate(&mut profile, &CostCalibrationConfig::default())
+                    .map_err(|e| JsValue::from_str(&e))?;
+                slot.insert((profile, model))
+            }
+        };
+        serde_json::to_string(&model.predict_all(num_params, tokens, profile)).map_err(|e| JsValue::from_str(&e.to_string()))
+    })
+}
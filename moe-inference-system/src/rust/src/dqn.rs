@@ -0,0 +1,465 @@
+use crate::checkpoint::{read_safetensors, write_safetensors, CheckpointHeader, PolicyCheckpoint};
+use crate::expert_registry::SharedRegistry;
+use crate::exploration::{EpsilonGreedy, ExplorationConfig, ExplorationContext, ExplorationStrategy};
+use crate::gating::GatingFunction;
+use crate::nn::{Adam, Mlp};
+use crate::policy_engine::{
+    bit_depth_index, certain, state_features, states_tensor, BitDepth, BitPrecisionPolicy, ExpertId, ExpertStats,
+    HardwareProfile, BIT_DEPTHS, NUM_BIT_DEPTHS, STATE_FEATURES,
+};
+use crate::reward::SharedReward;
+use crate::rng::{default_rng, SharedRng};
+use crate::trace_buffer::InferenceTrace;
+use candle_core::{Device, Error as CandleError, Tensor};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DqnConfig {
+    pub hidden_size: usize,
+    pub learning_rate: f64,
+    pub gamma: f32,
+    pub batch_size: usize,
+    pub replay_capacity: usize,
+    pub warmup: usize,                 // transitions stored before training starts
+    pub train_every: usize,            // transitions between gradient steps
+    pub target_update_interval: usize, // gradient steps between target network syncs
+    pub double_dqn: bool,              // online network picks a', target network scores it
+    pub priority_alpha: f32,           // 0 is uniform replay
+    pub priority_beta: f32,            // importance-sampling correction, annealed to 1
+    pub priority_beta_increment: f32,  // per gradient step
+    pub priority_epsilon: f32,         // keeps zero-error transitions replayable
+    pub max_grad_norm: f64,
+    pub stats_decay: f32,
+    pub exploration: ExplorationConfig,
+}
+
+impl Default for DqnConfig {
+    fn default() -> Self {
+        DqnConfig {
+            hidden_size: 32,
+            learning_rate: 1e-3,
+            gamma: 0.9,
+            batch_size: 32,
+            replay_capacity: 10_000,
+            warmup: 64,
+            train_every: 1,
+            target_update_interval: 100,
+            double_dqn: true,
+            priority_alpha: 0.6,
+            priority_beta: 0.4,
+            priority_beta_increment: 1e-3,
+            priority_epsilon: 1e-3,
+            max_grad_norm: 1.0,
+            stats_decay: 0.9,
+            exploration: ExplorationConfig::EpsilonGreedy(EpsilonGreedy::default()),
+        }
+    }
+}
+
+// Encodes what the policy knows about an expert as `state_features`: the depth it last ran at
+// and running averages of its outcomes, so unseen experts and machines still map to a state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateEncoder {
+    current_depths: HashMap<String, BitDepth>,
+    expert_stats: HashMap<String, ExpertStats>,
+}
+
+impl StateEncoder {
+    pub fn encode(&self, expert_id: &ExpertId, fallback_depth: Option<BitDepth>, hardware: &HardwareProfile) -> Vec<f32> {
+        let bit_depth = self
+            .current_depths
+            .get(&expert_id.0)
+            .copied()
+            .or(fallback_depth)
+            .unwrap_or(BitDepth::INT8);
+        let stats = self.expert_stats.get(&expert_id.0).copied().unwrap_or_default();
+        state_features(expert_id, bit_depth, hardware, &stats)
+    }
+
+    pub fn observe(&mut self, trace: &InferenceTrace, stats_decay: f32) {
+        self.current_depths.insert(trace.expert_id.0.clone(), trace.bit_depth);
+        self.expert_stats
+            .entry(trace.expert_id.0.clone())
+            .or_default()
+            .observe(trace, stats_decay);
+    }
+}
+
+pub(crate) struct Transition {
+    pub(crate) state: Vec<f32>,
+    pub(crate) action: usize,
+    pub(crate) reward: f32,
+    pub(crate) next_state: Vec<f32>,
+}
+
+// Proportional prioritized replay (Schaul et al.): transitions are drawn with probability
+// p_i^α / Σ p^α from a sum tree, and weighted by (N·P(i))^-β / max w to correct the bias
+pub struct PrioritizedReplay {
+    capacity: usize,
+    transitions: Vec<Transition>,
+    next: usize,       // slot overwritten once full
+    tree: Vec<f32>,    // sum tree over priorities, leaves at [capacity, 2·capacity)
+    max_priority: f32, // given to new transitions so each is replayed at least once soon
+}
+
+impl PrioritizedReplay {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        PrioritizedReplay {
+            capacity,
+            transitions: Vec::with_capacity(capacity),
+            next: 0,
+            tree: vec![0.0; 2 * capacity],
+            max_priority: 1.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    fn total(&self) -> f32 {
+        self.tree[1]
+    }
+
+    fn set_priority(&mut self, slot: usize, priority: f32) {
+        let mut node = slot + self.capacity;
+        self.tree[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.tree[node] = self.tree[2 * node] + self.tree[2 * node + 1];
+        }
+    }
+
+    pub(crate) fn push(&mut self, transition: Transition) {
+        let slot = self.next;
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[slot] = transition;
+        }
+        self.next = (slot + 1) % self.capacity;
+        self.set_priority(slot, self.max_priority);
+    }
+
+    // Leaf whose cumulative priority range contains `mass`
+    fn find(&self, mut mass: f32) -> usize {
+        let mut node = 1;
+        while node < self.capacity {
+            let left = 2 * node;
+            if mass < self.tree[left] || self.tree[left + 1] <= 0.0 {
+                node = left;
+            } else {
+                mass -= self.tree[left];
+                node = left + 1;
+            }
+        }
+        (node - self.capacity).min(self.transitions.len() - 1)
+    }
+
+    // Stratified sample of `batch` slots with their normalized importance weights
+    pub(crate) fn sample(&self, batch: usize, beta: f32, rng: &mut StdRng) -> (Vec<usize>, Vec<f32>) {
+        let total = self.total();
+        let segment = total / batch as f32;
+        let slots: Vec<usize> = (0..batch)
+            .map(|i| self.find((i as f32 + rng.gen::<f32>()) * segment))
+            .collect();
+        let n = self.len() as f32;
+        let weights: Vec<f32> = slots
+            .iter()
+            .map(|&s| (n * self.tree[s + self.capacity] / total).max(f32::MIN_POSITIVE).powf(-beta))
+            .collect();
+        let max = weights.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+        (slots, weights.iter().map(|w| w / max).collect())
+    }
+
+    pub(crate) fn update_priorities(&mut self, slots: &[usize], td_errors: &[f32], alpha: f32, epsilon: f32) {
+        for (&slot, error) in slots.iter().zip(td_errors) {
+            let priority = (error.abs() + epsilon).powf(alpha);
+            self.max_priority = self.max_priority.max(priority);
+            self.set_priority(slot, priority);
+        }
+    }
+}
+
+// Deep Q-network over the ordered bit depths: an MLP maps `state_features` to one value per
+// depth, trained on prioritized replay against a periodically synced target network
+pub struct DqnPolicy {
+    gate: Rc<dyn GatingFunction>,
+    registry: SharedRegistry,
+    reward: SharedReward,
+    config: DqnConfig,
+    online: Mlp,
+    target: Mlp,
+    optimizer: Adam,
+    replay: PrioritizedReplay,
+    encoder: StateEncoder,
+    exploration: Box<dyn ExplorationStrategy>,
+    beta: f32,
+    transitions_seen: u64,
+    train_steps: u64,
+    last_loss: Option<f32>,
+    rng: SharedRng, // weight initialization, exploration and replay sampling
+}
+
+impl DqnPolicy {
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward) -> Result<Self, CandleError> {
+        Self::with_config(gate, registry, reward, DqnConfig::default())
+    }
+
+    pub fn with_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        config: DqnConfig,
+    ) -> Result<Self, CandleError> {
+        let rng = default_rng();
+        let (online, target) = Self::networks(&config, &mut rng.borrow_mut())?;
+        let optimizer = Adam::new(online.vars(), config.learning_rate)?;
+        Ok(DqnPolicy {
+            gate,
+            registry,
+            reward,
+            online,
+            target,
+            optimizer,
+            replay: PrioritizedReplay::new(config.replay_capacity),
+            encoder: StateEncoder::default(),
+            exploration: config.exploration.build(),
+            beta: config.priority_beta,
+            transitions_seen: 0,
+            train_steps: 0,
+            last_loss: None,
+            config,
+            rng,
+        })
+    }
+
+    // Online and target networks, starting from the same weights
+    fn networks(config: &DqnConfig, rng: &mut StdRng) -> Result<(Mlp, Mlp), CandleError> {
+        let device = Device::Cpu;
+        let sizes = [STATE_FEATURES, config.hidden_size, config.hidden_size, BIT_DEPTHS.len()];
+        let online = Mlp::new(&sizes, &device, rng)?;
+        let target = Mlp::new(&sizes, &device, rng)?;
+        target.copy_from(&online)?;
+        Ok((online, target))
+    }
+
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.rng = rng;
+    }
+
+    pub fn set_exploration(&mut self, exploration: Box<dyn ExplorationStrategy>) {
+        self.exploration = exploration;
+    }
+
+    pub fn config(&self) -> &DqnConfig {
+        &self.config
+    }
+
+    pub fn replay_len(&self) -> usize {
+        self.replay.len()
+    }
+
+    pub fn train_steps(&self) -> u64 {
+        self.train_steps
+    }
+
+    // Importance-weighted TD loss of the latest gradient step
+    pub fn last_loss(&self) -> Option<f32> {
+        self.last_loss
+    }
+
+    fn features(&self, expert_id: &ExpertId, hardware: &HardwareProfile) -> Vec<f32> {
+        let fallback = self.registry.borrow().bit_depth(expert_id);
+        self.encoder.encode(expert_id, fallback, hardware)
+    }
+
+    fn q_values(network: &Mlp, states: &[Vec<f32>]) -> Result<Vec<Vec<f32>>, CandleError> {
+        network.forward(&states_tensor(states)?)?.to_vec2::<f32>()
+    }
+
+    // Learned value of each bit depth for an expert, in `BIT_DEPTHS` order
+    pub fn depth_values(&self, expert_id: &ExpertId, hardware: &HardwareProfile) -> Result<Vec<f32>, CandleError> {
+        let state = self.features(expert_id, hardware);
+        Ok(Self::q_values(&self.online, &[state])?.remove(0))
+    }
+
+    pub fn select_bit_depth(&self, expert_id: &ExpertId, hardware: &HardwareProfile) -> BitDepth {
+        let Ok(values) = self.depth_values(expert_id, hardware) else {
+            return BitDepth::INT8;
+        };
+        let context = ExplorationContext {
+            state_key: &expert_id.0,
+            values: &values,
+        };
+        BIT_DEPTHS[self.exploration.select(&context, &mut self.rng.borrow_mut())]
+    }
+
+    fn train_step(&mut self) -> Result<(), CandleError> {
+        let device = Device::Cpu;
+        let batch = self.config.batch_size.max(1);
+        let (slots, weights) = self.replay.sample(batch, self.beta, &mut self.rng.borrow_mut());
+        let picked: Vec<&Transition> = slots.iter().map(|&s| &self.replay.transitions[s]).collect();
+        let states: Vec<Vec<f32>> = picked.iter().map(|t| t.state.clone()).collect();
+        let next_states: Vec<Vec<f32>> = picked.iter().map(|t| t.next_state.clone()).collect();
+
+        // Targets are plain numbers, so no gradient flows into the target network
+        let target_q = Self::q_values(&self.target, &next_states)?;
+        let next_actions: Vec<usize> = if self.config.double_dqn {
+            Self::q_values(&self.online, &next_states)?.iter().map(|q| argmax(q)).collect()
+        } else {
+            target_q.iter().map(|q| argmax(q)).collect()
+        };
+        let targets: Vec<f32> = picked
+            .iter()
+            .zip(&target_q)
+            .zip(&next_actions)
+            .map(|((t, q), &a)| t.reward + self.config.gamma * q[a])
+            .collect();
+
+        let actions = Tensor::new(picked.iter().map(|t| t.action as u32).collect::<Vec<_>>(), &device)?.unsqueeze(1)?;
+        let q = self.online.forward(&states_tensor(&states)?)?.gather(&actions, 1)?.squeeze(1)?;
+        let td = (q - Tensor::new(targets, &device)?)?;
+        let td_errors = td.to_vec1::<f32>()?;
+        let loss = (td.sqr()? * Tensor::new(weights, &device)?)?.mean_all()?;
+        self.last_loss = Some(loss.to_scalar::<f32>()?);
+        self.optimizer.backward_step(&loss, Some(self.config.max_grad_norm))?;
+
+        self.replay.update_priorities(&slots, &td_errors, self.config.priority_alpha, self.config.priority_epsilon);
+        self.beta = (self.beta + self.config.priority_beta_increment).min(1.0);
+        self.train_steps += 1;
+        if self.train_steps.is_multiple_of(self.config.target_update_interval.max(1) as u64) {
+            self.target.copy_from(&self.online)?;
+        }
+        Ok(())
+    }
+}
+
+// Highest value, first on ties
+fn argmax(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (i, &v)| if v > best.1 { (i, v) } else { best })
+        .0
+}
+
+impl BitPrecisionPolicy for DqnPolicy {
+    fn select_experts(
+        &self,
+        input_tensor: &Tensor,
+        hardware_profile: &HardwareProfile,
+    ) -> Vec<(ExpertId, BitDepth)> {
+        let routed = match self.gate.route(input_tensor) {
+            Ok(routed) => routed,
+            Err(_) => return Vec::new(),
+        };
+        routed
+            .into_iter()
+            .filter(|(expert, _)| self.registry.borrow().is_active(expert))
+            .map(|(expert, _)| {
+                let bit_depth = self.select_bit_depth(&expert, hardware_profile);
+                (expert, bit_depth)
+            })
+            .collect()
+    }
+
+    // The action is the depth the expert ran at; the next state is the expert at that depth
+    // with refreshed stats
+    fn update_policy(&mut self, trace: InferenceTrace) {
+        let state = self.features(&trace.expert_id, &trace.hardware_profile);
+        let reward = self.reward.borrow().reward(&trace);
+        self.encoder.observe(&trace, self.config.stats_decay);
+        let next_state = self.features(&trace.expert_id, &trace.hardware_profile);
+        self.replay.push(Transition {
+            state,
+            action: bit_depth_index(trace.bit_depth),
+            reward,
+            next_state,
+        });
+        self.transitions_seen += 1;
+        self.exploration.observe(&trace.expert_id.0, bit_depth_index(trace.bit_depth));
+        self.exploration.decay();
+
+        let due = self.transitions_seen.is_multiple_of(self.config.train_every.max(1) as u64);
+        if due && self.replay.len() >= self.config.warmup.max(self.config.batch_size) && self.train_step().is_err() {
+            self.last_loss = None;
+        }
+    }
+
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let Ok(values) = self.depth_values(&trace.expert_id, &trace.hardware_profile) else {
+            return certain(BitDepth::INT8);
+        };
+        let probs = self.exploration.probabilities(&ExplorationContext {
+            state_key: &trace.expert_id.0,
+            values: &values,
+        });
+        std::array::from_fn(|i| probs[i])
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DqnState {
+    encoder: StateEncoder,
+    exploration_rate: f32,
+    beta: f32,
+    train_steps: u64,
+}
+
+fn dqn_schema() -> String {
+    format!("dqn:state={}:actions={}", STATE_FEATURES, BIT_DEPTHS.len())
+}
+
+// Online and target weights go in the tensors; the replay buffer is not saved and refills
+// after a warm start
+impl PolicyCheckpoint for DqnPolicy {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError> {
+        let header = CheckpointHeader::new("dqn", &dqn_schema(), &self.config)?;
+        let mut tensors = self.online.named_tensors("online");
+        tensors.extend(self.target.named_tensors("target"));
+        write_safetensors(
+            header,
+            tensors,
+            &DqnState {
+                encoder: self.encoder.clone(),
+                exploration_rate: self.exploration.rate(),
+                beta: self.beta,
+                train_steps: self.train_steps,
+            },
+        )
+    }
+
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError> {
+        let (header, tensors, state): (_, _, DqnState) = read_safetensors(data, "dqn", &dqn_schema())?;
+        let config: DqnConfig = header.hyperparameters()?;
+        let (online, target) = Self::networks(&config, &mut self.rng.borrow_mut())?;
+        online.load_named_tensors("online", &tensors)?;
+        target.load_named_tensors("target", &tensors)?;
+        self.optimizer = Adam::new(online.vars(), config.learning_rate)?;
+        self.online = online;
+        self.target = target;
+        self.replay = PrioritizedReplay::new(config.replay_capacity);
+        self.exploration = config.exploration.build();
+        self.exploration.set_rate(state.exploration_rate);
+        self.config = config;
+        self.encoder = state.encoder;
+        self.beta = state.beta;
+        self.train_steps = state.train_steps;
+        self.transitions_seen = 0;
+        self.last_loss = None;
+        Ok(())
+    }
+}
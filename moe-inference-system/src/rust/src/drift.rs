@@ -0,0 +1,233 @@
+use crate::policy_engine::BitDepth;
+use crate::trace_buffer::InferenceTrace;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftDetectorKind {
+    PageHinkley,
+    Adwin,
+}
+
+// What a policy does about an expert whose reward shifted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftResponse {
+    BoostExploration, // explore the expert at `exploration_boost`, decaying back as usual
+    ResetState,       // forget what was learned about the expert
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DriftConfig {
+    pub detector: DriftDetectorKind,
+    pub delta: f32,         // Page-Hinkley: per-sample change tolerated as noise
+    pub threshold: f32,     // Page-Hinkley: cumulative deviation that raises an alarm
+    pub confidence: f32,    // ADWIN: false alarm probability per check
+    pub max_window: usize,  // ADWIN: rewards kept per expert
+    pub min_samples: usize, // rewards before an expert can alarm, and per ADWIN sub-window
+    pub response: DriftResponse,
+    pub exploration_boost: f32,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        DriftConfig {
+            detector: DriftDetectorKind::PageHinkley,
+            delta: 0.02,
+            threshold: 1.0,
+            confidence: 0.002,
+            max_window: 1000,
+            min_samples: 30,
+            response: DriftResponse::Both,
+            exploration_boost: 0.5,
+        }
+    }
+}
+
+// A detected change in the rewards of an expert at one bit depth on one hardware class
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DriftAlarm {
+    pub expert_id: String,
+    pub bit_depth: BitDepth,
+    pub hardware_class: String,
+    pub step: u64, // rewards the monitor had seen, over all experts
+    pub detector: DriftDetectorKind,
+    pub mean_before: f32,
+    pub mean_after: f32,
+}
+
+// Two-sided Page-Hinkley test: cumulative deviations from the running mean, alarming when they
+// drift more than `threshold` above their minimum (reward rose) or below their maximum (reward fell).
+// The samples since that extremum are the new regime.
+#[derive(Debug, Clone, Default)]
+struct PageHinkley {
+    count: u64,
+    mean: f32,
+    up: f32,
+    up_min: f32,
+    since_up_min: (f32, u32), // sum and count
+    down: f32,
+    down_max: f32,
+    since_down_max: (f32, u32),
+}
+
+impl PageHinkley {
+    // Returns the running mean and the mean since the change when an alarm is raised
+    fn update(&mut self, x: f32, config: &DriftConfig) -> Option<(f32, f32)> {
+        self.count += 1;
+        self.mean += (x - self.mean) / self.count as f32;
+
+        self.up += x - self.mean - config.delta;
+        if self.up <= self.up_min {
+            self.up_min = self.up;
+            self.since_up_min = (0.0, 0);
+        } else {
+            self.since_up_min = (self.since_up_min.0 + x, self.since_up_min.1 + 1);
+        }
+        self.down += x - self.mean + config.delta;
+        if self.down >= self.down_max {
+            self.down_max = self.down;
+            self.since_down_max = (0.0, 0);
+        } else {
+            self.since_down_max = (self.since_down_max.0 + x, self.since_down_max.1 + 1);
+        }
+
+        if self.count < config.min_samples as u64 {
+            return None;
+        }
+        let (sum, n) = if self.up - self.up_min > config.threshold {
+            self.since_up_min
+        } else if self.down_max - self.down > config.threshold {
+            self.since_down_max
+        } else {
+            return None;
+        };
+        Some((self.mean, sum / n.max(1) as f32))
+    }
+}
+
+// ADWIN over an exact window: drop the oldest rewards while some split of the window has
+// sub-window means further apart than the Hoeffding/Bernstein bound
+#[derive(Debug, Clone, Default)]
+struct Adwin {
+    window: VecDeque<f32>,
+}
+
+impl Adwin {
+    // Returns the means of the dropped and kept parts when the window was cut
+    fn update(&mut self, x: f32, config: &DriftConfig) -> Option<(f32, f32)> {
+        self.window.push_back(x);
+        if self.window.len() > config.max_window.max(2) {
+            self.window.pop_front();
+        }
+        let n = self.window.len();
+        let min_side = config.min_samples.max(1);
+        if n < 2 * min_side {
+            return None;
+        }
+        let total: f32 = self.window.iter().sum();
+        let mean = total / n as f32;
+        let variance = self.window.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n as f32;
+        let log_term = (2.0 * n as f32 / config.confidence.max(1e-9)).ln();
+
+        let mut cut = None;
+        let mut head = 0.0f32;
+        for (i, v) in self.window.iter().enumerate().take(n - min_side) {
+            head += v;
+            let n0 = i + 1;
+            if n0 < min_side {
+                continue;
+            }
+            let n1 = n - n0;
+            let m = 1.0 / (1.0 / n0 as f32 + 1.0 / n1 as f32);
+            let epsilon = (2.0 / m * variance * log_term).sqrt() + 2.0 / (3.0 * m) * log_term;
+            let (mean0, mean1) = (head / n0 as f32, (total - head) / n1 as f32);
+            if (mean0 - mean1).abs() > epsilon {
+                cut = Some((n0, mean0, mean1));
+            }
+        }
+        let (n0, mean0, mean1) = cut?;
+        self.window.drain(..n0);
+        Some((mean0, mean1))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Detector {
+    PageHinkley(PageHinkley),
+    Adwin(Adwin),
+}
+
+// Change-point detection over per-expert reward streams, with the history of every alarm.
+// Each expert has one stream per bit depth and hardware class: rewards differ between depths, so a
+// policy moving between them would otherwise look like drift.
+#[derive(Debug, Clone)]
+pub struct DriftMonitor {
+    config: DriftConfig,
+    detectors: HashMap<(String, BitDepth, String), Detector>,
+    history: Vec<DriftAlarm>,
+    step: u64,
+}
+
+impl DriftMonitor {
+    pub fn new(config: DriftConfig) -> Self {
+        DriftMonitor {
+            config,
+            detectors: HashMap::new(),
+            history: Vec::new(),
+            step: 0,
+        }
+    }
+
+    pub fn config(&self) -> &DriftConfig {
+        &self.config
+    }
+
+    // Feed the reward of a trace; returns the alarm if it completes a detected change
+    pub fn observe(&mut self, trace: &InferenceTrace, reward: f32) -> Option<DriftAlarm> {
+        if !reward.is_finite() {
+            return None;
+        }
+        self.step += 1;
+        let config = &self.config;
+        let hardware_class = trace.hardware_profile.class().as_str().to_string();
+        let key = (trace.expert_id.0.clone(), trace.bit_depth, hardware_class.clone());
+        let detector = self.detectors.entry(key).or_insert_with(|| match config.detector {
+            DriftDetectorKind::PageHinkley => Detector::PageHinkley(PageHinkley::default()),
+            DriftDetectorKind::Adwin => Detector::Adwin(Adwin::default()),
+        });
+
+        let (mean_before, mean_after) = match detector {
+            Detector::PageHinkley(ph) => {
+                let means = ph.update(reward, config)?;
+                // Start over so the new regime becomes the reference
+                *ph = PageHinkley::default();
+                means
+            }
+            Detector::Adwin(adwin) => adwin.update(reward, config)?,
+        };
+        let alarm = DriftAlarm {
+            expert_id: trace.expert_id.0.clone(),
+            bit_depth: trace.bit_depth,
+            hardware_class,
+            step: self.step,
+            detector: config.detector,
+            mean_before,
+            mean_after,
+        };
+        self.history.push(alarm.clone());
+        Some(alarm)
+    }
+
+    pub fn history(&self) -> &[DriftAlarm] {
+        &self.history
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+}
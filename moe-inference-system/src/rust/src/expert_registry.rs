@@ -0,0 +1,233 @@
+use crate::policy_engine::{BitDepth, ExpertId};
+use crate::quantization::{dequantize_block, quantize_block, QuantizationOptions, QuantizedBlock};
+use crate::trace_buffer::InferenceTrace;
+use candle_core::{DType, Device, Error as CandleError};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Registry shared between the policies and the optimizer
+pub type SharedRegistry = Rc<RefCell<ExpertRegistry>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExpertStatus {
+    Active,
+    Retired, // kept for bookkeeping, never routed to
+}
+
+// Running usage statistics of one expert
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ExpertUsage {
+    pub invocations: u64,
+    pub mean_latency: f32,
+    pub mean_accuracy: f32,
+    pub mean_token_loss: f32,
+    pub last_used: u64, // registry clock tick of the latest invocation
+}
+
+// Block-quantized copy of an expert's weights
+#[derive(Debug, Clone)]
+pub struct QuantizedWeights {
+    pub bit_depth: BitDepth,
+    pub block_size: usize, // rows per block, as in quantize_batch
+    pub blocks: Vec<QuantizedBlock>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpertEntry {
+    pub id: ExpertId,
+    pub layer: usize,
+    pub shape: Vec<usize>,
+    pub raw_weights: Vec<f32>,
+    pub quantized: Option<QuantizedWeights>, // None while the expert runs in FP16
+    pub bit_depth: BitDepth,
+    pub usage: ExpertUsage,
+    pub status: ExpertStatus,
+    pub metadata: HashMap<String, String>,
+}
+
+impl ExpertEntry {
+    pub fn num_params(&self) -> usize {
+        self.raw_weights.len()
+    }
+
+    // Weights as seen by inference at the current bit depth
+    pub fn effective_weights(&self) -> Vec<f32> {
+        match &self.quantized {
+            Some(q) => q.blocks.iter().flat_map(dequantize_block).collect(),
+            None => self.raw_weights.clone(),
+        }
+    }
+}
+
+pub struct ExpertRegistry {
+    experts: Vec<ExpertEntry>,
+    index: HashMap<String, usize>,
+    block_size: usize,
+    symmetric: bool,
+    options: QuantizationOptions,
+    clock: u64,
+}
+
+impl ExpertRegistry {
+    pub fn new(block_size: usize, symmetric: bool) -> Self {
+        ExpertRegistry {
+            experts: Vec::new(),
+            index: HashMap::new(),
+            block_size: block_size.max(1),
+            symmetric,
+            options: QuantizationOptions::default(),
+            clock: 0,
+        }
+    }
+
+    pub fn shared(self) -> SharedRegistry {
+        Rc::new(RefCell::new(self))
+    }
+
+    pub fn set_quantization_options(&mut self, options: QuantizationOptions) {
+        self.options = options;
+    }
+
+    // Register an expert with raw f32 weights of the given shape, quantized to `bit_depth`
+    pub fn register(
+        &mut self,
+        id: ExpertId,
+        layer: usize,
+        shape: Vec<usize>,
+        weights: Vec<f32>,
+        bit_depth: BitDepth,
+    ) -> Result<(), String> {
+        if self.index.contains_key(&id.0) {
+            return Err(format!("Expert {} is already registered", id.0));
+        }
+        if shape.iter().product::<usize>() != weights.len() {
+            return Err(format!("Shape {:?} does not match {} weights", shape, weights.len()));
+        }
+        let mut entry = ExpertEntry {
+            id: id.clone(),
+            layer,
+            shape,
+            raw_weights: weights,
+            quantized: None,
+            bit_depth,
+            usage: ExpertUsage::default(),
+            status: ExpertStatus::Active,
+            metadata: HashMap::new(),
+        };
+        entry.quantized = self.quantize(&entry, bit_depth);
+        self.index.insert(id.0, self.experts.len());
+        self.experts.push(entry);
+        Ok(())
+    }
+
+    // Register every 2D tensor of a safetensors file as an expert named after the tensor.
+    // The layer index is the first numeric component of the name ("layers.3.experts.1.w1" -> 3).
+    // Nothing is registered if any of the names is already taken.
+    pub fn load_safetensors(&mut self, data: &[u8], bit_depth: BitDepth) -> Result<Vec<ExpertId>, CandleError> {
+        let tensors = candle_core::safetensors::load_buffer(data, &Device::Cpu)?;
+        let mut names: Vec<&String> = tensors.keys().filter(|name| tensors[*name].rank() == 2).collect();
+        names.sort();
+        if let Some(taken) = names.iter().find(|name| self.index.contains_key(name.as_str())) {
+            return Err(CandleError::Msg(format!("Expert {} is already registered", taken)));
+        }
+
+        let mut loaded = Vec::new();
+        for name in names {
+            let tensor = &tensors[name];
+            let weights = tensor.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+            let layer = name.split('.').find_map(|part| part.parse::<usize>().ok()).unwrap_or(0);
+            let id = ExpertId(name.clone());
+            self.register(id.clone(), layer, tensor.dims().to_vec(), weights, bit_depth)
+                .map_err(CandleError::Msg)?;
+            loaded.push(id);
+        }
+        Ok(loaded)
+    }
+
+    fn quantize(&self, entry: &ExpertEntry, bit_depth: BitDepth) -> Option<QuantizedWeights> {
+        if bit_depth == BitDepth::FP16 {
+            return None;
+        }
+        let cols = entry.shape.last().copied().unwrap_or(1).max(1);
+        let mut rng = self.options.rng();
+        let blocks = entry
+            .raw_weights
+            .chunks(self.block_size * cols)
+            .map(|block| quantize_block(block, bit_depth.bits(), self.symmetric, &self.options, &mut rng))
+            .collect();
+        Some(QuantizedWeights {
+            bit_depth,
+            block_size: self.block_size,
+            blocks,
+        })
+    }
+
+    // Re-derive the quantized variant from the raw weights at a new bit depth
+    pub fn requantize(&mut self, id: &ExpertId, bit_depth: BitDepth) -> Result<(), String> {
+        let idx = *self.index.get(&id.0).ok_or_else(|| format!("Unknown expert {}", id.0))?;
+        if self.experts[idx].status == ExpertStatus::Retired {
+            return Err(format!("Expert {} is retired", id.0));
+        }
+        if self.experts[idx].bit_depth == bit_depth {
+            return Ok(());
+        }
+        let quantized = self.quantize(&self.experts[idx], bit_depth);
+        let entry = &mut self.experts[idx];
+        entry.quantized = quantized;
+        entry.bit_depth = bit_depth;
+        Ok(())
+    }
+
+    // Stop routing to an expert and drop its quantized copy; raw weights are kept
+    pub fn retire(&mut self, id: &ExpertId) -> Result<(), String> {
+        let entry = self.get_mut(id).ok_or_else(|| format!("Unknown expert {}", id.0))?;
+        entry.status = ExpertStatus::Retired;
+        entry.quantized = None;
+        Ok(())
+    }
+
+    pub fn record_usage(&mut self, trace: &InferenceTrace) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.get_mut(&trace.expert_id) {
+            let usage = &mut entry.usage;
+            usage.invocations += 1;
+            let n = usage.invocations as f32;
+            usage.mean_latency += (trace.latency - usage.mean_latency) / n;
+            usage.mean_accuracy += (trace.accuracy - usage.mean_accuracy) / n;
+            usage.mean_token_loss += (trace.token_loss - usage.mean_token_loss) / n;
+            usage.last_used = clock;
+        }
+    }
+
+    pub fn get(&self, id: &ExpertId) -> Option<&ExpertEntry> {
+        self.index.get(&id.0).map(|&i| &self.experts[i])
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &ExpertId) -> Option<&mut ExpertEntry> {
+        self.index.get(&id.0).map(|&i| &mut self.experts[i])
+    }
+
+    pub fn bit_depth(&self, id: &ExpertId) -> Option<BitDepth> {
+        self.get(id).map(|e| e.bit_depth)
+    }
+
+    pub fn is_active(&self, id: &ExpertId) -> bool {
+        self.get(id).is_some_and(|e| e.status == ExpertStatus::Active)
+    }
+
+    // Active experts in registration order
+    pub fn active(&self) -> impl Iterator<Item = &ExpertEntry> {
+        self.experts.iter().filter(|e| e.status == ExpertStatus::Active)
+    }
+
+    pub fn len(&self) -> usize {
+        self.experts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.experts.is_empty()
+    }
+}
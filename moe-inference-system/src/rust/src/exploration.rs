@@ -0,0 +1,333 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// What a strategy sees of one decision: the learned value of every action on the ordered
+// bit-depth ladder (lowest precision first, as in `BIT_DEPTHS`) and the state they belong to
+pub struct ExplorationContext<'a> {
+    pub state_key: &'a str,
+    pub values: &'a [f32],
+}
+
+// Turns action values into a choice, trading exploitation of the best known depth against
+// trying others. `probabilities` must match the distribution `select` samples from, so the
+// choice can be logged as a propensity for off-policy evaluation.
+pub trait ExplorationStrategy {
+    fn select(&self, context: &ExplorationContext, rng: &mut StdRng) -> usize;
+    fn probabilities(&self, context: &ExplorationContext) -> Vec<f32>;
+    // Action taken in a state, once its outcome was learned from
+    fn observe(&mut self, _state_key: &str, _action: usize) {}
+    // Anneal after every learning update
+    fn decay(&mut self) {}
+    // The strategy's main knob (ε, temperature or bonus scale), saved in checkpoints
+    fn rate(&self) -> f32;
+    fn set_rate(&mut self, rate: f32);
+}
+
+// Highest value, first on ties
+fn greedy(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (i, &v)| if v > best.1 { (i, v) } else { best })
+        .0
+}
+
+fn one_hot(len: usize, index: usize) -> Vec<f32> {
+    let mut probs = vec![0.0; len];
+    probs[index] = 1.0;
+    probs
+}
+
+fn sample(probs: &[f32], rng: &mut StdRng) -> usize {
+    let mut u = rng.gen::<f32>() * probs.iter().sum::<f32>();
+    for (i, p) in probs.iter().enumerate() {
+        if u < *p {
+            return i;
+        }
+        u -= p;
+    }
+    probs.len() - 1
+}
+
+// Uniformly random action with probability ε, the greedy one otherwise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EpsilonGreedy {
+    pub epsilon: f32,
+    pub decay: f32,
+    pub min_epsilon: f32,
+}
+
+impl Default for EpsilonGreedy {
+    fn default() -> Self {
+        EpsilonGreedy {
+            epsilon: 0.1,
+            decay: 0.995,
+            min_epsilon: 0.01,
+        }
+    }
+}
+
+impl ExplorationStrategy for EpsilonGreedy {
+    fn select(&self, context: &ExplorationContext, rng: &mut StdRng) -> usize {
+        if rng.gen::<f32>() < self.epsilon {
+            rng.gen_range(0..context.values.len())
+        } else {
+            greedy(context.values)
+        }
+    }
+
+    fn probabilities(&self, context: &ExplorationContext) -> Vec<f32> {
+        let n = context.values.len();
+        let mut probs = vec![self.epsilon / n as f32; n];
+        probs[greedy(context.values)] += 1.0 - self.epsilon;
+        probs
+    }
+
+    fn decay(&mut self) {
+        self.epsilon = (self.epsilon * self.decay).max(self.min_epsilon);
+    }
+
+    fn rate(&self) -> f32 {
+        self.epsilon
+    }
+
+    fn set_rate(&mut self, rate: f32) {
+        self.epsilon = rate;
+    }
+}
+
+// Softmax over values / temperature: near-best depths are tried more than clearly bad ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Boltzmann {
+    pub temperature: f32,
+    pub decay: f32,
+    pub min_temperature: f32,
+}
+
+impl Default for Boltzmann {
+    fn default() -> Self {
+        Boltzmann {
+            temperature: 0.5,
+            decay: 0.999,
+            min_temperature: 0.01,
+        }
+    }
+}
+
+impl ExplorationStrategy for Boltzmann {
+    fn select(&self, context: &ExplorationContext, rng: &mut StdRng) -> usize {
+        sample(&self.probabilities(context), rng)
+    }
+
+    fn probabilities(&self, context: &ExplorationContext) -> Vec<f32> {
+        let t = self.temperature.max(1e-6);
+        let max = context.values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exp: Vec<f32> = context.values.iter().map(|v| ((v - max) / t).exp()).collect();
+        let total: f32 = exp.iter().sum();
+        exp.iter().map(|e| e / total).collect()
+    }
+
+    fn decay(&mut self) {
+        self.temperature = (self.temperature * self.decay).max(self.min_temperature);
+    }
+
+    fn rate(&self) -> f32 {
+        self.temperature
+    }
+
+    fn set_rate(&mut self, rate: f32) {
+        self.temperature = rate;
+    }
+}
+
+// UCB1 per state: value + c·sqrt(ln N / n_a), trying every action once first. Deterministic
+// given the counts, so its propensities are one-hot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Ucb {
+    pub c: f32,
+    #[serde(skip)]
+    counts: HashMap<String, Vec<u32>>,
+}
+
+impl Default for Ucb {
+    fn default() -> Self {
+        Ucb::new(0.5)
+    }
+}
+
+impl Ucb {
+    pub fn new(c: f32) -> Self {
+        Ucb {
+            c,
+            counts: HashMap::new(),
+        }
+    }
+
+    fn choice(&self, context: &ExplorationContext) -> usize {
+        let Some(counts) = self.counts.get(context.state_key) else {
+            return 0;
+        };
+        if let Some(untried) = (0..context.values.len()).find(|&a| counts.get(a).is_none_or(|n| *n == 0)) {
+            return untried;
+        }
+        let total = counts.iter().sum::<u32>() as f32;
+        let scores: Vec<f32> = context
+            .values
+            .iter()
+            .zip(counts)
+            .map(|(v, n)| v + self.c * (total.ln() / *n as f32).sqrt())
+            .collect();
+        greedy(&scores)
+    }
+}
+
+impl ExplorationStrategy for Ucb {
+    fn select(&self, context: &ExplorationContext, _rng: &mut StdRng) -> usize {
+        self.choice(context)
+    }
+
+    fn probabilities(&self, context: &ExplorationContext) -> Vec<f32> {
+        one_hot(context.values.len(), self.choice(context))
+    }
+
+    fn observe(&mut self, state_key: &str, action: usize) {
+        let counts = self.counts.entry(state_key.to_string()).or_default();
+        if counts.len() <= action {
+            counts.resize(action + 1, 0);
+        }
+        counts[action] += 1;
+    }
+
+    fn rate(&self) -> f32 {
+        self.c
+    }
+
+    fn set_rate(&mut self, rate: f32) {
+        self.c = rate;
+    }
+}
+
+// Jumps longer than this land uniformly on the ladder; their total mass is MAX_JUMP^-(μ-1)
+const MAX_JUMP: usize = 1024;
+
+// Lévy-flight foraging on the ordered ladder: with probability ε, jump L rungs up or down from
+// the greedy depth, with heavy-tailed P(L ≥ l) = l^-(μ-1), reflecting at the ends. Most
+// exploration probes the neighbouring depths; occasional long flights cross the whole ladder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LevyFlight {
+    pub epsilon: f32,
+    pub decay: f32,
+    pub min_epsilon: f32,
+    pub exponent: f32, // μ in (1, 3]: smaller means longer flights
+}
+
+impl Default for LevyFlight {
+    fn default() -> Self {
+        LevyFlight {
+            epsilon: 0.1,
+            decay: 0.995,
+            min_epsilon: 0.01,
+            exponent: 2.0,
+        }
+    }
+}
+
+// Position after walking `offset` rungs from 0 on a ladder of `len`, bouncing off both ends
+fn reflect(offset: i64, len: usize) -> usize {
+    if len <= 1 {
+        return 0;
+    }
+    let period = 2 * (len as i64 - 1);
+    let m = offset.rem_euclid(period);
+    (if m >= len as i64 { period - m } else { m }) as usize
+}
+
+impl LevyFlight {
+    fn tail(&self) -> f32 {
+        (self.exponent - 1.0).max(1e-3)
+    }
+
+    // Distribution of the landing rung of one flight from `start`
+    fn flight(&self, start: usize, len: usize) -> Vec<f32> {
+        let a = self.tail();
+        let mut probs = vec![0.0f32; len];
+        for l in 1..=MAX_JUMP {
+            let p = (l as f32).powf(-a) - ((l + 1) as f32).powf(-a);
+            probs[reflect(start as i64 + l as i64, len)] += p / 2.0;
+            probs[reflect(start as i64 - l as i64, len)] += p / 2.0;
+        }
+        let beyond = ((MAX_JUMP + 1) as f32).powf(-a);
+        for p in probs.iter_mut() {
+            *p += beyond / len as f32;
+        }
+        probs
+    }
+}
+
+impl ExplorationStrategy for LevyFlight {
+    fn select(&self, context: &ExplorationContext, rng: &mut StdRng) -> usize {
+        let len = context.values.len();
+        let start = greedy(context.values);
+        if rng.gen::<f32>() >= self.epsilon {
+            return start;
+        }
+        // Inverse CDF of the discrete Pareto jump length
+        let u = 1.0 - rng.gen::<f32>();
+        let jump = u.powf(-1.0 / self.tail()).floor() as usize;
+        if jump > MAX_JUMP {
+            return rng.gen_range(0..len);
+        }
+        let direction = if rng.gen::<bool>() { 1 } else { -1 };
+        reflect(start as i64 + direction * jump as i64, len)
+    }
+
+    fn probabilities(&self, context: &ExplorationContext) -> Vec<f32> {
+        let start = greedy(context.values);
+        let mut probs: Vec<f32> = self
+            .flight(start, context.values.len())
+            .iter()
+            .map(|p| self.epsilon * p)
+            .collect();
+        probs[start] += 1.0 - self.epsilon;
+        probs
+    }
+
+    fn decay(&mut self) {
+        self.epsilon = (self.epsilon * self.decay).max(self.min_epsilon);
+    }
+
+    fn rate(&self) -> f32 {
+        self.epsilon
+    }
+
+    fn set_rate(&mut self, rate: f32) {
+        self.epsilon = rate;
+    }
+}
+
+// Serializable choice of strategy, e.g. in `QLearningConfig`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ExplorationConfig {
+    EpsilonGreedy(EpsilonGreedy),
+    Boltzmann(Boltzmann),
+    Ucb(Ucb),
+    LevyFlight(LevyFlight),
+}
+
+impl ExplorationConfig {
+    pub fn build(&self) -> Box<dyn ExplorationStrategy> {
+        match self {
+            ExplorationConfig::EpsilonGreedy(s) => Box::new(s.clone()),
+            ExplorationConfig::Boltzmann(s) => Box::new(s.clone()),
+            ExplorationConfig::Ucb(s) => Box::new(Ucb::new(s.c)),
+            ExplorationConfig::LevyFlight(s) => Box::new(s.clone()),
+        }
+    }
+}
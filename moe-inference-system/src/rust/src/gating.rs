@@ -0,0 +1,77 @@
+use crate::nn::log_softmax;
+use crate::policy_engine::ExpertId;
+use crate::rng::{default_rng, standard_normal};
+use candle_core::{DType, Device, Error as CandleError, Tensor};
+
+// Routes an input to the experts that should process it
+pub trait GatingFunction {
+    // Selected experts with renormalized gate weights, highest weight first
+    fn route(&self, input: &Tensor) -> Result<Vec<(ExpertId, f32)>, CandleError>;
+}
+
+// Softmax over router logits x·W, averaged over tokens, keeping the top-k experts
+pub struct SoftmaxTopKGate {
+    experts: Vec<ExpertId>,
+    router: Tensor, // (hidden, num_experts)
+    top_k: usize,
+}
+
+impl SoftmaxTopKGate {
+    pub fn new(experts: Vec<ExpertId>, router: Tensor, top_k: usize) -> Result<Self, CandleError> {
+        let (_, num_experts) = router.dims2()?;
+        if num_experts != experts.len() {
+            return Err(CandleError::Msg(format!(
+                "Router has {} outputs but {} experts were given",
+                num_experts,
+                experts.len()
+            )));
+        }
+        Ok(SoftmaxTopKGate {
+            experts,
+            router: router.to_dtype(DType::F32)?,
+            top_k,
+        })
+    }
+
+    // Router with N(0, 1/hidden) weights, for experts that have no trained router yet.
+    // Drawn from `rng::default_rng`, so reproducible under a global seed.
+    pub fn random(experts: Vec<ExpertId>, hidden: usize, top_k: usize) -> Result<Self, CandleError> {
+        let std = 1.0 / (hidden.max(1) as f32).sqrt();
+        let rng = default_rng();
+        let mut rng = rng.borrow_mut();
+        let weights: Vec<f32> = (0..hidden * experts.len()).map(|_| std * standard_normal(&mut *rng)).collect();
+        let router = Tensor::from_vec(weights, (hidden, experts.len()), &Device::Cpu)?;
+        Self::new(experts, router, top_k)
+    }
+
+    pub fn experts(&self) -> &[ExpertId] {
+        &self.experts
+    }
+
+    // Mean routing probability of every expert over the tokens of `input`
+    // (shape (hidden,) or (..., hidden))
+    pub fn probabilities(&self, input: &Tensor) -> Result<Vec<f32>, CandleError> {
+        if self.experts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (hidden, _) = self.router.dims2()?;
+        let tokens = input.to_dtype(DType::F32)?.reshape((input.elem_count() / hidden, hidden))?;
+        let logits = tokens.matmul(&self.router)?;
+        log_softmax(&logits)?.exp()?.mean(0)?.to_vec1::<f32>()
+    }
+}
+
+impl GatingFunction for SoftmaxTopKGate {
+    fn route(&self, input: &Tensor) -> Result<Vec<(ExpertId, f32)>, CandleError> {
+        let probs = self.probabilities(input)?;
+        let mut ranked: Vec<usize> = (0..probs.len()).collect();
+        ranked.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+        ranked.truncate(self.top_k);
+
+        let total: f32 = ranked.iter().map(|&i| probs[i]).sum();
+        Ok(ranked
+            .into_iter()
+            .map(|i| (self.experts[i].clone(), if total > 0.0 { probs[i] / total } else { 0.0 }))
+            .collect())
+    }
+}
@@ -0,0 +1,263 @@
+use crate::expert_registry::SharedRegistry;
+use crate::policy_engine::{bit_depth_index, BitDepth, BitPrecisionPolicy, ExpertId, HardwareProfile, BIT_DEPTHS, NUM_BIT_DEPTHS};
+use crate::rng::{default_rng, SharedRng};
+use crate::trace_buffer::InferenceTrace;
+use candle_core::Tensor;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GuardrailConfig {
+    pub min_bit_depth: BitDepth,
+    pub expert_min_bit_depth: HashMap<String, BitDepth>, // overrides `min_bit_depth` per expert
+    pub accuracy_floor: Option<f32>, // observed accuracy below this rolls the expert back
+    pub rollback_cooldown: usize,    // selections the rolled-back depth stays pinned
+    pub max_step: usize,             // ladder levels a single decision may move
+    pub exploration_fraction: f32,   // share of requests on which the depth may change at all
+    pub audit_capacity: usize,
+}
+
+impl Default for GuardrailConfig {
+    fn default() -> Self {
+        GuardrailConfig {
+            min_bit_depth: BitDepth::INT4,
+            expert_min_bit_depth: HashMap::new(),
+            accuracy_floor: None,
+            rollback_cooldown: 100,
+            max_step: 1,
+            exploration_fraction: 0.05,
+            audit_capacity: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardReason {
+    Rollback,
+    ExplorationBudget,
+    RateLimit,
+    MinBitDepth,
+}
+
+// One decision the guardrails changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardOverride {
+    pub tick: u64,
+    pub expert_id: ExpertId,
+    pub current: BitDepth,
+    pub proposed: BitDepth,
+    pub applied: BitDepth,
+    pub reasons: Vec<GuardReason>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rollback {
+    depth: BitDepth,
+    remaining: usize,
+}
+
+#[derive(Default)]
+struct GuardState {
+    tick: u64,
+    last_seen: HashMap<String, BitDepth>,
+    last_good: HashMap<String, BitDepth>, // latest depth that met the accuracy floor
+    rollbacks: HashMap<String, Rollback>,
+    audit: VecDeque<GuardOverride>,
+}
+
+// Policy-independent safety checks on proposed bit depths. The current depth of an expert comes
+// from the registry, falling back to the depth of its latest trace.
+pub struct Guardrails {
+    registry: SharedRegistry,
+    config: GuardrailConfig,
+    state: RefCell<GuardState>,
+    rng: SharedRng, // decides which requests fall within the exploration budget
+}
+
+impl Guardrails {
+    pub fn new(registry: SharedRegistry, config: GuardrailConfig) -> Self {
+        Guardrails {
+            registry,
+            config,
+            state: RefCell::new(GuardState::default()),
+            rng: default_rng(),
+        }
+    }
+
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.rng = rng;
+    }
+
+    pub fn config(&self) -> &GuardrailConfig {
+        &self.config
+    }
+
+    fn min_depth(&self, expert_id: &ExpertId) -> BitDepth {
+        self.config
+            .expert_min_bit_depth
+            .get(&expert_id.0)
+            .copied()
+            .unwrap_or(self.config.min_bit_depth)
+    }
+
+    // Bit depth the guardrails allow for one proposal; overrides are appended to the audit log
+    pub fn check(&self, expert_id: &ExpertId, proposed: BitDepth) -> BitDepth {
+        let mut state = self.state.borrow_mut();
+        state.tick += 1;
+        let current = self
+            .registry
+            .borrow()
+            .bit_depth(expert_id)
+            .or_else(|| state.last_seen.get(&expert_id.0).copied())
+            .unwrap_or(proposed);
+
+        let mut reasons = Vec::new();
+        let mut applied = proposed;
+        let rollback = state.rollbacks.get_mut(&expert_id.0).map(|r| {
+            r.remaining = r.remaining.saturating_sub(1);
+            *r
+        });
+        match rollback {
+            Some(rollback) => {
+                if rollback.remaining == 0 {
+                    state.rollbacks.remove(&expert_id.0);
+                }
+                if applied != rollback.depth {
+                    applied = rollback.depth;
+                    reasons.push(GuardReason::Rollback);
+                }
+            }
+            None => {
+                if applied != current && self.rng.borrow_mut().gen::<f32>() >= self.config.exploration_fraction {
+                    applied = current;
+                    reasons.push(GuardReason::ExplorationBudget);
+                }
+            }
+        }
+
+        let from = bit_depth_index(current);
+        let to = bit_depth_index(applied);
+        let limited = to.clamp(from.saturating_sub(self.config.max_step), from + self.config.max_step);
+        if limited != to {
+            applied = BIT_DEPTHS[limited.min(BIT_DEPTHS.len() - 1)];
+            reasons.push(GuardReason::RateLimit);
+        }
+
+        let min = self.min_depth(expert_id);
+        if applied.bits() < min.bits() {
+            applied = min;
+            reasons.push(GuardReason::MinBitDepth);
+        }
+
+        if !reasons.is_empty() {
+            let tick = state.tick;
+            state.audit.push_back(GuardOverride {
+                tick,
+                expert_id: expert_id.clone(),
+                current,
+                proposed,
+                applied,
+                reasons,
+            });
+            while state.audit.len() > self.config.audit_capacity {
+                state.audit.pop_front();
+            }
+        }
+        applied
+    }
+
+    // Track accuracy against the floor; a miss pins the expert to its last good depth
+    pub fn observe(&self, trace: &InferenceTrace) {
+        let mut state = self.state.borrow_mut();
+        let expert = trace.expert_id.0.clone();
+        state.last_seen.insert(expert.clone(), trace.bit_depth);
+        let Some(floor) = self.config.accuracy_floor else {
+            return;
+        };
+        if trace.accuracy >= floor {
+            state.last_good.insert(expert, trace.bit_depth);
+        } else if let Some(&good) = state.last_good.get(&expert) {
+            if good != trace.bit_depth && !state.rollbacks.contains_key(&expert) {
+                state.rollbacks.insert(
+                    expert,
+                    Rollback {
+                        depth: good,
+                        remaining: self.config.rollback_cooldown.max(1),
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn audit_log(&self) -> Vec<GuardOverride> {
+        self.state.borrow().audit.iter().cloned().collect()
+    }
+
+    pub fn clear_audit_log(&self) {
+        self.state.borrow_mut().audit.clear();
+    }
+}
+
+// Wraps any policy so its selections pass through `Guardrails`
+pub struct GuardedPolicy<P: BitPrecisionPolicy> {
+    inner: P,
+    guardrails: Guardrails,
+}
+
+impl<P: BitPrecisionPolicy> GuardedPolicy<P> {
+    pub fn new(inner: P, registry: SharedRegistry, config: GuardrailConfig) -> Self {
+        GuardedPolicy {
+            inner,
+            guardrails: Guardrails::new(registry, config),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    pub fn guardrails(&self) -> &Guardrails {
+        &self.guardrails
+    }
+
+    // Randomness of the guardrails only; seed the inner policy through its own `set_rng`
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.guardrails.set_rng(rng);
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+}
+
+impl<P: BitPrecisionPolicy> BitPrecisionPolicy for GuardedPolicy<P> {
+    fn select_experts(
+        &self,
+        input_tensor: &Tensor,
+        hardware_profile: &HardwareProfile,
+    ) -> Vec<(ExpertId, BitDepth)> {
+        self.inner
+            .select_experts(input_tensor, hardware_profile)
+            .into_iter()
+            .map(|(expert, proposed)| {
+                let applied = self.guardrails.check(&expert, proposed);
+                (expert, applied)
+            })
+            .collect()
+    }
+
+    fn update_policy(&mut self, trace: InferenceTrace) {
+        self.guardrails.observe(&trace);
+        self.inner.update_policy(trace);
+    }
+
+    // The inner policy's distribution; the guardrails' own randomness and state are not modeled
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        self.inner.bit_depth_probabilities(trace)
+    }
+}
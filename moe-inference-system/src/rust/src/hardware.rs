@@ -0,0 +1,274 @@
+use crate::qmatmul::{available_backends, KernelBackend};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HardwareClass {
+    Cpu,
+    Gpu,
+    Tpu,
+    Wasm,
+}
+
+impl HardwareClass {
+    // Free-form hardware types ("gpu", "cuda:0", "cpu-avx512", ...) map onto a class; unknown ones are CPUs
+    pub fn from_type(hardware_type: &str) -> Self {
+        let t = hardware_type.to_ascii_lowercase();
+        if ["gpu", "cuda", "metal", "rocm"].iter().any(|k| t.contains(k)) {
+            HardwareClass::Gpu
+        } else if t.contains("tpu") {
+            HardwareClass::Tpu
+        } else if t.contains("wasm") {
+            HardwareClass::Wasm
+        } else {
+            HardwareClass::Cpu
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HardwareClass::Cpu => "cpu",
+            HardwareClass::Gpu => "gpu",
+            HardwareClass::Tpu => "tpu",
+            HardwareClass::Wasm => "wasm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimdCapabilities {
+    pub avx2: bool,
+    pub avx512: bool,
+    pub neon: bool,
+    pub wasm_simd: bool,
+}
+
+impl SimdCapabilities {
+    // Instruction sets the qmatmul kernels can dispatch to, so the profile and the kernels agree
+    pub fn from_backends(backends: &[KernelBackend]) -> Self {
+        SimdCapabilities {
+            avx2: backends.contains(&KernelBackend::Avx2),
+            avx512: backends.contains(&KernelBackend::Avx512),
+            neon: backends.contains(&KernelBackend::Neon),
+            wasm_simd: backends.contains(&KernelBackend::WasmSimd128),
+        }
+    }
+}
+
+// Per-core data caches plus the shared last level, in KB (0 when unknown)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSizes {
+    pub l1_data_kb: u32,
+    pub l2_kb: u32,
+    pub l3_kb: u32,
+}
+
+// Where inference runs. Per-class state (SLOs, cost fits, reward tables) is keyed by `class()`;
+// the other fields are zero until detected, so profiles sent as just a type still parse.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HardwareProfile {
+    pub hardware_type: String, // e.g., "cpu", "gpu", "tpu"
+    #[serde(default)]
+    pub cores: usize,
+    #[serde(default)]
+    pub simd: SimdCapabilities,
+    #[serde(default)]
+    pub cache: CacheSizes,
+    #[serde(default)]
+    pub memory_bandwidth_gbps: f32, // from `calibrate`
+    #[serde(default)]
+    pub total_memory_mb: f32,
+    #[serde(default)]
+    pub available_memory_mb: f32,
+}
+
+// Length of `HardwareProfile::features`
+pub const HARDWARE_FEATURES: usize = 6;
+
+fn log_scale(value: f32, max: f32) -> f32 {
+    (value.max(0.0).ln_1p() / max.ln_1p()).min(1.0)
+}
+
+impl HardwareProfile {
+    pub fn new(hardware_type: &str) -> Self {
+        HardwareProfile {
+            hardware_type: hardware_type.to_string(),
+            ..HardwareProfile::default()
+        }
+    }
+
+    pub fn class(&self) -> HardwareClass {
+        HardwareClass::from_type(&self.hardware_type)
+    }
+
+    // Machine-independent description in [0, 1]:
+    // [cores | SIMD width | L2 | L3 | memory bandwidth | free memory share]
+    pub fn features(&self) -> [f32; HARDWARE_FEATURES] {
+        let simd = if self.simd.avx512 {
+            1.0
+        } else if self.simd.avx2 || self.simd.neon || self.simd.wasm_simd {
+            0.5
+        } else {
+            0.0
+        };
+        let free = if self.total_memory_mb > 0.0 {
+            (self.available_memory_mb / self.total_memory_mb).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        [
+            log_scale(self.cores as f32, 256.0),
+            simd,
+            log_scale(self.cache.l2_kb as f32, 65536.0),
+            log_scale(self.cache.l3_kb as f32, 1048576.0),
+            self.memory_bandwidth_gbps / (self.memory_bandwidth_gbps + 50.0),
+            free,
+        ]
+    }
+
+    // Profile of the machine this code runs on: SIMD from the kernels' runtime feature detection,
+    // memory and caches from /proc and /sys on Linux; memory bandwidth stays 0 until `calibrate`
+    pub fn detect() -> Self {
+        let mut profile = HardwareProfile::new(if cfg!(target_arch = "wasm32") { "wasm" } else { "cpu" });
+        profile.cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        profile.simd = SimdCapabilities::from_backends(&available_backends());
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") {
+                (profile.total_memory_mb, profile.available_memory_mb) = parse_meminfo(&meminfo);
+            }
+            profile.cache = read_sys_caches();
+        }
+        profile
+    }
+}
+
+// SIMD flags from the "flags" (x86) or "Features" (ARM) lines of another machine's
+// /proc/cpuinfo. AVX-512 needs both F and BW, as the INT8 kernel does.
+pub fn parse_cpu_flags(cpuinfo: &str) -> SimdCapabilities {
+    let mut simd = SimdCapabilities::default();
+    let (mut avx512f, mut avx512bw) = (false, false);
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !matches!(key.trim(), "flags" | "Features") {
+            continue;
+        }
+        for flag in value.split_whitespace() {
+            match flag {
+                "avx2" => simd.avx2 = true,
+                "avx512f" => avx512f = true,
+                "avx512bw" => avx512bw = true,
+                "neon" | "asimd" => simd.neon = true,
+                _ => {}
+            }
+        }
+    }
+    simd.avx512 = avx512f && avx512bw;
+    simd
+}
+
+// (MemTotal, MemAvailable) in MB
+pub fn parse_meminfo(meminfo: &str) -> (f32, f32) {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|rest| rest.split_whitespace().next()?.parse::<f32>().ok())
+            .map_or(0.0, |kb| kb / 1024.0)
+    };
+    (field("MemTotal"), field("MemAvailable"))
+}
+
+// sysfs cache sizes such as "48K", "2048K" or "32M", in KB
+pub fn parse_cache_size(size: &str) -> Option<u32> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1),
+        'M' | 'm' => (&size[..size.len() - 1], 1024),
+        'G' | 'g' => (&size[..size.len() - 1], 1024 * 1024),
+        _ => (size, 1),
+    };
+    digits.parse::<u32>().ok().map(|n| n * multiplier)
+}
+
+#[cfg(target_os = "linux")]
+fn read_sys_caches() -> CacheSizes {
+    let mut cache = CacheSizes::default();
+    for index in 0.. {
+        let dir = format!("/sys/devices/system/cpu/cpu0/cache/index{}", index);
+        let read = |name: &str| std::fs::read_to_string(format!("{}/{}", dir, name)).ok();
+        let Some(level) = read("level") else {
+            break;
+        };
+        let kind = read("type").unwrap_or_default();
+        let Some(size) = read("size").as_deref().and_then(parse_cache_size) else {
+            continue;
+        };
+        match (level.trim(), kind.trim()) {
+            ("1", "Data") => cache.l1_data_kb = size,
+            ("2", _) => cache.l2_kb = size,
+            ("3", _) => cache.l3_kb = size,
+            _ => {}
+        }
+    }
+    cache
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CalibrationConfig {
+    pub buffer_mb: usize, // well above the last-level cache so the copy streams from DRAM
+    pub repetitions: usize,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            buffer_mb: 64,
+            repetitions: 5,
+        }
+    }
+}
+
+// Measure memory bandwidth with a timed buffer copy (best of `repetitions`, read plus write
+// traffic) and store it in the profile. Returns None where no monotonic clock is available.
+pub fn calibrate(profile: &mut HardwareProfile, config: &CalibrationConfig) -> Option<f32> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let len = (config.buffer_mb.max(1) << 20) / std::mem::size_of::<u64>();
+        let src: Vec<u64> = (0..len as u64).collect();
+        let mut dst = vec![0u64; len];
+        let mut best = f64::INFINITY;
+        for _ in 0..config.repetitions.max(1) {
+            let start = std::time::Instant::now();
+            dst.copy_from_slice(std::hint::black_box(&src));
+            std::hint::black_box(&mut dst);
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        let bytes = 2.0 * (len * std::mem::size_of::<u64>()) as f64;
+        let gbps = (bytes / best.max(1e-9) / 1e9) as f32;
+        profile.memory_bandwidth_gbps = gbps;
+        Some(gbps)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (profile, config);
+        None
+    }
+}
+
+// Detected profile of the host as JSON, optionally with the bandwidth benchmark run
+#[wasm_bindgen]
+pub fn detect_hardware_profile(calibrate_bandwidth: bool) -> Result<String, JsValue> {
+    let mut profile = HardwareProfile::detect();
+    if calibrate_bandwidth {
+        calibrate(&mut profile, &CalibrationConfig::default());
+    }
+    serde_json::to_string(&profile).map_err(|e| JsValue::from_str(&e.to_string()))
+}
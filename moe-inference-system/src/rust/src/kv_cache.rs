@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// Storage format of quantized KV blocks (backs the `PetriPlace::QuantizeKV` stage)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvPrecision {
+    INT8,
+    INT4, // two codes packed per byte
+    FP8,  // E4M3, max magnitude 448
+}
+
+// Which axis shares a scale inside a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvScaleGranularity {
+    PerToken,   // one scale per token over head_dim
+    PerChannel, // one scale per channel over the block's tokens (suits keys with outlier channels)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvCacheConfig {
+    pub num_layers: usize,
+    pub num_heads: usize,
+    pub head_dim: usize,
+    pub precision: KvPrecision,
+    pub key_granularity: KvScaleGranularity,
+    pub value_granularity: KvScaleGranularity,
+    pub block_tokens: usize, // tokens kept in f32 until a full block can be quantized
+    pub max_tokens: Option<usize>, // per layer; oldest blocks are evicted beyond this
+}
+
+impl KvCacheConfig {
+    pub fn new(num_layers: usize, num_heads: usize, head_dim: usize, precision: KvPrecision) -> Self {
+        KvCacheConfig {
+            num_layers,
+            num_heads,
+            head_dim,
+            precision,
+            key_granularity: KvScaleGranularity::PerChannel,
+            value_granularity: KvScaleGranularity::PerToken,
+            block_tokens: 32,
+            max_tokens: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvictionEvent {
+    pub layer: usize,
+    pub tokens: usize,       // number of tokens dropped from every head of the layer
+    pub first_position: usize, // absolute position of the first dropped token
+}
+
+// Called whenever tokens leave the cache, e.g. to spill them or update attention-sink bookkeeping
+pub trait EvictionHook {
+    fn on_evict(&mut self, event: &EvictionEvent);
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KvMemoryStats {
+    pub tokens: usize,
+    pub quantized_bytes: usize,
+    pub full_precision_bytes: usize, // same tokens stored as f32
+    pub saved_bytes: usize,
+    pub compression_ratio: f32,
+}
+
+// One quantized block of `tokens` x `head_dim` values
+struct KvBlock {
+    codes: Vec<u8>,
+    scales: Vec<f32>,
+    tokens: usize,
+    granularity: KvScaleGranularity,
+}
+
+impl KvBlock {
+    fn bytes(&self) -> usize {
+        self.codes.len() + self.scales.len() * std::mem::size_of::<f32>()
+    }
+}
+
+// Keys or values of one head: quantized blocks plus an f32 tail that is not yet a full block
+#[derive(Default)]
+struct KvStream {
+    blocks: VecDeque<KvBlock>,
+    residual: Vec<f32>,
+}
+
+fn code_max(precision: KvPrecision) -> f32 {
+    match precision {
+        KvPrecision::INT8 => 127.0,
+        KvPrecision::INT4 => 7.0,
+        KvPrecision::FP8 => 448.0,
+    }
+}
+
+fn f32_to_e4m3(x: f32) -> u8 {
+    if x.is_nan() {
+        return 0x7f;
+    }
+    let sign = if x.is_sign_negative() { 0x80 } else { 0x00 };
+    let a = x.abs().min(448.0);
+    let min_normal = 2f32.powi(-6);
+    if a < min_normal {
+        // Subnormal: a = m * 2^-9; m == 8 rolls over into the smallest normal, which has the same encoding
+        return sign | (a / 2f32.powi(-9)).round() as u8;
+    }
+    let mut exponent = a.log2().floor() as i32;
+    let mut mantissa = ((a / 2f32.powi(exponent) - 1.0) * 8.0).round() as u8;
+    if mantissa == 8 {
+        exponent += 1;
+        mantissa = 0;
+    }
+    let biased = (exponent + 7) as u8;
+    if biased > 15 || (biased == 15 && mantissa == 7) {
+        return sign | 0x7e; // 448, the largest finite value
+    }
+    sign | (biased << 3) | mantissa
+}
+
+fn e4m3_to_f32(code: u8) -> f32 {
+    if code & 0x7f == 0x7f {
+        return f32::NAN;
+    }
+    let sign = if code & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((code >> 3) & 0x0f) as i32;
+    let mantissa = (code & 0x07) as f32;
+    let magnitude = if exponent == 0 {
+        mantissa / 8.0 * 2f32.powi(-6)
+    } else {
+        (1.0 + mantissa / 8.0) * 2f32.powi(exponent - 7)
+    };
+    sign * magnitude
+}
+
+fn encode(value: f32, precision: KvPrecision) -> u8 {
+    match precision {
+        KvPrecision::INT8 => (value.round().clamp(-127.0, 127.0) as i8) as u8,
+        KvPrecision::INT4 => (value.round().clamp(-7.0, 7.0) as i8 + 8) as u8,
+        KvPrecision::FP8 => f32_to_e4m3(value),
+    }
+}
+
+fn decode(code: u8, precision: KvPrecision) -> f32 {
+    match precision {
+        KvPrecision::INT8 => code as i8 as f32,
+        KvPrecision::INT4 => code as f32 - 8.0,
+        KvPrecision::FP8 => e4m3_to_f32(code),
+    }
+}
+
+fn quantize_kv_block(
+    values: &[f32],
+    head_dim: usize,
+    precision: KvPrecision,
+    granularity: KvScaleGranularity,
+) -> KvBlock {
+    let tokens = values.len() / head_dim;
+    let max = code_max(precision);
+    let scale_for = |max_abs: f32| if max_abs > 0.0 { max_abs / max } else { 1.0 };
+
+    let scales: Vec<f32> = match granularity {
+        KvScaleGranularity::PerToken => values
+            .chunks(head_dim)
+            .map(|row| scale_for(row.iter().fold(0.0f32, |acc, v| acc.max(v.abs()))))
+            .collect(),
+        KvScaleGranularity::PerChannel => (0..head_dim)
+            .map(|c| scale_for((0..tokens).fold(0.0f32, |acc, t| acc.max(values[t * head_dim + c].abs()))))
+            .collect(),
+    };
+    let scale_at = |i: usize| match granularity {
+        KvScaleGranularity::PerToken => scales[i / head_dim],
+        KvScaleGranularity::PerChannel => scales[i % head_dim],
+    };
+
+    let raw: Vec<u8> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| encode(v / scale_at(i), precision))
+        .collect();
+    let codes = match precision {
+        KvPrecision::INT4 => raw
+            .chunks(2)
+            .map(|pair| pair[0] | (pair.get(1).copied().unwrap_or(8) << 4))
+            .collect(),
+        _ => raw,
+    };
+
+    KvBlock { codes, scales, tokens, granularity }
+}
+
+fn dequantize_kv_block(block: &KvBlock, head_dim: usize, precision: KvPrecision, out: &mut Vec<f32>) {
+    let len = block.tokens * head_dim;
+    for i in 0..len {
+        let code = match precision {
+            KvPrecision::INT4 => (block.codes[i / 2] >> ((i % 2) * 4)) & 0x0f,
+            _ => block.codes[i],
+        };
+        let scale = match block.granularity {
+            KvScaleGranularity::PerToken => block.scales[i / head_dim],
+            KvScaleGranularity::PerChannel => block.scales[i % head_dim],
+        };
+        out.push(decode(code, precision) * scale);
+    }
+}
+
+// Quantized KV cache with per-layer, per-head key/value streams
+pub struct QuantizedKvCache {
+    config: KvCacheConfig,
+    keys: Vec<Vec<KvStream>>, // [layer][head]
+    values: Vec<Vec<KvStream>>,
+    evicted: Vec<usize>, // tokens evicted so far per layer
+    hooks: Vec<Box<dyn EvictionHook>>,
+}
+
+impl QuantizedKvCache {
+    pub fn new(config: KvCacheConfig) -> Result<Self, String> {
+        if config.num_layers == 0 || config.num_heads == 0 || config.head_dim == 0 || config.block_tokens == 0 {
+            return Err(format!(
+                "KV cache dimensions must be non-zero, got {} layers, {} heads, head_dim {}, block_tokens {}",
+                config.num_layers, config.num_heads, config.head_dim, config.block_tokens
+            ));
+        }
+        let streams = || {
+            (0..config.num_layers)
+                .map(|_| (0..config.num_heads).map(|_| KvStream::default()).collect())
+                .collect()
+        };
+        Ok(QuantizedKvCache {
+            keys: streams(),
+            values: streams(),
+            evicted: vec![0; config.num_layers],
+            hooks: Vec::new(),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &KvCacheConfig {
+        &self.config
+    }
+
+    pub fn add_eviction_hook(&mut self, hook: Box<dyn EvictionHook>) {
+        self.hooks.push(hook);
+    }
+
+    fn check_layer(&self, layer: usize) -> Result<(), String> {
+        if layer >= self.config.num_layers {
+            return Err(format!("Layer {} out of range ({} layers)", layer, self.config.num_layers));
+        }
+        Ok(())
+    }
+
+    // Tokens held for a layer that is known to exist
+    fn tokens(&self, layer: usize) -> usize {
+        let stream = &self.keys[layer][0];
+        stream.blocks.iter().map(|b| b.tokens).sum::<usize>() + stream.residual.len() / self.config.head_dim
+    }
+
+    // Tokens currently held for a layer (identical across heads)
+    pub fn len(&self, layer: usize) -> Result<usize, String> {
+        self.check_layer(layer)?;
+        Ok(self.tokens(layer))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..self.config.num_layers).all(|layer| self.tokens(layer) == 0)
+    }
+
+    // Append tokens for one layer. `keys` and `values` are laid out [token][head][head_dim].
+    pub fn append(&mut self, layer: usize, keys: &[f32], values: &[f32]) -> Result<(), String> {
+        let token_width = self.config.num_heads * self.config.head_dim;
+        self.check_layer(layer)?;
+        if keys.len() != values.len() || !keys.len().is_multiple_of(token_width) {
+            return Err(format!(
+                "Expected keys and values of equal length divisible by {}, got {} and {}",
+                token_width,
+                keys.len(),
+                values.len()
+            ));
+        }
+
+        let head_dim = self.config.head_dim;
+        let block_len = self.config.block_tokens * head_dim;
+        for (token_keys, token_values) in keys.chunks(token_width).zip(values.chunks(token_width)) {
+            for head in 0..self.config.num_heads {
+                let range = head * head_dim..(head + 1) * head_dim;
+                for (stream, data, granularity) in [
+                    (&mut self.keys[layer][head], &token_keys[range.clone()], self.config.key_granularity),
+                    (&mut self.values[layer][head], &token_values[range.clone()], self.config.value_granularity),
+                ] {
+                    stream.residual.extend_from_slice(data);
+                    if stream.residual.len() == block_len {
+                        let block = quantize_kv_block(&stream.residual, head_dim, self.config.precision, granularity);
+                        stream.blocks.push_back(block);
+                        stream.residual.clear();
+                    }
+                }
+            }
+        }
+
+        if let Some(max_tokens) = self.config.max_tokens {
+            while self.tokens(layer) > max_tokens && !self.keys[layer][0].blocks.is_empty() {
+                self.evict_oldest_block(layer)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Dequantized keys and values of one head, each laid out [token][head_dim]
+    pub fn read(&self, layer: usize, head: usize) -> Result<(Vec<f32>, Vec<f32>), String> {
+        self.check_layer(layer)?;
+        if head >= self.config.num_heads {
+            return Err(format!("Head {} out of range ({} heads)", head, self.config.num_heads));
+        }
+        let read_stream = |stream: &KvStream| {
+            let mut out = Vec::with_capacity(self.tokens(layer) * self.config.head_dim);
+            for block in &stream.blocks {
+                dequantize_kv_block(block, self.config.head_dim, self.config.precision, &mut out);
+            }
+            out.extend_from_slice(&stream.residual);
+            out
+        };
+        Ok((read_stream(&self.keys[layer][head]), read_stream(&self.values[layer][head])))
+    }
+
+    // Drop the oldest quantized block of a layer from every head and notify hooks
+    pub fn evict_oldest_block(&mut self, layer: usize) -> Result<usize, String> {
+        self.check_layer(layer)?;
+        let mut dropped = 0;
+        for head in 0..self.config.num_heads {
+            if let Some(block) = self.keys[layer][head].blocks.pop_front() {
+                dropped = block.tokens;
+            }
+            self.values[layer][head].blocks.pop_front();
+        }
+        if dropped > 0 {
+            let event = EvictionEvent {
+                layer,
+                tokens: dropped,
+                first_position: self.evicted[layer],
+            };
+            self.evicted[layer] += dropped;
+            for hook in self.hooks.iter_mut() {
+                hook.on_evict(&event);
+            }
+        }
+        Ok(dropped)
+    }
+
+    pub fn clear(&mut self) {
+        for layer in 0..self.config.num_layers {
+            for head in 0..self.config.num_heads {
+                self.keys[layer][head] = KvStream::default();
+                self.values[layer][head] = KvStream::default();
+            }
+            self.evicted[layer] = 0;
+        }
+    }
+
+    pub fn memory_stats(&self) -> KvMemoryStats {
+        let mut tokens = 0;
+        let mut quantized_bytes = 0;
+        for layer in 0..self.config.num_layers {
+            tokens += self.tokens(layer);
+            for stream in self.keys[layer].iter().chain(&self.values[layer]) {
+                quantized_bytes += stream.blocks.iter().map(KvBlock::bytes).sum::<usize>();
+                quantized_bytes += stream.residual.len() * std::mem::size_of::<f32>();
+            }
+        }
+        let full_precision_bytes =
+            tokens * 2 * self.config.num_heads * self.config.head_dim * std::mem::size_of::<f32>();
+        KvMemoryStats {
+            tokens,
+            quantized_bytes,
+            full_precision_bytes,
+            saved_bytes: full_precision_bytes.saturating_sub(quantized_bytes),
+            compression_ratio: if quantized_bytes == 0 {
+                1.0
+            } else {
+                full_precision_bytes as f32 / quantized_bytes as f32
+            },
+        }
+    }
+}
@@ -1,4 +1,30 @@
-mod quantization;
+pub mod bandit;
+pub mod checkpoint;
+pub mod cost_model;
+pub mod drift;
+pub mod dqn;
+pub mod expert_registry;
+pub mod exploration;
+pub mod gating;
+pub mod guardrails;
+pub mod hardware;
+pub mod kv_cache;
+pub mod nn;
+pub mod offline;
+pub mod ope;
+pub mod petri;
+pub mod policy_engine;
+pub mod qmatmul;
+pub mod quantization;
+pub mod reward;
+pub mod rl_optimize_bit_depth;
+pub mod rng;
+pub mod sensitivity;
+pub mod simulator;
+pub mod trace_buffer;
+
+#[cfg(test)]
+mod tests;
 
 use wasm_bindgen::prelude::*;
 
@@ -0,0 +1,186 @@
+use candle_core::{DType, Device, Error as CandleError, Tensor, Var};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::HashMap;
+
+// Dense layer y = x W + b with trainable parameters
+pub struct Linear {
+    pub weight: Var, // (in, out)
+    pub bias: Var,   // (out,)
+}
+
+impl Linear {
+    // Uniform(-1/sqrt(in), 1/sqrt(in)) initialization, as in PyTorch
+    pub fn new(inputs: usize, outputs: usize, device: &Device, rng: &mut StdRng) -> Result<Self, CandleError> {
+        let bound = 1.0 / (inputs as f32).sqrt();
+        let weight: Vec<f32> = (0..inputs * outputs).map(|_| rng.gen_range(-bound..bound)).collect();
+        Ok(Linear {
+            weight: Var::from_tensor(&Tensor::from_vec(weight, (inputs, outputs), device)?)?,
+            bias: Var::zeros(outputs, DType::F32, device)?,
+        })
+    }
+
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor, CandleError> {
+        x.matmul(self.weight.as_tensor())?.broadcast_add(self.bias.as_tensor())
+    }
+}
+
+// Multi-layer perceptron with tanh hidden activations and a linear output
+pub struct Mlp {
+    layers: Vec<Linear>,
+}
+
+impl Mlp {
+    pub fn new(sizes: &[usize], device: &Device, rng: &mut StdRng) -> Result<Self, CandleError> {
+        let layers = sizes
+            .windows(2)
+            .map(|w| Linear::new(w[0], w[1], device, rng))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Mlp { layers })
+    }
+
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor, CandleError> {
+        let mut h = x.clone();
+        for (i, layer) in self.layers.iter().enumerate() {
+            h = layer.forward(&h)?;
+            if i + 1 < self.layers.len() {
+                h = h.tanh()?;
+            }
+        }
+        Ok(h)
+    }
+
+    pub fn vars(&self) -> Vec<Var> {
+        self.layers
+            .iter()
+            .flat_map(|l| [l.weight.clone(), l.bias.clone()])
+            .collect()
+    }
+
+    // Parameters named "{prefix}.{layer}.weight" / "{prefix}.{layer}.bias", for checkpoints
+    pub fn named_tensors(&self, prefix: &str) -> Vec<(String, Tensor)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, l)| {
+                [
+                    (format!("{}.{}.weight", prefix, i), l.weight.as_tensor().clone()),
+                    (format!("{}.{}.bias", prefix, i), l.bias.as_tensor().clone()),
+                ]
+            })
+            .collect()
+    }
+
+    // Inverse of `named_tensors`; every parameter must be present with a matching shape
+    pub fn load_named_tensors(&self, prefix: &str, tensors: &HashMap<String, Tensor>) -> Result<(), CandleError> {
+        for (i, layer) in self.layers.iter().enumerate() {
+            for (name, var) in [("weight", &layer.weight), ("bias", &layer.bias)] {
+                let key = format!("{}.{}.{}", prefix, i, name);
+                let tensor = tensors
+                    .get(&key)
+                    .ok_or_else(|| CandleError::Msg(format!("Missing tensor {}", key)))?;
+                if tensor.dims() != var.dims() {
+                    return Err(CandleError::Msg(format!(
+                        "Tensor {} has shape {:?}, expected {:?}",
+                        key,
+                        tensor.dims(),
+                        var.dims()
+                    )));
+                }
+                var.set(&tensor.to_dtype(DType::F32)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Overwrite this network's parameters with another's (same architecture)
+    pub fn copy_from(&self, other: &Mlp) -> Result<(), CandleError> {
+        for (dst, src) in self.vars().iter().zip(other.vars()) {
+            dst.set(src.as_tensor())?;
+        }
+        Ok(())
+    }
+}
+
+// Numerically stable log-softmax over the last dimension of a (batch, n) tensor
+pub fn log_softmax(logits: &Tensor) -> Result<Tensor, CandleError> {
+    let max = logits.max_keepdim(1)?.detach()?;
+    let shifted = logits.broadcast_sub(&max)?;
+    let log_sum = shifted.exp()?.sum_keepdim(1)?.log()?;
+    shifted.broadcast_sub(&log_sum)
+}
+
+// Adam optimizer over a fixed set of variables
+pub struct Adam {
+    vars: Vec<Var>,
+    first_moment: Vec<Tensor>,
+    second_moment: Vec<Tensor>,
+    learning_rate: f64,
+    beta1: f64,
+    beta2: f64,
+    epsilon: f64,
+    step: i32,
+}
+
+impl Adam {
+    pub fn new(vars: Vec<Var>, learning_rate: f64) -> Result<Self, CandleError> {
+        let first_moment = vars.iter().map(|v| v.zeros_like()).collect::<Result<Vec<_>, _>>()?;
+        let second_moment = vars.iter().map(|v| v.zeros_like()).collect::<Result<Vec<_>, _>>()?;
+        Ok(Adam {
+            vars,
+            first_moment,
+            second_moment,
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            step: 0,
+        })
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    // One gradient step on `loss`; gradients above `max_grad_norm` (global L2) are rescaled
+    pub fn backward_step(&mut self, loss: &Tensor, max_grad_norm: Option<f64>) -> Result<(), CandleError> {
+        let grads = loss.backward()?;
+        let mut gradients = Vec::with_capacity(self.vars.len());
+        for var in &self.vars {
+            // Gradients carry their backward graph; detach them so the moments don't chain
+            // every step's graph onto the next one
+            gradients.push(match grads.get(var.as_tensor()) {
+                Some(g) => g.detach()?,
+                None => var.zeros_like()?,
+            });
+        }
+
+        let mut clip = 1.0;
+        if let Some(max_norm) = max_grad_norm {
+            let mut total = 0.0f64;
+            for g in &gradients {
+                total += g.sqr()?.sum_all()?.to_scalar::<f32>()? as f64;
+            }
+            let norm = total.sqrt();
+            if norm > max_norm {
+                clip = max_norm / norm;
+            }
+        }
+
+        self.step += 1;
+        let bias1 = 1.0 - self.beta1.powi(self.step);
+        let bias2 = 1.0 - self.beta2.powi(self.step);
+        for (i, var) in self.vars.iter().enumerate() {
+            let g = gradients[i].affine(clip, 0.0)?;
+            let m = (self.first_moment[i].affine(self.beta1, 0.0)? + g.affine(1.0 - self.beta1, 0.0)?)?;
+            let v = (self.second_moment[i].affine(self.beta2, 0.0)? + g.sqr()?.affine(1.0 - self.beta2, 0.0)?)?;
+            let m_hat = m.affine(1.0 / bias1, 0.0)?;
+            let v_hat = v.affine(1.0 / bias2, 0.0)?;
+            let update = (m_hat / v_hat.sqrt()?.affine(1.0, self.epsilon)?)?.affine(self.learning_rate, 0.0)?;
+            var.set(&var.as_tensor().sub(&update)?)?;
+            self.first_moment[i] = m;
+            self.second_moment[i] = v;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use crate::policy_engine::{
+    q_table_checkpoint, q_transition, BitDepth, QLearningConfig, QLearningPolicy, NUM_BIT_DEPTHS,
+};
+use crate::reward::{RewardConfig, RewardModel, WeightedSumReward};
+use crate::trace_buffer::InferenceTrace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfflineAlgorithm {
+    FittedQ,      // fitted Q-iteration: regress Q(s, a) onto r + γ max Q(s', ·) of the previous iterate
+    Conservative, // CQL: TD updates plus a penalty pushing down Q of actions absent from the log
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OfflineConfig {
+    pub algorithm: OfflineAlgorithm,
+    pub iterations: usize,
+    pub discount_factor: f32,
+    pub learning_rate: f32, // CQL step size
+    pub cql_alpha: f32,     // CQL penalty weight
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        OfflineConfig {
+            algorithm: OfflineAlgorithm::Conservative,
+            iterations: 50,
+            discount_factor: 0.9,
+            learning_rate: 0.1,
+            cql_alpha: 1.0,
+        }
+    }
+}
+
+// Metrics after one pass over the dataset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainingPoint {
+    pub iteration: usize,
+    pub bellman_error: f32,    // mean squared TD error before the pass
+    pub mean_q: f32,           // mean Q of the logged actions
+    pub mean_max_q: f32,       // mean greedy value of the logged states
+    pub conservative_gap: f32, // mean logsumexp Q(s, ·) - Q(s, a_logged)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OfflineResult {
+    pub q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+    pub curve: Vec<TrainingPoint>,
+}
+
+impl OfflineResult {
+    // Warm start a live policy; it keeps learning online from here
+    pub fn apply_to(&self, policy: &mut QLearningPolicy) {
+        policy.set_q_table(self.q_table.clone());
+    }
+}
+
+struct Transition {
+    state: String,
+    action: usize,
+    reward: f32,
+    next_state: String,
+}
+
+fn log_sum_exp(q: &[f32; NUM_BIT_DEPTHS]) -> f32 {
+    let max = q.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    max + q.iter().map(|v| (v - max).exp()).sum::<f32>().ln()
+}
+
+fn max_q(q: &[f32; NUM_BIT_DEPTHS]) -> f32 {
+    q.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+}
+
+// Trains a `QLearningPolicy` table from logged traces, without any live exploration
+pub struct OfflineTrainer {
+    config: OfflineConfig,
+}
+
+impl OfflineTrainer {
+    pub fn new(config: OfflineConfig) -> Self {
+        OfflineTrainer { config }
+    }
+
+    // The reward model observes the whole dataset first, so normalization reflects the logs
+    pub fn train(&self, traces: &[InferenceTrace], reward: &mut dyn RewardModel) -> OfflineResult {
+        for trace in traces {
+            reward.observe(trace);
+        }
+        // Logs are in serving order, so each expert's previous trace gives the depth it moved from
+        let mut depths: HashMap<String, BitDepth> = HashMap::new();
+        let transitions: Vec<Transition> = traces
+            .iter()
+            .map(|t| {
+                let (state, action, next_state) = q_transition(depths.insert(t.expert_id.0.clone(), t.bit_depth), t);
+                Transition {
+                    state,
+                    action,
+                    reward: reward.reward(t),
+                    next_state,
+                }
+            })
+            .filter(|t| t.reward.is_finite())
+            .collect();
+
+        let mut q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]> = HashMap::new();
+        let mut curve = Vec::with_capacity(self.config.iterations);
+        for iteration in 0..self.config.iterations {
+            let bellman_error = self.bellman_error(&q_table, &transitions);
+            q_table = match self.config.algorithm {
+                OfflineAlgorithm::FittedQ => self.fitted_q_step(&q_table, &transitions),
+                OfflineAlgorithm::Conservative => self.cql_step(q_table, &transitions),
+            };
+            curve.push(Self::evaluate(iteration, bellman_error, &q_table, &transitions));
+        }
+        OfflineResult { q_table, curve }
+    }
+
+    fn target(&self, q_table: &HashMap<String, [f32; NUM_BIT_DEPTHS]>, t: &Transition) -> f32 {
+        let next = q_table.get(&t.next_state).copied().unwrap_or([0.0; NUM_BIT_DEPTHS]);
+        t.reward + self.config.discount_factor * max_q(&next)
+    }
+
+    fn bellman_error(&self, q_table: &HashMap<String, [f32; NUM_BIT_DEPTHS]>, transitions: &[Transition]) -> f32 {
+        let total: f32 = transitions
+            .iter()
+            .map(|t| {
+                let q = q_table.get(&t.state).map_or(0.0, |q| q[t.action]);
+                (self.target(q_table, t) - q).powi(2)
+            })
+            .sum();
+        total / transitions.len().max(1) as f32
+    }
+
+    // Exact tabular regression: each logged (s, a) takes the mean target of its samples
+    fn fitted_q_step(
+        &self,
+        q_table: &HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+        transitions: &[Transition],
+    ) -> HashMap<String, [f32; NUM_BIT_DEPTHS]> {
+        let mut sums: HashMap<&str, ([f32; NUM_BIT_DEPTHS], [u32; NUM_BIT_DEPTHS])> = HashMap::new();
+        for t in transitions {
+            let entry = sums.entry(&t.state).or_insert(([0.0; NUM_BIT_DEPTHS], [0; NUM_BIT_DEPTHS]));
+            entry.0[t.action] += self.target(q_table, t);
+            entry.1[t.action] += 1;
+        }
+        let mut next = q_table.clone();
+        for (state, (sum, count)) in sums {
+            let q = next.entry(state.to_string()).or_insert([0.0; NUM_BIT_DEPTHS]);
+            for a in 0..NUM_BIT_DEPTHS {
+                if count[a] > 0 {
+                    q[a] = sum[a] / count[a] as f32;
+                }
+            }
+        }
+        next
+    }
+
+    // One pass of TD updates with the CQL regularizer α·(logsumexp Q(s, ·) - Q(s, a_logged))
+    fn cql_step(
+        &self,
+        mut q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+        transitions: &[Transition],
+    ) -> HashMap<String, [f32; NUM_BIT_DEPTHS]> {
+        let lr = self.config.learning_rate;
+        let alpha = self.config.cql_alpha;
+        for t in transitions {
+            let target = self.target(&q_table, t);
+            let q = q_table.entry(t.state.clone()).or_insert([0.0; NUM_BIT_DEPTHS]);
+            let td = target - q[t.action];
+            let lse = log_sum_exp(q);
+            for v in q.iter_mut() {
+                *v -= lr * alpha * (*v - lse).exp();
+            }
+            q[t.action] += lr * (td + alpha);
+        }
+        q_table
+    }
+
+    fn evaluate(
+        iteration: usize,
+        bellman_error: f32,
+        q_table: &HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+        transitions: &[Transition],
+    ) -> TrainingPoint {
+        let n = transitions.len().max(1) as f32;
+        let (mut mean_q, mut mean_max_q, mut gap) = (0.0, 0.0, 0.0);
+        for t in transitions {
+            let q = q_table.get(&t.state).copied().unwrap_or([0.0; NUM_BIT_DEPTHS]);
+            mean_q += q[t.action] / n;
+            mean_max_q += max_q(&q) / n;
+            gap += (log_sum_exp(&q) - q[t.action]) / n;
+        }
+        TrainingPoint {
+            iteration,
+            bellman_error,
+            mean_q,
+            mean_max_q,
+            conservative_gap: gap,
+        }
+    }
+}
+
+// Traces as a JSON array or as JSON lines, one `InferenceTrace` per line
+pub fn parse_trace_log(log: &str) -> Result<Vec<InferenceTrace>, String> {
+    if log.trim_start().starts_with('[') {
+        return serde_json::from_str(log).map_err(|e| e.to_string());
+    }
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+        .collect()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct OfflineJob {
+    training: OfflineConfig,
+    reward: RewardConfig,
+    policy: QLearningConfig,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OfflineJobOutput {
+    curve: Vec<TrainingPoint>,
+    checkpoint: serde_json::Value, // `QLearningPolicy` checkpoint
+}
+
+// Train on a trace log and return the training curve plus a Q-learning checkpoint (JSON)
+#[wasm_bindgen]
+pub fn train_offline_policy(traces: &str, config: &str) -> Result<String, JsValue> {
+    let traces = parse_trace_log(traces).map_err(|e| JsValue::from_str(&e))?;
+    let job: OfflineJob = serde_json::from_str(config).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mut reward = WeightedSumReward::new(job.reward);
+    let result = OfflineTrainer::new(job.training).train(&traces, &mut reward);
+
+    let checkpoint = q_table_checkpoint(&job.policy, &result.q_table, job.policy.exploration_rate)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let output = OfflineJobOutput {
+        curve: result.curve,
+        checkpoint: serde_json::from_slice(&checkpoint).map_err(|e| JsValue::from_str(&e.to_string()))?,
+    };
+    serde_json::to_string(&output).map_err(|e| JsValue::from_str(&e.to_string()))
+}
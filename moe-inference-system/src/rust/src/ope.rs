@@ -0,0 +1,306 @@
+use crate::policy_engine::{bit_depth_index, BitPrecisionPolicy, BIT_DEPTHS, NUM_BIT_DEPTHS};
+use crate::reward::RewardModel;
+use crate::trace_buffer::InferenceTrace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OpeConfig {
+    pub confidence: f32,         // two-sided confidence level of the intervals
+    pub max_weight: Option<f32>, // clip importance weights to bound variance (adds bias)
+}
+
+impl Default for OpeConfig {
+    fn default() -> Self {
+        OpeConfig {
+            confidence: 0.95,
+            max_weight: None,
+        }
+    }
+}
+
+// Point estimate with a normal-approximation confidence interval
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Estimate {
+    pub value: f32,
+    pub std_error: f32,
+    pub lower: f32,
+    pub upper: f32,
+}
+
+// Estimated mean reward per trace of a policy on logged traffic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpeReport {
+    pub ips: Estimate,
+    pub snips: Estimate,
+    pub doubly_robust: Estimate,
+    pub logged_value: f32,          // mean reward the logging policy actually obtained
+    pub samples: usize,             // traces with a usable propensity
+    pub skipped: usize,             // traces without one
+    pub effective_sample_size: f32, // (Σw)² / Σw²
+}
+
+// Candidate minus baseline, with intervals from paired per-trace differences
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyComparison {
+    pub baseline: OpeReport,
+    pub candidate: OpeReport,
+    pub ips_difference: Estimate,
+    pub snips_difference: Estimate,
+    pub doubly_robust_difference: Estimate,
+}
+
+// Logged sample with a valid propensity
+struct Sample<'a> {
+    trace: &'a InferenceTrace,
+    action: usize,
+    propensity: f32,
+    reward: f32,
+}
+
+// Direct-method reward model for DR: mean logged reward per (expert, hardware class, bit depth),
+// falling back to the mean per bit depth and then the overall mean
+struct RewardTable {
+    by_context: HashMap<(String, &'static str, usize), (f32, u32)>,
+    by_action: [(f32, u32); NUM_BIT_DEPTHS],
+    overall: f32,
+}
+
+impl RewardTable {
+    fn fit(samples: &[Sample]) -> Self {
+        let mut by_context: HashMap<(String, &'static str, usize), (f32, u32)> = HashMap::new();
+        let mut by_action = [(0.0f32, 0u32); NUM_BIT_DEPTHS];
+        for s in samples {
+            let key = (
+                s.trace.expert_id.0.clone(),
+                s.trace.hardware_profile.class().as_str(),
+                s.action,
+            );
+            let entry = by_context.entry(key).or_insert((0.0, 0));
+            entry.0 += s.reward;
+            entry.1 += 1;
+            by_action[s.action].0 += s.reward;
+            by_action[s.action].1 += 1;
+        }
+        let overall = samples.iter().map(|s| s.reward).sum::<f32>() / samples.len().max(1) as f32;
+        RewardTable {
+            by_context,
+            by_action,
+            overall,
+        }
+    }
+
+    fn predict(&self, trace: &InferenceTrace, action: usize) -> f32 {
+        let key = (
+            trace.expert_id.0.clone(),
+            trace.hardware_profile.class().as_str(),
+            action,
+        );
+        if let Some(&(sum, n)) = self.by_context.get(&key) {
+            return sum / n as f32;
+        }
+        match self.by_action[action] {
+            (sum, n) if n > 0 => sum / n as f32,
+            _ => self.overall,
+        }
+    }
+}
+
+// Each estimator's value with its centered per-trace terms, whose spread gives the standard error
+struct Influence {
+    ips: (f32, Vec<f32>),
+    snips: (f32, Vec<f32>),
+    doubly_robust: (f32, Vec<f32>),
+    effective_sample_size: f32,
+}
+
+fn influence(
+    policy: &dyn BitPrecisionPolicy,
+    samples: &[Sample],
+    table: &RewardTable,
+    config: &OpeConfig,
+) -> Influence {
+    let n = samples.len().max(1) as f32;
+    let mut weights = Vec::with_capacity(samples.len());
+    let mut dr_terms = Vec::with_capacity(samples.len());
+    for s in samples {
+        let target = policy.bit_depth_probabilities(s.trace);
+        let mut w = target[s.action] / s.propensity;
+        if let Some(max) = config.max_weight {
+            w = w.min(max);
+        }
+        let direct: f32 = (0..BIT_DEPTHS.len()).map(|a| target[a] * table.predict(s.trace, a)).sum();
+        dr_terms.push(direct + w * (s.reward - table.predict(s.trace, s.action)));
+        weights.push(w);
+    }
+
+    let ips_terms: Vec<f32> = samples.iter().zip(&weights).map(|(s, w)| w * s.reward).collect();
+    let ips = ips_terms.iter().sum::<f32>() / n;
+    let weight_sum: f32 = weights.iter().sum();
+    let mean_weight = weight_sum / n;
+    let snips = if weight_sum > 0.0 {
+        samples.iter().zip(&weights).map(|(s, w)| w * s.reward).sum::<f32>() / weight_sum
+    } else {
+        0.0
+    };
+    let dr = dr_terms.iter().sum::<f32>() / n;
+    let weight_sq: f32 = weights.iter().map(|w| w * w).sum();
+
+    Influence {
+        ips: (ips, ips_terms.iter().map(|t| t - ips).collect()),
+        // Delta method for the ratio estimator
+        snips: (
+            snips,
+            samples
+                .iter()
+                .zip(&weights)
+                .map(|(s, w)| if mean_weight > 0.0 { w * (s.reward - snips) / mean_weight } else { 0.0 })
+                .collect(),
+        ),
+        doubly_robust: (dr, dr_terms.iter().map(|t| t - dr).collect()),
+        effective_sample_size: if weight_sq > 0.0 { weight_sum * weight_sum / weight_sq } else { 0.0 },
+    }
+}
+
+fn estimate(value: f32, influence: &[f32], z: f32) -> Estimate {
+    let n = influence.len();
+    let std_error = if n > 1 {
+        let variance = influence.iter().map(|t| t * t).sum::<f32>() / (n - 1) as f32;
+        (variance / n as f32).sqrt()
+    } else {
+        0.0
+    };
+    Estimate {
+        value,
+        std_error,
+        lower: value - z * std_error,
+        upper: value + z * std_error,
+    }
+}
+
+// Inverse standard normal CDF (Acklam's rational approximation, |error| < 1.2e-9)
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    let p = p.clamp(1e-12, 1.0 - 1e-12);
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+fn z_score(confidence: f32) -> f32 {
+    normal_quantile(0.5 + confidence.clamp(0.0, 0.999_999) as f64 / 2.0) as f32
+}
+
+// Keep traces whose logged propensity is a valid probability
+fn logged_samples<'a>(traces: &'a [InferenceTrace], reward: &dyn RewardModel) -> (Vec<Sample<'a>>, usize) {
+    let samples: Vec<Sample> = traces
+        .iter()
+        .filter_map(|trace| {
+            let propensity = trace.propensity.filter(|p| *p > 0.0 && *p <= 1.0)?;
+            let reward = reward.reward(trace);
+            reward.is_finite().then(|| Sample {
+                trace,
+                action: bit_depth_index(trace.bit_depth),
+                propensity,
+                reward,
+            })
+        })
+        .collect();
+    let skipped = traces.len() - samples.len();
+    (samples, skipped)
+}
+
+fn report(samples: &[Sample], skipped: usize, influence: &Influence, z: f32) -> OpeReport {
+    OpeReport {
+        ips: estimate(influence.ips.0, &influence.ips.1, z),
+        snips: estimate(influence.snips.0, &influence.snips.1, z),
+        doubly_robust: estimate(influence.doubly_robust.0, &influence.doubly_robust.1, z),
+        logged_value: samples.iter().map(|s| s.reward).sum::<f32>() / samples.len().max(1) as f32,
+        samples: samples.len(),
+        skipped,
+        effective_sample_size: influence.effective_sample_size,
+    }
+}
+
+// Estimate how `policy`, in its current state, would have scored on the logged traces.
+// The logged action of a trace is the bit depth it ran at, with `propensity` its logging probability.
+pub fn evaluate_policy(
+    policy: &dyn BitPrecisionPolicy,
+    traces: &[InferenceTrace],
+    reward: &dyn RewardModel,
+    config: &OpeConfig,
+) -> OpeReport {
+    let (samples, skipped) = logged_samples(traces, reward);
+    let table = RewardTable::fit(&samples);
+    let influence = influence(policy, &samples, &table, config);
+    report(&samples, skipped, &influence, z_score(config.confidence))
+}
+
+pub fn compare_policies(
+    baseline: &dyn BitPrecisionPolicy,
+    candidate: &dyn BitPrecisionPolicy,
+    traces: &[InferenceTrace],
+    reward: &dyn RewardModel,
+    config: &OpeConfig,
+) -> PolicyComparison {
+    let (samples, skipped) = logged_samples(traces, reward);
+    let table = RewardTable::fit(&samples);
+    let z = z_score(config.confidence);
+    let base = influence(baseline, &samples, &table, config);
+    let cand = influence(candidate, &samples, &table, config);
+
+    let difference = |b: &(f32, Vec<f32>), c: &(f32, Vec<f32>)| {
+        let paired: Vec<f32> = c.1.iter().zip(&b.1).map(|(c, b)| c - b).collect();
+        estimate(c.0 - b.0, &paired, z)
+    };
+    PolicyComparison {
+        ips_difference: difference(&base.ips, &cand.ips),
+        snips_difference: difference(&base.snips, &cand.snips),
+        doubly_robust_difference: difference(&base.doubly_robust, &cand.doubly_robust),
+        baseline: report(&samples, skipped, &base, z),
+        candidate: report(&samples, skipped, &cand, z),
+    }
+}
@@ -1,3 +1,5 @@
+use crate::cost_model::{CostEstimate, SharedCostModel};
+use crate::policy_engine::{BitDepth, HardwareProfile};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,30 +14,53 @@ pub enum PetriPlace {
 pub struct PetriTransition {
     pub from: PetriPlace,
     pub to: PetriPlace,
-    pub weight: u32,
+    pub bit_depth: BitDepth,
+    pub weight: f32, // predicted latency in ms, as the TS monoid's numeric weight
+    pub cost: CostEstimate,
 }
 
 pub struct PetriNetMonoid {
     transitions: Vec<PetriTransition>,
+    cost_model: SharedCostModel,
 }
 
 impl PetriNetMonoid {
-    pub fn new() -> Self {
+    pub fn new(cost_model: SharedCostModel) -> Self {
         PetriNetMonoid {
             transitions: Vec::new(),
+            cost_model,
         }
     }
 
-    pub fn log_transition(&mut self, from: PetriPlace, to: PetriPlace, bit_depth: BitDepth) {
-        let weight = match bit_depth {
-            BitDepth::INT4 => 1,
-            BitDepth::INT8 => 2,
-            BitDepth::FP16 => 3,
-        };
-        self.transitions.push(PetriTransition { from, to, weight });
+    // Weight the transition by the predicted cost of running an expert of `num_params` weights
+    // over `tokens` at `bit_depth` on `hardware`
+    pub fn log_transition(
+        &mut self,
+        from: PetriPlace,
+        to: PetriPlace,
+        bit_depth: BitDepth,
+        num_params: usize,
+        tokens: usize,
+        hardware: &HardwareProfile,
+    ) {
+        let cost = self.cost_model.borrow().predict(num_params, tokens, bit_depth, hardware);
+        self.transitions.push(PetriTransition {
+            from,
+            to,
+            bit_depth,
+            weight: cost.latency,
+            cost,
+        });
     }
 
     pub fn get_transitions(&self) -> &[PetriTransition] {
         &self.transitions
     }
-}
\ No newline at end of file
+
+    // Combined cost of every logged transition
+    pub fn total_cost(&self) -> CostEstimate {
+        self.transitions
+            .iter()
+            .fold(CostEstimate::default(), |acc, t| acc + t.cost)
+    }
+}
@@ -1,29 +1,90 @@
 use serde::{Deserialize, Serialize};
-use candle_core::Tensor;
+use candle_core::{Device, Error as CandleError, Tensor};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use crate::drift::{DriftAlarm, DriftConfig, DriftMonitor, DriftResponse};
+use crate::exploration::{EpsilonGreedy, ExplorationConfig, ExplorationContext, ExplorationStrategy};
+use crate::checkpoint::{read_json, read_safetensors, write_json, write_safetensors, CheckpointHeader, PolicyCheckpoint};
+use crate::expert_registry::{ExpertRegistry, SharedRegistry};
+use crate::gating::{GatingFunction, SoftmaxTopKGate};
+use crate::hardware::{HardwareClass, HARDWARE_FEATURES};
+use crate::nn::{log_softmax, Adam, Mlp};
+use crate::reward::{SharedReward, WeightedSumReward};
+use crate::rng::{default_rng, seeded_rng, SharedRng};
+use crate::trace_buffer::InferenceTrace;
+use rand::rngs::StdRng;
+use rand::Rng;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+// Move along the bit-depth ladder `BIT_DEPTHS` (INT4 < INT8 < FP16), relative to the expert's
+// current depth or to an absolute rung
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum QuantizationDecision {
-    Up,
-    Down,
+    Up,   // one rung towards higher precision
+    Down, // one rung towards lower precision
     Hold,
+    Step(i8),      // any number of rungs, positive towards higher precision
+    Set(BitDepth), // straight to a depth, wherever the expert is; used for depths imposed by constraints
+}
+
+impl QuantizationDecision {
+    // Smallest relative decision taking an expert from `from` to `to`
+    pub fn between(from: BitDepth, to: BitDepth) -> Self {
+        match bit_depth_index(to) as i8 - bit_depth_index(from) as i8 {
+            0 => QuantizationDecision::Hold,
+            1 => QuantizationDecision::Up,
+            -1 => QuantizationDecision::Down,
+            rungs => QuantizationDecision::Step(rungs),
+        }
+    }
+
+    // Rungs this decision moves from `current`, before stopping at the ends of the ladder
+    pub fn offset(&self, current: BitDepth) -> i8 {
+        match self {
+            QuantizationDecision::Up => 1,
+            QuantizationDecision::Down => -1,
+            QuantizationDecision::Hold => 0,
+            QuantizationDecision::Step(rungs) => *rungs,
+            QuantizationDecision::Set(target) => bit_depth_index(*target) as i8 - bit_depth_index(current) as i8,
+        }
+    }
+
+    // Bit depth reached by applying this decision at `current`; moves past either end stop there
+    pub fn apply(&self, current: BitDepth) -> BitDepth {
+        let rung = bit_depth_index(current) as i32 + self.offset(current) as i32;
+        BIT_DEPTHS[rung.clamp(0, BIT_DEPTHS.len() as i32 - 1) as usize]
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExpertId(pub String);
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BitDepth {
     INT4,
     INT8,
     FP16,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HardwareProfile {
-    pub hardware_type: String, // e.g., "cpu", "gpu", "tpu"
+impl BitDepth {
+    // Storage width of one weight at this depth
+    pub fn bits(&self) -> u8 {
+        match self {
+            BitDepth::INT4 => 4,
+            BitDepth::INT8 => 8,
+            BitDepth::FP16 => 16,
+        }
+    }
+
+    // Inverse of `bits`
+    pub fn from_bits(bits: u8) -> Option<BitDepth> {
+        BIT_DEPTHS.into_iter().find(|d| d.bits() == bits)
+    }
 }
 
+pub use crate::hardware::HardwareProfile;
+
 pub trait BitPrecisionPolicy {
     fn select_experts(
         &self,
@@ -31,129 +92,849 @@ pub trait BitPrecisionPolicy {
         hardware_profile: &HardwareProfile,
     ) -> Vec<(ExpertId, BitDepth)>;
     fn update_policy(&mut self, trace: InferenceTrace);
+    // Probability of running `trace.expert_id` at each of INT4, INT8, FP16 for the trace's
+    // context, given the policy's current state; used for propensities and off-policy evaluation
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS];
+}
+
+// Bootstrap target used by `QLearningPolicy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UpdateRule {
+    QLearning, // r + γ max_a' Q(s', a')
+    Sarsa,     // r + γ Q(s', a') with a' the next action actually taken for the expert
+}
+
+// Mirrors the TS `QLearningConfig` so the same JSON configures both implementations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QLearningConfig {
+    pub learning_rate: f32,
+    pub discount_factor: f32,
+    pub exploration_rate: f32,
+    pub exploration_decay: f32, // multiplied into the exploration rate after every update
+    pub min_exploration_rate: f32,
+    pub update_rule: UpdateRule,
+    pub exploration: Option<ExplorationConfig>, // None is ε-greedy with the rates above
+}
+
+impl Default for QLearningConfig {
+    fn default() -> Self {
+        QLearningConfig {
+            learning_rate: 0.1,
+            discount_factor: 0.9,
+            exploration_rate: 0.1,
+            exploration_decay: 0.995,
+            min_exploration_rate: 0.01,
+            update_rule: UpdateRule::QLearning,
+            exploration: None,
+        }
+    }
+}
+
+impl QLearningConfig {
+    fn exploration_strategy(&self) -> Box<dyn ExplorationStrategy> {
+        match &self.exploration {
+            Some(exploration) => exploration.build(),
+            None => Box::new(EpsilonGreedy {
+                epsilon: self.exploration_rate,
+                decay: self.exploration_decay,
+                min_epsilon: self.min_exploration_rate,
+            }),
+        }
+    }
 }
 
 pub struct QLearningPolicy {
-    q_table: HashMap<String, [f32; 3]>,
-    lambda1: f32, // Latency penalty
-    lambda2: f32, // Token drop penalty
-    epsilon: f32, // Exploration rate
+    gate: Rc<dyn GatingFunction>,
+    registry: SharedRegistry,
+    q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+    reward: SharedReward,
+    exploration: Box<dyn ExplorationStrategy>,
+    config: QLearningConfig,
+    pending: HashMap<String, (String, usize, f32)>, // SARSA: expert -> (state key, action, reward) awaiting a'
+    depths: HashMap<String, BitDepth>,              // expert -> depth of its latest trace
+    rng: SharedRng,
+    drift: Option<DriftMonitor>,
+    boosts: HashMap<String, f32>, // expert -> share of uniform exploration after a drift alarm
 }
 
 impl QLearningPolicy {
-    pub fn new(lambda1: f32, lambda2: f32, epsilon: f32) -> Self {
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward, epsilon: f32) -> Self {
+        Self::with_config(
+            gate,
+            registry,
+            reward,
+            QLearningConfig {
+                exploration_rate: epsilon,
+                ..QLearningConfig::default()
+            },
+        )
+    }
+
+    pub fn with_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        config: QLearningConfig,
+    ) -> Self {
         QLearningPolicy {
+            gate,
+            registry,
             q_table: HashMap::new(),
-            lambda1,
-            lambda2,
-            epsilon,
+            reward,
+            exploration: config.exploration_strategy(),
+            config,
+            pending: HashMap::new(),
+            depths: HashMap::new(),
+            rng: default_rng(),
+            drift: None,
+            boosts: HashMap::new(),
         }
     }
 
+    // Replace the source of exploration draws, e.g. with a seeded one to replay a run
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.rng = rng;
+    }
+
+    // Current ε, temperature or bonus scale of the exploration strategy
+    pub fn exploration_rate(&self) -> f32 {
+        self.exploration.rate()
+    }
+
+    // Replace how actions are explored, e.g. with `LevyFlight`
+    pub fn set_exploration(&mut self, exploration: Box<dyn ExplorationStrategy>) {
+        self.exploration = exploration;
+    }
+
+    // Probability of a uniformly random depth for one expert after a drift alarm, on top of the strategy
+    pub fn exploration_boost(&self, expert_id: &ExpertId) -> f32 {
+        self.boosts.get(&expert_id.0).copied().unwrap_or(0.0)
+    }
+
+    // Watch each expert's reward stream for change points and re-explore the experts that shift
+    pub fn set_drift_detection(&mut self, config: DriftConfig) {
+        self.drift = Some(DriftMonitor::new(config));
+    }
+
+    pub fn clear_drift_detection(&mut self) {
+        self.drift = None;
+        self.boosts.clear();
+    }
+
+    // Every drift alarm raised since detection was enabled
+    pub fn drift_history(&self) -> &[DriftAlarm] {
+        self.drift.as_ref().map_or(&[], |d| d.history())
+    }
+
+    fn respond_to_drift(&mut self, alarm: &DriftAlarm, response: DriftResponse, boost: f32) {
+        if matches!(response, DriftResponse::ResetState | DriftResponse::Both) {
+            // Keys are "expert:bit depth:hardware class"; expert ids may contain ':' themselves
+            self.q_table
+                .retain(|key, _| key.rsplitn(3, ':').nth(2) != Some(alarm.expert_id.as_str()));
+            self.pending.remove(&alarm.expert_id);
+        }
+        if matches!(response, DriftResponse::BoostExploration | DriftResponse::Both) {
+            self.boosts.insert(alarm.expert_id.clone(), boost.clamp(0.0, 1.0));
+        }
+    }
+
+    fn q_values(&self, state_key: &str) -> [f32; NUM_BIT_DEPTHS] {
+        self.q_table.get(state_key).copied().unwrap_or([0.0; NUM_BIT_DEPTHS])
+    }
+
+    fn td_update(&mut self, state_key: String, action: usize, target: f32) {
+        let alpha = self.config.learning_rate;
+        let q_values = self.q_table.entry(state_key).or_insert([0.0; NUM_BIT_DEPTHS]);
+        q_values[action] += alpha * (target - q_values[action]);
+    }
+
     fn get_state_key(&self, expert_id: &ExpertId, bit_depth: BitDepth, hardware: &HardwareProfile) -> String {
-        format!("{}:{:?}:{}", expert_id.0, bit_depth, hardware.hardware_type)
+        q_state_key(expert_id, bit_depth, hardware)
+    }
+
+    pub fn q_table(&self) -> &HashMap<String, [f32; NUM_BIT_DEPTHS]> {
+        &self.q_table
+    }
+
+    // Replace the learned values, e.g. with a table trained offline
+    pub fn set_q_table(&mut self, q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]>) {
+        self.q_table = q_table;
+        self.pending.clear();
     }
 }
 
+// Tabular state of `QLearningPolicy`: one entry per (expert, bit depth, hardware class), so
+// machines of the same class share what was learned
+pub(crate) fn q_state_key(expert_id: &ExpertId, bit_depth: BitDepth, hardware: &HardwareProfile) -> String {
+    format!("{}:{:?}:{}", expert_id.0, bit_depth, hardware.class().as_str())
+}
+
+// Tabular transition between consecutive traces of an expert: from the depth it ran at before
+// (`previous`, or the trace's own depth for its first trace), the action is the rung it ran at
+// next, which is also the next state. The same move means different actions from different
+// depths, so Up from INT4 and Up from INT8 are learned apart.
+pub(crate) fn q_transition(previous: Option<BitDepth>, trace: &InferenceTrace) -> (String, usize, String) {
+    let current = previous.unwrap_or(trace.bit_depth);
+    (
+        q_state_key(&trace.expert_id, current, &trace.hardware_profile),
+        bit_depth_index(trace.bit_depth),
+        q_state_key(&trace.expert_id, trace.bit_depth, &trace.hardware_profile),
+    )
+}
+
 impl BitPrecisionPolicy for QLearningPolicy {
     fn select_experts(
         &self,
         input_tensor: &Tensor,
         hardware_profile: &HardwareProfile,
     ) -> Vec<(ExpertId, BitDepth)> {
-        // Mock expert selection (replace with actual MoE gating)
-        let experts = vec![ExpertId("expert1".to_string()), ExpertId("expert2".to_string())];
-        let bit_depths = [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16];
+        // Bit depths are only decided for the active experts the gate routes this input to
+        let routed = match self.gate.route(input_tensor) {
+            Ok(routed) => routed,
+            Err(_) => return Vec::new(),
+        };
+        let registry = self.registry.borrow();
 
-        experts
+        routed
             .into_iter()
-            .map(|expert| {
-                let state_key = self.get_state_key(&expert, BitDepth::INT8, hardware_profile);
-                let q_values = self.q_table.get(&state_key).copied().unwrap_or([0.0; 3]);
-                let action = if rand::random::<f32>() < self.epsilon {
-                    // Exploration
-                    match rand::random::<u8>() % 3 {
-                        0 => QuantizationDecision::Up,
-                        1 => QuantizationDecision::Down,
-                        _ => QuantizationDecision::Hold,
-                    }
+            .filter(|(expert, _)| registry.is_active(expert))
+            .map(|(expert, _)| {
+                let current = registry.bit_depth(&expert).unwrap_or(BitDepth::INT8);
+                let state_key = self.get_state_key(&expert, current, hardware_profile);
+                let values = self.q_values(&state_key);
+                let context = ExplorationContext {
+                    state_key: &state_key,
+                    values: &values,
+                };
+                let mut rng = self.rng.borrow_mut();
+                let action = if rng.gen::<f32>() < self.exploration_boost(&expert) {
+                    rng.gen_range(0..BIT_DEPTHS.len())
                 } else {
-                    // Exploitation
-                    let idx = q_values
-                        .iter()
-                        .enumerate()
-                        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                        .map(|(i, _)| i)
-                        .unwrap();
-                    match idx {
-                        0 => QuantizationDecision::Up,
-                        1 => QuantizationDecision::Down,
-                        _ => QuantizationDecision::Hold,
-                    }
+                    self.exploration.select(&context, &mut rng)
                 };
 
-                let bit_depth = match action {
-                    QuantizationDecision::Up => BitDepth::FP16,
-                    QuantizationDecision::Down => BitDepth::INT4,
-                    QuantizationDecision::Hold => BitDepth::INT8,
-                };
-                (expert, bit_depth)
+                (expert, BIT_DEPTHS[action])
             })
             .collect()
     }
 
     fn update_policy(&mut self, trace: InferenceTrace) {
-        let state_key = self.get_state_key(&trace.expert_id, trace.bit_depth, &trace.hardware_profile);
-        let q_values = self.q_table.entry(state_key).or_insert([0.0; 3]);
-        let action_idx = match trace.decision {
-            QuantizationDecision::Up => 0,
-            QuantizationDecision::Down => 1,
-            QuantizationDecision::Hold => 2,
-        };
-        let reward = trace.accuracy - self.lambda1 * trace.latency - self.lambda2 * trace.token_loss;
-        q_values[action_idx] += 0.1 * (reward - q_values[action_idx]);
+        let previous = self.depths.insert(trace.expert_id.0.clone(), trace.bit_depth);
+        let (state_key, action_idx, next_key) = q_transition(previous, &trace);
+        let reward = self.reward.borrow().reward(&trace);
+        let gamma = self.config.discount_factor;
+
+        let alarm = self.drift.as_mut().and_then(|monitor| {
+            let config = monitor.config();
+            let (response, boost) = (config.response, config.exploration_boost);
+            monitor.observe(&trace, reward).map(|alarm| (alarm, response, boost))
+        });
+        if let Some((alarm, response, boost)) = alarm {
+            self.respond_to_drift(&alarm, response, boost);
+        }
+
+        self.exploration.observe(&state_key, action_idx);
+        match self.config.update_rule {
+            UpdateRule::QLearning => {
+                let next_max = self.q_values(&next_key).iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                self.td_update(state_key, action_idx, reward + gamma * next_max);
+            }
+            UpdateRule::Sarsa => {
+                // This trace's (state, action) is the successor of the expert's previous transition
+                if let Some((prev_key, prev_action, prev_reward)) = self.pending.remove(&trace.expert_id.0) {
+                    let next_q = self.q_values(&state_key)[action_idx];
+                    self.td_update(prev_key, prev_action, prev_reward + gamma * next_q);
+                }
+                self.pending.insert(trace.expert_id.0.clone(), (state_key, action_idx, reward));
+            }
+        }
+
+        self.exploration.decay();
+        if let Some(boost) = self.boosts.get_mut(&trace.expert_id.0) {
+            *boost *= self.config.exploration_decay;
+            if *boost <= self.config.min_exploration_rate {
+                self.boosts.remove(&trace.expert_id.0);
+            }
+        }
     }
+
+    // The strategy's distribution, mixed with uniform by any drift boost
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let current = self.registry.borrow().bit_depth(&trace.expert_id).unwrap_or(BitDepth::INT8);
+        let state_key = self.get_state_key(&trace.expert_id, current, &trace.hardware_profile);
+        let values = self.q_values(&state_key);
+        let strategy = self.exploration.probabilities(&ExplorationContext {
+            state_key: &state_key,
+            values: &values,
+        });
+        let boost = self.exploration_boost(&trace.expert_id);
+        std::array::from_fn(|i| boost / NUM_BIT_DEPTHS as f32 + (1.0 - boost) * strategy[i])
+    }
+}
+
+// Q-table keys are "expert:bit depth:hardware class", values are per target depth in `BIT_DEPTHS` order
+const Q_TABLE_SCHEMA: &str = "q_table:expert,bit_depth,hardware:int4,int8,fp16";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QLearningState {
+    q_table: HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+    epsilon: f32,
+}
+
+// Checkpoint loadable by `QLearningPolicy`, also used to ship offline-trained tables
+pub(crate) fn q_table_checkpoint(
+    config: &QLearningConfig,
+    q_table: &HashMap<String, [f32; NUM_BIT_DEPTHS]>,
+    epsilon: f32,
+) -> Result<Vec<u8>, CandleError> {
+    let header = CheckpointHeader::new("q_learning", Q_TABLE_SCHEMA, config)?;
+    write_json(
+        header,
+        &QLearningState {
+            q_table: q_table.clone(),
+            epsilon,
+        },
+    )
+}
+
+impl PolicyCheckpoint for QLearningPolicy {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError> {
+        q_table_checkpoint(&self.config, &self.q_table, self.exploration.rate())
+    }
+
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError> {
+        let (header, state): (_, QLearningState) = read_json(data, "q_learning", Q_TABLE_SCHEMA)?;
+        self.config = header.hyperparameters()?;
+        self.q_table = state.q_table;
+        self.exploration = self.config.exploration_strategy();
+        self.exploration.set_rate(state.epsilon);
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+// Number of hash buckets used to one-hot encode expert ids in the state features
+const EXPERT_BUCKETS: usize = 8;
+// Hardware classes told apart by `hardware_index`
+const HARDWARE_CLASSES: usize = 3;
+// Recent latency, accuracy and token loss
+const STAT_FEATURES: usize = 3;
+pub(crate) const STATE_FEATURES: usize = EXPERT_BUCKETS + NUM_BIT_DEPTHS + HARDWARE_CLASSES + STAT_FEATURES + HARDWARE_FEATURES;
+pub(crate) const BIT_DEPTHS: [BitDepth; 3] = [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16];
+pub(crate) const NUM_BIT_DEPTHS: usize = BIT_DEPTHS.len();
+
+pub(crate) fn states_tensor(states: &[Vec<f32>]) -> Result<Tensor, CandleError> {
+    let flat: Vec<f32> = states.iter().flatten().copied().collect();
+    Tensor::from_vec(flat, (states.len(), STATE_FEATURES), &Device::Cpu)
+}
+
+pub(crate) fn bit_depth_index(bit_depth: BitDepth) -> usize {
+    match bit_depth {
+        BitDepth::INT4 => 0,
+        BitDepth::INT8 => 1,
+        BitDepth::FP16 => 2,
+    }
+}
+
+// Distribution that always picks `bit_depth`
+pub(crate) fn certain(bit_depth: BitDepth) -> [f32; NUM_BIT_DEPTHS] {
+    let mut probs = [0.0f32; NUM_BIT_DEPTHS];
+    probs[bit_depth_index(bit_depth)] = 1.0;
+    probs
+}
+
+// Exponential moving averages of an expert's recent inference outcomes
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ExpertStats {
+    pub latency: f32,
+    pub accuracy: f32,
+    pub token_loss: f32,
+}
+
+impl ExpertStats {
+    pub(crate) fn observe(&mut self, trace: &InferenceTrace, decay: f32) {
+        self.latency = decay * self.latency + (1.0 - decay) * trace.latency;
+        self.accuracy = decay * self.accuracy + (1.0 - decay) * trace.accuracy;
+        self.token_loss = decay * self.token_loss + (1.0 - decay) * trace.token_loss;
+    }
+}
+
+// Position of the hardware class in one-hot encodings: cpu (and wasm), gpu, tpu
+pub(crate) fn hardware_index(hardware: &HardwareProfile) -> usize {
+    match hardware.class() {
+        HardwareClass::Gpu => 1,
+        HardwareClass::Tpu => 2,
+        HardwareClass::Cpu | HardwareClass::Wasm => 0,
+    }
+}
+
+// Feature vector [expert bucket one-hot | bit depth one-hot | hardware one-hot | recent stats |
+// hardware features], the last letting one network generalize across machines of a class
+pub fn state_features(
+    expert_id: &ExpertId,
+    bit_depth: BitDepth,
+    hardware: &HardwareProfile,
+    stats: &ExpertStats,
+) -> Vec<f32> {
+    let mut features = vec![0.0f32; STATE_FEATURES];
+    let bucket = expert_id.0.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize)) % EXPERT_BUCKETS;
+    features[bucket] = 1.0;
+    features[EXPERT_BUCKETS + bit_depth_index(bit_depth)] = 1.0;
+    let hardware_offset = EXPERT_BUCKETS + NUM_BIT_DEPTHS;
+    features[hardware_offset + hardware_index(hardware)] = 1.0;
+    let stats_offset = hardware_offset + HARDWARE_CLASSES;
+    features[stats_offset] = stats.latency;
+    features[stats_offset + 1] = stats.accuracy;
+    features[stats_offset + 2] = stats.token_loss;
+    features[stats_offset + STAT_FEATURES..].copy_from_slice(&hardware.features());
+    features
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PPOConfig {
+    pub hidden_size: usize,
+    pub learning_rate: f64,
+    pub gamma: f32,        // discount factor
+    pub gae_lambda: f32,   // GAE smoothing
+    pub clip_epsilon: f32, // surrogate ratio clip
+    pub value_coef: f32,
+    pub entropy_coef: f32,
+    pub rollout_size: usize, // transitions collected before each update
+    pub epochs: usize,
+    pub minibatch_size: usize,
+    pub max_grad_norm: f64,
+    pub stats_decay: f32,
+}
+
+impl Default for PPOConfig {
+    fn default() -> Self {
+        PPOConfig {
+            hidden_size: 32,
+            learning_rate: 3e-3,
+            gamma: 0.9,
+            gae_lambda: 0.95,
+            clip_epsilon: 0.2,
+            value_coef: 0.5,
+            entropy_coef: 0.01,
+            rollout_size: 64,
+            epochs: 4,
+            minibatch_size: 16,
+            max_grad_norm: 0.5,
+            stats_decay: 0.9,
+        }
+    }
+}
+
+// Depth the actor drew for an expert, with the state and log-probability it was drawn at
+struct SampledAction {
+    bit_depth: BitDepth,
+    state: Vec<f32>,
+    log_prob: f32,
+}
+
+// One step of experience; the action is the bit depth the expert ran at
+struct RolloutStep {
+    expert: String,
+    state: Vec<f32>,
+    next_state: Vec<f32>,
+    action: usize,
+    reward: f32,
+    log_prob: f32,
+    value: f32,
 }
 
 pub struct PPOPolicy {
-    policy_network: Tensor, // Placeholder for neural network
-    lambda1: f32,
-    lambda2: f32,
+    gate: Rc<dyn GatingFunction>,
+    registry: SharedRegistry,
+    actor: Mlp,  // state -> logits over BIT_DEPTHS
+    critic: Mlp, // state -> value
+    actor_optimizer: Adam,
+    critic_optimizer: Adam,
+    config: PPOConfig,
+    rollout: Vec<RolloutStep>,
+    current_depths: HashMap<String, BitDepth>,
+    expert_stats: HashMap<String, ExpertStats>,
+    sampled: RefCell<HashMap<String, SampledAction>>, // last draw per expert, matched to its next trace
+    reward: SharedReward,
+    rng: SharedRng, // weight initialization, action sampling and minibatch shuffling
 }
 
 impl PPOPolicy {
-    pub fn new(lambda1: f32, lambda2: f32) -> Self {
-        // Initialize a simple policy network (placeholder)
-        let policy_network = Tensor::zeros((10, 3), candle_core::DType::F32, &candle_core::Device::Cpu).unwrap();
-        PPOPolicy {
-            policy_network,
-            lambda1,
-            lambda2,
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward) -> Result<Self, CandleError> {
+        Self::with_config(gate, registry, reward, PPOConfig::default())
+    }
+
+    pub fn with_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        config: PPOConfig,
+    ) -> Result<Self, CandleError> {
+        let rng = default_rng();
+        let (actor, critic) = Self::networks(&config, &mut rng.borrow_mut())?;
+        let actor_optimizer = Adam::new(actor.vars(), config.learning_rate)?;
+        let critic_optimizer = Adam::new(critic.vars(), config.learning_rate)?;
+        Ok(PPOPolicy {
+            gate,
+            registry,
+            actor,
+            critic,
+            actor_optimizer,
+            critic_optimizer,
+            config,
+            rollout: Vec::new(),
+            current_depths: HashMap::new(),
+            expert_stats: HashMap::new(),
+            sampled: RefCell::new(HashMap::new()),
+            reward,
+            rng,
+        })
+    }
+
+    fn networks(config: &PPOConfig, rng: &mut StdRng) -> Result<(Mlp, Mlp), CandleError> {
+        let device = Device::Cpu;
+        let actor = Mlp::new(&[STATE_FEATURES, config.hidden_size, config.hidden_size, BIT_DEPTHS.len()], &device, rng)?;
+        let critic = Mlp::new(&[STATE_FEATURES, config.hidden_size, config.hidden_size, 1], &device, rng)?;
+        Ok((actor, critic))
+    }
+
+    // Replace the source of action sampling and shuffling; the current weights are kept
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.rng = rng;
+    }
+
+    fn features(&self, expert_id: &ExpertId, hardware: &HardwareProfile) -> Vec<f32> {
+        self.features_at(expert_id, None, hardware)
+    }
+
+    // `current_depth`, when the caller knows it, overrides the depth last seen for the expert
+    fn features_at(&self, expert_id: &ExpertId, current_depth: Option<BitDepth>, hardware: &HardwareProfile) -> Vec<f32> {
+        let bit_depth = current_depth
+            .or_else(|| self.current_depths.get(&expert_id.0).copied())
+            .or_else(|| self.registry.borrow().bit_depth(expert_id))
+            .unwrap_or(BitDepth::INT8);
+        let stats = self.expert_stats.get(&expert_id.0).copied().unwrap_or_default();
+        state_features(expert_id, bit_depth, hardware, &stats)
+    }
+
+    fn action_probabilities(&self, states: &[Vec<f32>]) -> Result<Vec<Vec<f32>>, CandleError> {
+        log_softmax(&self.actor.forward(&states_tensor(states)?)?)?.exp()?.to_vec2::<f32>()
+    }
+
+    fn values(&self, states: &[Vec<f32>]) -> Result<Vec<f32>, CandleError> {
+        self.critic.forward(&states_tensor(states)?)?.squeeze(1)?.to_vec1::<f32>()
+    }
+
+    // Sample a bit depth for one expert from the current actor
+    pub fn select_bit_depth(&self, expert_id: &ExpertId, hardware: &HardwareProfile) -> BitDepth {
+        self.select_bit_depth_from(expert_id, None, hardware)
+    }
+
+    // Same as `select_bit_depth`, for an expert the caller reports running at `current_depth`
+    pub fn select_bit_depth_from(
+        &self,
+        expert_id: &ExpertId,
+        current_depth: Option<BitDepth>,
+        hardware: &HardwareProfile,
+    ) -> BitDepth {
+        let state = self.features_at(expert_id, current_depth, hardware);
+        let probs = match self.action_probabilities(std::slice::from_ref(&state)) {
+            Ok(p) => p.into_iter().next().unwrap_or_default(),
+            Err(_) => return BitDepth::INT8,
+        };
+        let draw = self.rng.borrow_mut().gen::<f32>();
+        let mut cumulative = 0.0;
+        let idx = probs
+            .iter()
+            .position(|p| {
+                cumulative += p;
+                draw < cumulative
+            })
+            .unwrap_or(BIT_DEPTHS.len() - 1);
+        let sampled = SampledAction {
+            bit_depth: BIT_DEPTHS[idx],
+            state,
+            log_prob: probs.get(idx).copied().unwrap_or(0.0).max(1e-8).ln(),
+        };
+        self.sampled.borrow_mut().insert(expert_id.0.clone(), sampled);
+        BIT_DEPTHS[idx]
+    }
+
+    // Generalized advantage estimates and returns, computed per expert trajectory
+    fn advantages(&self) -> Result<(Vec<f32>, Vec<f32>), CandleError> {
+        let next_states: Vec<Vec<f32>> = self.rollout.iter().map(|s| s.next_state.clone()).collect();
+        let next_values = self.values(&next_states)?;
+
+        let mut trajectories: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, step) in self.rollout.iter().enumerate() {
+            trajectories.entry(step.expert.as_str()).or_default().push(i);
+        }
+
+        let mut advantages = vec![0.0f32; self.rollout.len()];
+        for indices in trajectories.values() {
+            let mut gae = 0.0f32;
+            for (pos, &i) in indices.iter().enumerate().rev() {
+                let step = &self.rollout[i];
+                let next_value = match indices.get(pos + 1) {
+                    Some(&j) => self.rollout[j].value,
+                    None => next_values[i],
+                };
+                let delta = step.reward + self.config.gamma * next_value - step.value;
+                gae = delta + self.config.gamma * self.config.gae_lambda * gae;
+                advantages[i] = gae;
+            }
         }
+        let returns = advantages.iter().zip(&self.rollout).map(|(a, s)| a + s.value).collect();
+        Ok((advantages, returns))
+    }
+
+    // Clipped-surrogate PPO over minibatches of the collected rollout
+    fn train(&mut self) -> Result<(), CandleError> {
+        let device = Device::Cpu;
+        let (mut advantages, returns) = self.advantages()?;
+        let n = advantages.len();
+        let mean = advantages.iter().sum::<f32>() / n as f32;
+        let std = (advantages.iter().map(|a| (a - mean) * (a - mean)).sum::<f32>() / n as f32).sqrt();
+        advantages.iter_mut().for_each(|a| *a = (*a - mean) / (std + 1e-8));
+
+        let states = states_tensor(&self.rollout.iter().map(|s| s.state.clone()).collect::<Vec<_>>())?;
+        let actions = Tensor::new(self.rollout.iter().map(|s| s.action as u32).collect::<Vec<_>>(), &device)?;
+        let old_log_probs = Tensor::new(self.rollout.iter().map(|s| s.log_prob).collect::<Vec<_>>(), &device)?;
+        let advantages = Tensor::new(advantages, &device)?;
+        let returns = Tensor::new(returns, &device)?;
+
+        let mut order: Vec<u32> = (0..n as u32).collect();
+        for _ in 0..self.config.epochs {
+            // Fisher-Yates shuffle
+            for i in (1..order.len()).rev() {
+                let j = self.rng.borrow_mut().gen_range(0..=i);
+                order.swap(i, j);
+            }
+            for batch in order.chunks(self.config.minibatch_size.max(1)) {
+                let idx = Tensor::new(batch, &device)?;
+                let batch_states = states.index_select(&idx, 0)?;
+                let batch_actions = actions.index_select(&idx, 0)?.unsqueeze(1)?;
+                let batch_old = old_log_probs.index_select(&idx, 0)?;
+                let batch_adv = advantages.index_select(&idx, 0)?;
+                let batch_returns = returns.index_select(&idx, 0)?;
+
+                let log_probs = log_softmax(&self.actor.forward(&batch_states)?)?;
+                let new_log_probs = log_probs.gather(&batch_actions, 1)?.squeeze(1)?;
+                let ratio = (new_log_probs - batch_old)?.exp()?;
+                let eps = self.config.clip_epsilon as f64;
+                let unclipped = (&ratio * &batch_adv)?;
+                let clipped = (ratio.clamp(1.0 - eps, 1.0 + eps)? * &batch_adv)?;
+                let policy_loss = unclipped.minimum(&clipped)?.mean_all()?.neg()?;
+                let entropy = (log_probs.exp()? * &log_probs)?.sum(1)?.mean_all()?.neg()?;
+                let actor_loss = (policy_loss - entropy.affine(self.config.entropy_coef as f64, 0.0)?)?;
+                self.actor_optimizer.backward_step(&actor_loss, Some(self.config.max_grad_norm))?;
+
+                let values = self.critic.forward(&batch_states)?.squeeze(1)?;
+                let value_loss = (values - batch_returns)?.sqr()?.mean_all()?.affine(self.config.value_coef as f64, 0.0)?;
+                self.critic_optimizer.backward_step(&value_loss, Some(self.config.max_grad_norm))?;
+            }
+        }
+        self.rollout.clear();
+        Ok(())
     }
 }
 
 impl BitPrecisionPolicy for PPOPolicy {
     fn select_experts(
         &self,
-        _input_tensor: &Tensor,
+        input_tensor: &Tensor,
         hardware_profile: &HardwareProfile,
     ) -> Vec<(ExpertId, BitDepth)> {
-        // Mock PPO-based selection
-        let experts = vec![ExpertId("expert1".to_string()), ExpertId("expert2".to_string())];
-        experts
+        let routed = match self.gate.route(input_tensor) {
+            Ok(routed) => routed,
+            Err(_) => return Vec::new(),
+        };
+        routed
             .into_iter()
-            .map(|expert| {
-                // Simplified policy network output (replace with actual NN inference)
-                let bit_depth = BitDepth::INT8; // Placeholder
+            .filter(|(expert, _)| self.registry.borrow().is_active(expert))
+            .map(|(expert, _)| {
+                let bit_depth = self.select_bit_depth(&expert, hardware_profile);
                 (expert, bit_depth)
             })
             .collect()
     }
 
     fn update_policy(&mut self, trace: InferenceTrace) {
-        // PPO update with clipped advantage (placeholder)
-        let reward = trace.accuracy - self.lambda1 * trace.latency - self.lambda2 * trace.token_loss;
-        // Update policy_network (requires actual NN training logic)
+        let sampled = self.sampled.get_mut().remove(&trace.expert_id.0);
+        let action = bit_depth_index(trace.bit_depth);
+        let reward = self.reward.borrow().reward(&trace);
+
+        // Next state: the expert now sits at the depth it ran at, with refreshed stats
+        self.current_depths.insert(trace.expert_id.0.clone(), trace.bit_depth);
+        self.expert_stats
+            .entry(trace.expert_id.0.clone())
+            .or_default()
+            .observe(&trace, self.config.stats_decay);
+        let next_state = self.features(&trace.expert_id, &trace.hardware_profile);
+
+        // The clipped ratio needs the probability the depth was drawn with, so only depths this
+        // policy sampled are learned from; overrides and other policies' choices only move the state
+        let Some(sampled) = sampled.filter(|s| s.bit_depth == trace.bit_depth) else {
+            return;
+        };
+        let Ok(values) = self.values(std::slice::from_ref(&sampled.state)) else {
+            return;
+        };
+        self.rollout.push(RolloutStep {
+            expert: trace.expert_id.0.clone(),
+            state: sampled.state,
+            next_state,
+            action,
+            reward,
+            log_prob: sampled.log_prob,
+            value: values[0],
+        });
+
+        if self.rollout.len() >= self.config.rollout_size && self.train().is_err() {
+            self.rollout.clear();
+        }
+    }
+
+    fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let state = self.features(&trace.expert_id, &trace.hardware_profile);
+        match self.action_probabilities(&[state]) {
+            Ok(probs) => {
+                let mut out = [0.0f32; NUM_BIT_DEPTHS];
+                out.copy_from_slice(&probs[0][..NUM_BIT_DEPTHS]);
+                out
+            }
+            Err(_) => certain(BitDepth::INT8),
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PPOState {
+    current_depths: HashMap<String, BitDepth>,
+    expert_stats: HashMap<String, ExpertStats>,
+}
+
+fn ppo_schema() -> String {
+    format!("mlp:state={}:actions={}", STATE_FEATURES, BIT_DEPTHS.len())
+}
+
+// Actor and critic weights go in the tensors; the rollout in progress is not saved
+impl PolicyCheckpoint for PPOPolicy {
+    fn save_checkpoint(&self) -> Result<Vec<u8>, CandleError> {
+        let header = CheckpointHeader::new("ppo", &ppo_schema(), &self.config)?;
+        let mut tensors = self.actor.named_tensors("actor");
+        tensors.extend(self.critic.named_tensors("critic"));
+        write_safetensors(
+            header,
+            tensors,
+            &PPOState {
+                current_depths: self.current_depths.clone(),
+                expert_stats: self.expert_stats.clone(),
+            },
+        )
+    }
+
+    fn load_checkpoint(&mut self, data: &[u8]) -> Result<(), CandleError> {
+        let (header, tensors, state): (_, _, PPOState) = read_safetensors(data, "ppo", &ppo_schema())?;
+        let config: PPOConfig = header.hyperparameters()?;
+        // Rebuild in case the checkpoint was trained with another hidden size; fresh optimizers
+        // also drop Adam moments that belonged to the previous weights
+        let (actor, critic) = Self::networks(&config, &mut self.rng.borrow_mut())?;
+        actor.load_named_tensors("actor", &tensors)?;
+        critic.load_named_tensors("critic", &tensors)?;
+        self.actor_optimizer = Adam::new(actor.vars(), config.learning_rate)?;
+        self.critic_optimizer = Adam::new(critic.vars(), config.learning_rate)?;
+        self.actor = actor;
+        self.critic = critic;
+        self.config = config;
+        self.current_depths = state.current_depths;
+        self.expert_stats = state.expert_stats;
+        self.rollout.clear();
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PPOStateInput {
+    expert_id: String,
+    #[serde(default)]
+    current_bit_depth: Option<u8>, // 4, 8 or 16; falls back to the depth last seen in a trace
+    hardware_class: String,
+    #[serde(default)]
+    hardware_profile: Option<HardwareProfile>, // e.g. from `detect_hardware_profile`; overrides the class
+}
+
+thread_local! {
+    static PPO_REWARD: SharedReward = WeightedSumReward::from_lambdas(0.1, 0.05).shared();
+
+    // Built on first use, so a `set_global_seed` issued before then seeds its weights too
+    static PPO_POLICY: RefCell<Option<PPOPolicy>> = const { RefCell::new(None) };
+}
+
+// Only used through `select_bit_depth`, so it needs no experts behind its gate
+fn new_shared_ppo() -> Result<PPOPolicy, CandleError> {
+    let router = Tensor::zeros((1, 0), candle_core::DType::F32, &Device::Cpu)?;
+    PPOPolicy::new(
+        Rc::new(SoftmaxTopKGate::new(Vec::new(), router, 0)?),
+        ExpertRegistry::new(1, true).shared(),
+        PPO_REWARD.with(|r| r.clone()),
+    )
+}
+
+// Run `f` on the shared PPO policy behind the `*_ppo_policy` exports
+pub(crate) fn with_shared_ppo<R>(f: impl FnOnce(&mut PPOPolicy) -> R) -> Result<R, JsValue> {
+    PPO_POLICY.with(|p| {
+        let mut slot = p.borrow_mut();
+        let policy = match &mut *slot {
+            Some(policy) => policy,
+            None => slot.insert(new_shared_ppo().map_err(|e| JsValue::from_str(&e.to_string()))?),
+        };
+        Ok(f(policy))
+    })
+}
+
+// Sampling of the shared PPO policy restarts from `seed`; see `rng::set_global_seed`. A policy
+// that is not built yet draws from the global seed when it is.
+pub(crate) fn reseed_ppo_policy(seed: u64) {
+    PPO_POLICY.with(|p| {
+        if let Some(policy) = p.borrow_mut().as_mut() {
+            policy.set_rng(seeded_rng(seed));
+        }
+    });
+}
+
+// Entry point for the TS `invokeRustPPOPolicy`: takes a JSON `RLState`, returns the sampled bit
+// width as a TS `BitDepth` (4, 8 or 16)
+#[wasm_bindgen]
+pub fn invoke_ppo_policy(state: &str) -> Result<u8, JsValue> {
+    let state: PPOStateInput = serde_json::from_str(state).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let current_depth = match state.current_bit_depth {
+        Some(bits) => Some(
+            BitDepth::from_bits(bits)
+                .ok_or_else(|| JsValue::from_str(&format!("Unsupported current bit depth: {}", bits)))?,
+        ),
+        None => None,
+    };
+    let hardware = state
+        .hardware_profile
+        .unwrap_or_else(|| HardwareProfile::new(&state.hardware_class));
+    let expert_id = ExpertId(state.expert_id);
+    with_shared_ppo(|p| p.select_bit_depth_from(&expert_id, current_depth, &hardware).bits())
+}
+
+// Feed an observed `InferenceTrace` (JSON) to the shared PPO policy
+#[wasm_bindgen]
+pub fn update_ppo_policy(trace: &str) -> Result<(), JsValue> {
+    let trace: InferenceTrace = serde_json::from_str(trace).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    update_shared_ppo(trace)
+}
+
+pub(crate) fn update_shared_ppo(trace: InferenceTrace) -> Result<(), JsValue> {
+    PPO_REWARD.with(|r| r.borrow_mut().observe(&trace));
+    with_shared_ppo(|p| p.update_policy(trace))
+}
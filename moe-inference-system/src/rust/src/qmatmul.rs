@@ -0,0 +1,507 @@
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use wasm_bindgen::prelude::*;
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use std::arch::wasm32::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+// Instruction set used by the dot-product kernels on this machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KernelBackend {
+    Avx512,
+    Avx2,
+    Neon,
+    WasmSimd128,
+    Portable,
+}
+
+static BACKEND: OnceLock<KernelBackend> = OnceLock::new();
+
+pub fn detect_backend() -> KernelBackend {
+    *BACKEND.get_or_init(|| available_backends()[0])
+}
+
+// Row-major (rows, cols) INT8 matrix with one symmetric scale per row
+#[derive(Debug, Clone)]
+pub struct QuantizedMatrixI8 {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<i8>,
+    pub scales: Vec<f32>,
+}
+
+impl QuantizedMatrixI8 {
+    pub fn from_f32(values: &[f32], rows: usize, cols: usize) -> Result<Self, String> {
+        if cols == 0 {
+            return Err("INT8 matrix needs at least one column".to_string());
+        }
+        check_len("weights", values.len(), rows * cols)?;
+        let mut data = Vec::with_capacity(rows * cols);
+        let mut scales = Vec::with_capacity(rows);
+        for row in values.chunks(cols).take(rows) {
+            let (codes, scale) = quantize_row_i8(row);
+            data.extend(codes);
+            scales.push(scale);
+        }
+        Ok(QuantizedMatrixI8 { rows, cols, data, scales })
+    }
+}
+
+// Row-major (rows, cols) INT4 weight matrix, two codes per byte, one scale per
+// `group_size` consecutive weights of a row
+#[derive(Debug, Clone)]
+pub struct QuantizedMatrixI4 {
+    pub rows: usize,
+    pub cols: usize,
+    pub group_size: usize,
+    pub packed: Vec<u8>,
+    pub scales: Vec<f32>,
+}
+
+impl QuantizedMatrixI4 {
+    // `cols` must be a multiple of `group_size`, and `group_size` even
+    pub fn from_f32(values: &[f32], rows: usize, cols: usize, group_size: usize) -> Result<Self, String> {
+        if cols == 0 || group_size == 0 || !group_size.is_multiple_of(2) || !cols.is_multiple_of(group_size) {
+            return Err(format!(
+                "INT4 group size must be even, non-zero and divide the {} columns, got {}",
+                cols, group_size
+            ));
+        }
+        check_len("weights", values.len(), rows * cols)?;
+        let mut packed = Vec::with_capacity(rows * cols / 2);
+        let mut scales = Vec::with_capacity(rows * cols / group_size);
+        for group in values.chunks(group_size) {
+            let max_abs = group.iter().fold(0.0f32, |acc, v| acc.max(v.abs()));
+            let scale = if max_abs > 0.0 { max_abs / 7.0 } else { 1.0 };
+            scales.push(scale);
+            for pair in group.chunks(2) {
+                let lo = ((pair[0] / scale).round().clamp(-7.0, 7.0) as i8 + 8) as u8;
+                let hi = ((pair[1] / scale).round().clamp(-7.0, 7.0) as i8 + 8) as u8;
+                packed.push(lo | (hi << 4));
+            }
+        }
+        Ok(QuantizedMatrixI4 { rows, cols, group_size, packed, scales })
+    }
+
+    fn groups_per_row(&self) -> usize {
+        self.cols / self.group_size
+    }
+
+    // Packed codes of one group, two per byte
+    fn packed_group(&self, row: usize, group: usize) -> &[u8] {
+        let start = (row * self.cols + group * self.group_size) / 2;
+        &self.packed[start..start + self.group_size / 2]
+    }
+}
+
+fn check_len(what: &str, actual: usize, expected: usize) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!("Expected {} {} values, got {}", expected, what, actual));
+    }
+    Ok(())
+}
+
+// Dynamic symmetric INT8 quantization of one activation row
+pub fn quantize_row_i8(row: &[f32]) -> (Vec<i8>, f32) {
+    let max_abs = row.iter().fold(0.0f32, |acc, v| acc.max(v.abs()));
+    let scale = if max_abs > 0.0 { max_abs / 127.0 } else { 1.0 };
+    let codes = row.iter().map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8).collect();
+    (codes, scale)
+}
+
+// INT8 x INT8 -> INT32: out[i][j] = Σ_k a[i][k] * b[j][k] for a (m, k) and b (n, k)
+pub fn gemm_i8_i32(a: &[i8], b: &QuantizedMatrixI8, m: usize, out: &mut [i32]) -> Result<(), String> {
+    let backend = detect_backend();
+    let k = b.cols;
+    check_len("activation", a.len(), m * k)?;
+    check_len("output", out.len(), m * b.rows)?;
+    for i in 0..m {
+        let a_row = &a[i * k..(i + 1) * k];
+        for j in 0..b.rows {
+            out[i * b.rows + j] = dot_i8(backend, a_row, &b.data[j * k..(j + 1) * k]);
+        }
+    }
+    Ok(())
+}
+
+// f32 activations (m, k) times INT8 weights (n, k)^T, returning dequantized f32 (m, n).
+// Activations are quantized per row on the fly.
+pub fn matmul_i8(x: &[f32], weights: &QuantizedMatrixI8, m: usize) -> Result<Vec<f32>, String> {
+    let k = weights.cols;
+    check_len("activation", x.len(), m * k)?;
+    let mut codes = Vec::with_capacity(m * k);
+    let mut row_scales = Vec::with_capacity(m);
+    for row in x.chunks(k).take(m) {
+        let (row_codes, scale) = quantize_row_i8(row);
+        codes.extend(row_codes);
+        row_scales.push(scale);
+    }
+
+    let mut acc = vec![0i32; m * weights.rows];
+    gemm_i8_i32(&codes, weights, m, &mut acc)?;
+    Ok(acc
+        .iter()
+        .enumerate()
+        .map(|(idx, &v)| v as f32 * row_scales[idx / weights.rows] * weights.scales[idx % weights.rows])
+        .collect())
+}
+
+// INT4 weights (n, k) times f32 vector x (k). x is quantized to INT8 and each group is
+// dotted against the packed codes in integer: y[j] = s_x * Σ_g scale[j][g] * Σ_k code[j][k] * q[k]
+pub fn gemv_i4_f32(weights: &QuantizedMatrixI4, x: &[f32], y: &mut [f32]) -> Result<(), String> {
+    check_len("activation", x.len(), weights.cols)?;
+    check_len("output", y.len(), weights.rows)?;
+    let backend = detect_backend();
+    let (codes, x_scale) = quantize_row_i8(x);
+    let groups = weights.groups_per_row();
+    for (row, out) in y.iter_mut().enumerate() {
+        let mut acc = 0.0f32;
+        for group in 0..groups {
+            let start = group * weights.group_size;
+            let partial = dot_i4_i8(
+                backend,
+                weights.packed_group(row, group),
+                &codes[start..start + weights.group_size],
+            );
+            acc += partial as f32 * weights.scales[row * groups + group];
+        }
+        *out = acc * x_scale;
+    }
+    Ok(())
+}
+
+// f32 activations (m, k) times INT4 weights (n, k)^T -> f32 (m, n)
+pub fn matmul_i4_f32(x: &[f32], weights: &QuantizedMatrixI4, m: usize) -> Result<Vec<f32>, String> {
+    check_len("activation", x.len(), m * weights.cols)?;
+    let mut out = vec![0.0f32; m * weights.rows];
+    for (row, y) in x.chunks(weights.cols).zip(out.chunks_mut(weights.rows)) {
+        gemv_i4_f32(weights, row, y)?;
+    }
+    Ok(out)
+}
+
+enum StoredWeights {
+    Int4(QuantizedMatrixI4),
+    Int8(QuantizedMatrixI8),
+    Fp16 { rows: usize, cols: usize, values: Vec<f32> },
+}
+
+// A (rows, cols) weight matrix quantized once at `bit_depth` (4 or 8; 16 keeps the weights in
+// f32) and kept on the Rust side, so JS pays for quantization once per expert, not per matmul
+#[wasm_bindgen]
+pub struct QuantizedMatrix {
+    weights: StoredWeights,
+}
+
+#[wasm_bindgen]
+impl QuantizedMatrix {
+    // INT4 scales cover `group_size` weights; other depths ignore it
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        weights: Vec<f32>,
+        rows: usize,
+        cols: usize,
+        bit_depth: u8,
+        group_size: usize,
+    ) -> Result<QuantizedMatrix, JsValue> {
+        Self::build(weights, rows, cols, bit_depth, group_size).map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rows(&self) -> usize {
+        match &self.weights {
+            StoredWeights::Int4(w) => w.rows,
+            StoredWeights::Int8(w) => w.rows,
+            StoredWeights::Fp16 { rows, .. } => *rows,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cols(&self) -> usize {
+        match &self.weights {
+            StoredWeights::Int4(w) => w.cols,
+            StoredWeights::Int8(w) => w.cols,
+            StoredWeights::Fp16 { cols, .. } => *cols,
+        }
+    }
+
+    #[wasm_bindgen(getter, js_name = bitDepth)]
+    pub fn bit_depth(&self) -> u8 {
+        match &self.weights {
+            StoredWeights::Int4(_) => 4,
+            StoredWeights::Int8(_) => 8,
+            StoredWeights::Fp16 { .. } => 16,
+        }
+    }
+
+    // f32 activations (m, cols) times the stored weights, returning f32 (m, rows)
+    pub fn matmul(&self, x: Vec<f32>, m: usize) -> Result<Vec<f32>, JsValue> {
+        self.matmul_f32(&x, m).map_err(|e| JsValue::from_str(&e))
+    }
+}
+
+impl QuantizedMatrix {
+    pub fn build(weights: Vec<f32>, rows: usize, cols: usize, bit_depth: u8, group_size: usize) -> Result<Self, String> {
+        let weights = match bit_depth {
+            4 => StoredWeights::Int4(QuantizedMatrixI4::from_f32(&weights, rows, cols, group_size)?),
+            8 => StoredWeights::Int8(QuantizedMatrixI8::from_f32(&weights, rows, cols)?),
+            16 if cols == 0 => return Err("FP16 matrix needs at least one column".to_string()),
+            16 => {
+                check_len("weights", weights.len(), rows * cols)?;
+                StoredWeights::Fp16 { rows, cols, values: weights }
+            }
+            _ => return Err(format!("Unsupported bit depth: {}", bit_depth)),
+        };
+        Ok(QuantizedMatrix { weights })
+    }
+
+    pub fn matmul_f32(&self, x: &[f32], m: usize) -> Result<Vec<f32>, String> {
+        match &self.weights {
+            StoredWeights::Int4(w) => matmul_i4_f32(x, w, m),
+            StoredWeights::Int8(w) => matmul_i8(x, w, m),
+            StoredWeights::Fp16 { cols, values, .. } => {
+                check_len("activation", x.len(), m * cols)?;
+                let backend = detect_backend();
+                Ok(x.chunks(*cols)
+                    .flat_map(|x_row| values.chunks(*cols).map(|w_row| dot_f32(backend, x_row, w_row)))
+                    .collect())
+            }
+        }
+    }
+}
+
+// Backends this machine can run, fastest first; the portable kernels are always last
+pub(crate) fn available_backends() -> Vec<KernelBackend> {
+    let mut backends = Vec::new();
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
+            backends.push(KernelBackend::Avx512);
+        }
+        if is_x86_feature_detected!("avx2") {
+            backends.push(KernelBackend::Avx2);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            backends.push(KernelBackend::Neon);
+        }
+    }
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    backends.push(KernelBackend::WasmSimd128);
+    backends.push(KernelBackend::Portable);
+    backends
+}
+
+// Callers must pass a backend from `available_backends`
+pub(crate) fn dot_i8(backend: KernelBackend, a: &[i8], b: &[i8]) -> i32 {
+    assert_eq!(a.len(), b.len(), "dot product operands differ in length");
+    match backend {
+        #[cfg(target_arch = "x86_64")]
+        KernelBackend::Avx512 => unsafe { dot_i8_avx512(a, b) },
+        #[cfg(target_arch = "x86_64")]
+        KernelBackend::Avx2 => unsafe { dot_i8_avx2(a, b) },
+        #[cfg(target_arch = "aarch64")]
+        KernelBackend::Neon => unsafe { dot_i8_neon(a, b) },
+        #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+        KernelBackend::WasmSimd128 => dot_i8_simd128(a, b),
+        _ => dot_i8_portable(a, b),
+    }
+}
+
+pub(crate) fn dot_f32(backend: KernelBackend, a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len(), "dot product operands differ in length");
+    match backend {
+        #[cfg(target_arch = "x86_64")]
+        KernelBackend::Avx512 => unsafe { dot_f32_avx512(a, b) },
+        #[cfg(target_arch = "x86_64")]
+        KernelBackend::Avx2 => unsafe { dot_f32_avx2(a, b) },
+        #[cfg(target_arch = "aarch64")]
+        KernelBackend::Neon => unsafe { dot_f32_neon(a, b) },
+        #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+        KernelBackend::WasmSimd128 => dot_f32_simd128(a, b),
+        _ => dot_f32_portable(a, b),
+    }
+}
+
+// Σ_k (code[k] - 8) * x[k] over packed INT4 codes (low nibble first) and INT8 activations
+pub(crate) fn dot_i4_i8(backend: KernelBackend, packed: &[u8], x: &[i8]) -> i32 {
+    assert_eq!(packed.len() * 2, x.len(), "dot product operands differ in length");
+    match backend {
+        // Every AVX-512 machine also has AVX2, which the nibble kernel needs
+        #[cfg(target_arch = "x86_64")]
+        KernelBackend::Avx512 | KernelBackend::Avx2 => unsafe { dot_i4_i8_avx2(packed, x) },
+        _ => dot_i4_i8_portable(packed, x),
+    }
+}
+
+pub(crate) fn dot_i8_portable(a: &[i8], b: &[i8]) -> i32 {
+    a.iter().zip(b).map(|(&x, &y)| x as i32 * y as i32).sum()
+}
+
+pub(crate) fn dot_f32_portable(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub(crate) fn dot_i4_i8_portable(packed: &[u8], x: &[i8]) -> i32 {
+    packed
+        .iter()
+        .zip(x.chunks(2))
+        .map(|(&byte, pair)| {
+            ((byte & 0x0f) as i32 - 8) * pair[0] as i32 + ((byte >> 4) as i32 - 8) * pair[1] as i32
+        })
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_i8_avx2(a: &[i8], b: &[i8]) -> i32 {
+    let chunks = a.len() / 16;
+    let mut acc = _mm256_setzero_si256();
+    for c in 0..chunks {
+        let va = _mm256_cvtepi8_epi16(_mm_loadu_si128(a.as_ptr().add(c * 16) as *const __m128i));
+        let vb = _mm256_cvtepi8_epi16(_mm_loadu_si128(b.as_ptr().add(c * 16) as *const __m128i));
+        acc = _mm256_add_epi32(acc, _mm256_madd_epi16(va, vb));
+    }
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    lanes.iter().sum::<i32>() + dot_i8_portable(&a[chunks * 16..], &b[chunks * 16..])
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw")]
+unsafe fn dot_i8_avx512(a: &[i8], b: &[i8]) -> i32 {
+    let chunks = a.len() / 32;
+    let mut acc = _mm512_setzero_si512();
+    for c in 0..chunks {
+        let va = _mm512_cvtepi8_epi16(_mm256_loadu_si256(a.as_ptr().add(c * 32) as *const __m256i));
+        let vb = _mm512_cvtepi8_epi16(_mm256_loadu_si256(b.as_ptr().add(c * 32) as *const __m256i));
+        acc = _mm512_add_epi32(acc, _mm512_madd_epi16(va, vb));
+    }
+    _mm512_reduce_add_epi32(acc) + dot_i8_portable(&a[chunks * 32..], &b[chunks * 32..])
+}
+
+// 16 packed bytes (32 codes) per step. maddubs multiplies the unsigned codes by the signed
+// activations; subtracting 8 * x the same way recentres the codes. Pair sums stay within i16.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_i4_i8_avx2(packed: &[u8], x: &[i8]) -> i32 {
+    let chunks = packed.len() / 16;
+    let low_mask = _mm_set1_epi8(0x0f);
+    let eight = _mm256_set1_epi8(8);
+    let ones = _mm256_set1_epi16(1);
+    let mut acc = _mm256_setzero_si256();
+    for c in 0..chunks {
+        let bytes = _mm_loadu_si128(packed.as_ptr().add(c * 16) as *const __m128i);
+        let lo = _mm_and_si128(bytes, low_mask);
+        let hi = _mm_and_si128(_mm_srli_epi16(bytes, 4), low_mask);
+        let codes = _mm256_set_m128i(_mm_unpackhi_epi8(lo, hi), _mm_unpacklo_epi8(lo, hi));
+        let vx = _mm256_loadu_si256(x.as_ptr().add(c * 32) as *const __m256i);
+        let pairs = _mm256_sub_epi16(_mm256_maddubs_epi16(codes, vx), _mm256_maddubs_epi16(eight, vx));
+        acc = _mm256_add_epi32(acc, _mm256_madd_epi16(pairs, ones));
+    }
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    lanes.iter().sum::<i32>() + dot_i4_i8_portable(&packed[chunks * 16..], &x[chunks * 32..])
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_f32_avx2(a: &[f32], b: &[f32]) -> f32 {
+    let chunks = a.len() / 8;
+    let mut acc = _mm256_setzero_ps();
+    for c in 0..chunks {
+        let va = _mm256_loadu_ps(a.as_ptr().add(c * 8));
+        let vb = _mm256_loadu_ps(b.as_ptr().add(c * 8));
+        acc = _mm256_add_ps(acc, _mm256_mul_ps(va, vb));
+    }
+    let mut lanes = [0.0f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+    lanes.iter().sum::<f32>() + dot_f32_portable(&a[chunks * 8..], &b[chunks * 8..])
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn dot_f32_avx512(a: &[f32], b: &[f32]) -> f32 {
+    let chunks = a.len() / 16;
+    let mut acc = _mm512_setzero_ps();
+    for c in 0..chunks {
+        let va = _mm512_loadu_ps(a.as_ptr().add(c * 16));
+        let vb = _mm512_loadu_ps(b.as_ptr().add(c * 16));
+        acc = _mm512_fmadd_ps(va, vb, acc);
+    }
+    _mm512_reduce_add_ps(acc) + dot_f32_portable(&a[chunks * 16..], &b[chunks * 16..])
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_i8_neon(a: &[i8], b: &[i8]) -> i32 {
+    let chunks = a.len() / 16;
+    let mut acc = vdupq_n_s32(0);
+    for c in 0..chunks {
+        let va = vld1q_s8(a.as_ptr().add(c * 16));
+        let vb = vld1q_s8(b.as_ptr().add(c * 16));
+        acc = vpadalq_s16(acc, vmull_s8(vget_low_s8(va), vget_low_s8(vb)));
+        acc = vpadalq_s16(acc, vmull_high_s8(va, vb));
+    }
+    vaddvq_s32(acc) + dot_i8_portable(&a[chunks * 16..], &b[chunks * 16..])
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_f32_neon(a: &[f32], b: &[f32]) -> f32 {
+    let chunks = a.len() / 4;
+    let mut acc = vdupq_n_f32(0.0);
+    for c in 0..chunks {
+        acc = vfmaq_f32(acc, vld1q_f32(a.as_ptr().add(c * 4)), vld1q_f32(b.as_ptr().add(c * 4)));
+    }
+    vaddvq_f32(acc) + dot_f32_portable(&a[chunks * 4..], &b[chunks * 4..])
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn dot_i8_simd128(a: &[i8], b: &[i8]) -> i32 {
+    let chunks = a.len() / 16;
+    let mut acc = i32x4_splat(0);
+    for c in 0..chunks {
+        // SAFETY: c * 16 + 16 <= a.len() == b.len(); v128_load has no alignment requirement
+        let (va, vb) = unsafe {
+            (
+                v128_load(a.as_ptr().add(c * 16) as *const v128),
+                v128_load(b.as_ptr().add(c * 16) as *const v128),
+            )
+        };
+        acc = i32x4_add(acc, i32x4_dot_i16x8(i16x8_extend_low_i8x16(va), i16x8_extend_low_i8x16(vb)));
+        acc = i32x4_add(acc, i32x4_dot_i16x8(i16x8_extend_high_i8x16(va), i16x8_extend_high_i8x16(vb)));
+    }
+    i32x4_extract_lane::<0>(acc)
+        + i32x4_extract_lane::<1>(acc)
+        + i32x4_extract_lane::<2>(acc)
+        + i32x4_extract_lane::<3>(acc)
+        + dot_i8_portable(&a[chunks * 16..], &b[chunks * 16..])
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn dot_f32_simd128(a: &[f32], b: &[f32]) -> f32 {
+    let chunks = a.len() / 4;
+    let mut acc = f32x4_splat(0.0);
+    for c in 0..chunks {
+        // SAFETY: c * 4 + 4 <= a.len() == b.len(); v128_load has no alignment requirement
+        let (va, vb) = unsafe {
+            (
+                v128_load(a.as_ptr().add(c * 4) as *const v128),
+                v128_load(b.as_ptr().add(c * 4) as *const v128),
+            )
+        };
+        acc = f32x4_add(acc, f32x4_mul(va, vb));
+    }
+    f32x4_extract_lane::<0>(acc)
+        + f32x4_extract_lane::<1>(acc)
+        + f32x4_extract_lane::<2>(acc)
+        + f32x4_extract_lane::<3>(acc)
+        + dot_f32_portable(&a[chunks * 4..], &b[chunks * 4..])
+}
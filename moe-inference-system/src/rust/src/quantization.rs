@@ -1,13 +1,18 @@
 use wasm_bindgen::prelude::*;
-use candle_core::{Tensor, Device, DType, Error as CandleError, Module, Var};
+use candle_core::safetensors::Load;
+use candle_core::{Tensor, Device, Error as CandleError, Var};
 use safetensors::SafeTensors;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::rng::default_rng;
 use std::fs::File;
 use std::io::Read;
 
-// LoRA configuration from adapter_config.json
+// LoRA configuration from adapter_config.json; only the rank is used, the other fields
+// are kept so the file is validated as a whole
 #[derive(Deserialize)]
+#[allow(dead_code)]
 struct LoRAConfig {
     r: usize,  // LoRA rank
     task_type: String,
@@ -26,25 +31,103 @@ struct LoRACheckpoint {
     b_shape: (usize, usize),
 }
 
+// Rounding applied when mapping scaled weights onto the integer grid
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    Nearest,
+    Stochastic, // round up with probability equal to the fractional part
+}
+
+// How the per-block scale is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleMethod {
+    MaxAbs,     // raw block range
+    ClipSearch, // grid search over clip fractions of the block range
+    MseOptimal, // alternating least-squares refinement of scale and offset
+}
+
+// Error metric minimized by the clipping search
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipObjective {
+    Mse,
+    Percentile, // absolute error at `error_percentile`
+}
+
+// Optional block-loop settings, passed from JS as a JSON object
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QuantizationOptions {
+    pub rounding: RoundingMode,
+    pub seed: Option<u64>, // stochastic rounding stream; None takes the next `default_rng` stream
+    pub scale_method: ScaleMethod,
+    pub clip_objective: ClipObjective,
+    pub clip_grid_size: usize,
+    pub min_clip_ratio: f32,
+    pub error_percentile: f32,
+    pub mse_iterations: usize,
+}
+
+impl QuantizationOptions {
+    // Nearest rounding never draws, so it leaves the global streams to other components
+    pub fn rng(&self) -> StdRng {
+        match (self.seed, self.rounding) {
+            (Some(seed), _) => StdRng::seed_from_u64(seed),
+            (None, RoundingMode::Stochastic) => {
+                let shared = default_rng();
+                let rng = shared.borrow().clone();
+                rng
+            }
+            (None, RoundingMode::Nearest) => StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl Default for QuantizationOptions {
+    fn default() -> Self {
+        QuantizationOptions {
+            rounding: RoundingMode::Nearest,
+            seed: None,
+            scale_method: ScaleMethod::MaxAbs,
+            clip_objective: ClipObjective::Mse,
+            clip_grid_size: 20,
+            min_clip_ratio: 0.5,
+            error_percentile: 0.99,
+            mse_iterations: 10,
+        }
+    }
+}
+
+// Integer codes of one block (stored as f32) and the affine map back to weights:
+// w ≈ codes * scale + offset
+#[derive(Debug, Clone)]
+pub struct QuantizedBlock {
+    pub codes: Vec<f32>,
+    pub scale: f32,
+    pub offset: f32,
+}
+
 // Load adapter_config.json
 fn load_lora_config(path: &str) -> Result<LoRAConfig, CandleError> {
-    let mut file = File::open(path).map_err(|e| CandleError::Io(e))?;
+    let mut file = File::open(path).map_err(CandleError::Io)?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents).map_err(|e| CandleError::Io(e))?;
+    file.read_to_string(&mut contents).map_err(CandleError::Io)?;
     serde_json::from_str(&contents).map_err(|e| CandleError::Msg(e.to_string()))
 }
 
 // Load Safetensors checkpoint
 fn load_safetensors_checkpoint(path: &str, rows: usize, cols: usize, lora_rank: usize) -> Result<LoRACheckpoint, CandleError> {
-    let data = std::fs::read(path).map_err(|e| CandleError::Io(e))?;
+    let data = std::fs::read(path).map_err(CandleError::Io)?;
     let safetensors = SafeTensors::deserialize(&data).map_err(|e| CandleError::Msg(e.to_string()))?;
 
     // Assume LoRA weights are stored as "lora_A" and "lora_B" (adjust based on actual naming)
     let a_tensor = safetensors.tensor("lora_A").map_err(|e| CandleError::Msg(e.to_string()))?;
     let b_tensor = safetensors.tensor("lora_B").map_err(|e| CandleError::Msg(e.to_string()))?;
 
-    let a_data = a_tensor.data().to_vec();
-    let b_data = b_tensor.data().to_vec();
+    let a_data = a_tensor.load(&Device::Cpu)?.flatten_all()?.to_vec1::<f32>()?;
+    let b_data = b_tensor.load(&Device::Cpu)?.flatten_all()?.to_vec1::<f32>()?;
 
     Ok(LoRACheckpoint {
         a: a_data,
@@ -56,52 +139,217 @@ fn load_safetensors_checkpoint(path: &str, rows: usize, cols: usize, lora_rank:
 
 // Straight-through estimator for quantization
 fn straight_through_estimator(tensor: &Tensor, quantized: &Tensor) -> Result<Tensor, CandleError> {
-    tensor + (quantized - tensor).detach()?
+    tensor + (quantized - tensor)?.detach()?
+}
+
+// Smallest and largest integer code for a bit depth
+fn code_range(bit_depth: u8, symmetric: bool) -> (f32, f32) {
+    if symmetric {
+        let qmax = ((1 << (bit_depth - 1)) - 1) as f32;
+        (-qmax, qmax)
+    } else {
+        (0.0, ((1 << bit_depth) - 1) as f32)
+    }
+}
+
+fn round_code(value: f32, rounding: RoundingMode, rng: &mut StdRng) -> f32 {
+    match rounding {
+        RoundingMode::Nearest => value.round(),
+        RoundingMode::Stochastic => (value + rng.gen::<f32>()).floor(),
+    }
+}
+
+// Scale and offset covering the full block range. A zero-width range (all-zero or
+// constant blocks) gets a unit scale so that every weight maps to code 0 exactly.
+fn max_abs_params(block: &[f32], bit_depth: u8, symmetric: bool) -> (f32, f32) {
+    let (qmin, qmax) = code_range(bit_depth, symmetric);
+    let (scale, offset) = if symmetric {
+        let max_abs = block.iter().fold(0.0f32, |acc, v| acc.max(v.abs()));
+        (max_abs / qmax, 0.0)
+    } else {
+        let min = block.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = block.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        ((max - min) / (qmax - qmin), min)
+    };
+    if scale > 0.0 && scale.is_finite() {
+        (scale, offset)
+    } else {
+        (1.0, if offset.is_finite() { offset } else { 0.0 })
+    }
+}
+
+// Reconstruction error of round-to-nearest quantization with the given parameters
+fn reconstruction_error(
+    block: &[f32],
+    scale: f32,
+    offset: f32,
+    code_range: (f32, f32),
+    objective: ClipObjective,
+    percentile: f32,
+) -> f32 {
+    let (qmin, qmax) = code_range;
+    let mut errors: Vec<f32> = block
+        .iter()
+        .map(|&w| {
+            let code = ((w - offset) / scale).round().clamp(qmin, qmax);
+            (code * scale + offset - w).abs()
+        })
+        .collect();
+
+    match objective {
+        ClipObjective::Mse => errors.iter().map(|e| e * e).sum::<f32>() / errors.len() as f32,
+        ClipObjective::Percentile => {
+            errors.sort_by(|a, b| a.total_cmp(b));
+            let rank = ((errors.len() - 1) as f32 * percentile.clamp(0.0, 1.0)).round() as usize;
+            errors[rank]
+        }
+    }
+}
+
+// Grid search over clip fractions of the block range, keeping the lowest-error one
+fn clip_search_params(block: &[f32], bit_depth: u8, symmetric: bool, options: &QuantizationOptions) -> (f32, f32) {
+    let range = code_range(bit_depth, symmetric);
+    let (base_scale, base_offset) = max_abs_params(block, bit_depth, symmetric);
+    let steps = options.clip_grid_size.max(1);
+    let min_ratio = options.min_clip_ratio.clamp(0.0, 1.0);
+
+    let mut best = (base_scale, base_offset);
+    let mut best_error = f32::INFINITY;
+    for step in 0..=steps {
+        let ratio = 1.0 - (1.0 - min_ratio) * step as f32 / steps as f32;
+        let (scale, offset) = if symmetric {
+            (base_scale * ratio, 0.0)
+        } else {
+            // Shrink the range towards its centre
+            let half_width = base_scale * (range.1 - range.0) / 2.0;
+            let centre = base_offset + half_width;
+            (base_scale * ratio, centre - half_width * ratio)
+        };
+        let error = reconstruction_error(block, scale, offset, range, options.clip_objective, options.error_percentile);
+        if error < best_error {
+            best = (scale, offset);
+            best_error = error;
+        }
+    }
+    best
+}
+
+// Alternating minimization of the squared error: fix codes, solve the least-squares
+// scale (and offset), requantize, repeat
+fn mse_optimal_params(block: &[f32], bit_depth: u8, symmetric: bool, options: &QuantizationOptions) -> (f32, f32) {
+    let range = code_range(bit_depth, symmetric);
+    let (mut scale, mut offset) = max_abs_params(block, bit_depth, symmetric);
+    let mut best = (scale, offset);
+    let mut best_error = reconstruction_error(block, scale, offset, range, ClipObjective::Mse, 0.0);
+
+    for _ in 0..options.mse_iterations {
+        let codes: Vec<f32> = block
+            .iter()
+            .map(|&w| ((w - offset) / scale).round().clamp(range.0, range.1))
+            .collect();
+
+        if symmetric {
+            let qq: f32 = codes.iter().map(|q| q * q).sum();
+            if qq == 0.0 {
+                break;
+            }
+            scale = block.iter().zip(&codes).map(|(w, q)| w * q).sum::<f32>() / qq;
+        } else {
+            let n = block.len() as f32;
+            let mean_q = codes.iter().sum::<f32>() / n;
+            let mean_w = block.iter().sum::<f32>() / n;
+            let var_q: f32 = codes.iter().map(|q| (q - mean_q) * (q - mean_q)).sum();
+            if var_q == 0.0 {
+                break;
+            }
+            let cov: f32 = block.iter().zip(&codes).map(|(w, q)| (w - mean_w) * (q - mean_q)).sum();
+            scale = cov / var_q;
+            offset = mean_w - scale * mean_q;
+        }
+        if scale.is_nan() || scale <= 0.0 {
+            break;
+        }
+
+        let error = reconstruction_error(block, scale, offset, range, ClipObjective::Mse, 0.0);
+        if error < best_error {
+            best = (scale, offset);
+            best_error = error;
+        } else {
+            break;
+        }
+    }
+    best
+}
+
+// Quantize one block with the configured scale solver and rounding. Non-finite weights are
+// left out of the scale search; NaN quantizes as 0.0 and ±Inf saturates to the code range.
+pub(crate) fn quantize_block(
+    block: &[f32],
+    bit_depth: u8,
+    symmetric: bool,
+    options: &QuantizationOptions,
+    rng: &mut StdRng,
+) -> QuantizedBlock {
+    if block.is_empty() {
+        return QuantizedBlock { codes: Vec::new(), scale: 1.0, offset: 0.0 };
+    }
+
+    let finite: Vec<f32> = block.iter().map(|&w| if w.is_finite() { w } else { 0.0 }).collect();
+    let (scale, offset) = match options.scale_method {
+        ScaleMethod::MaxAbs => max_abs_params(&finite, bit_depth, symmetric),
+        ScaleMethod::ClipSearch => clip_search_params(&finite, bit_depth, symmetric, options),
+        ScaleMethod::MseOptimal => mse_optimal_params(&finite, bit_depth, symmetric, options),
+    };
+    let (qmin, qmax) = code_range(bit_depth, symmetric);
+    let codes = block
+        .iter()
+        .map(|&w| {
+            let w = if w.is_nan() { 0.0 } else { w };
+            round_code((w - offset) / scale, options.rounding, rng).clamp(qmin, qmax)
+        })
+        .collect();
+
+    QuantizedBlock { codes, scale, offset }
 }
 
-// Helper function for GPTQ quantization (unchanged)
+// Map a block's codes back to weights
+pub(crate) fn dequantize_block(block: &QuantizedBlock) -> Vec<f32> {
+    block.codes.iter().map(|q| q * block.scale + block.offset).collect()
+}
+
+// Helper function for GPTQ quantization
 fn gptq_quantize(
     tensor: &Tensor,
     bit_depth: u8,
     block_size: usize,
-    symmetric: bool
+    symmetric: bool,
+    options: &QuantizationOptions,
+    rng: &mut StdRng,
 ) -> Result<Tensor, CandleError> {
-    let dtype = match bit_depth {
-        4 => DType::QInt4,
-        8 => DType::QInt8,
-        _ => return Err(CandleError::UnsupportedDType),
-    };
+    if bit_depth != 4 && bit_depth != 8 {
+        return Err(CandleError::Msg(format!("Unsupported bit depth: {}", bit_depth)));
+    }
 
     let shape = tensor.shape();
-    let block_count = (shape[0] + block_size - 1) / block_size;
+    let rows = tensor.dims()[0];
+    let block_count = rows.div_ceil(block_size);
     let mut quantized = Vec::new();
 
     for i in 0..block_count {
         let start = i * block_size;
-        let end = std::cmp::min(start + block_size, shape[0]);
+        let end = std::cmp::min(start + block_size, rows);
         let block = tensor.narrow(0, start, end - start)?;
-        
-        let scale = if symmetric {
-            block.abs()?.max(0)?.to_scalar::<f32>()? / ((1 << (bit_depth - 1)) - 1) as f32
-        } else {
-            let min = block.min(0)?.to_scalar::<f32>()?;
-            let max = block.max(0)?.to_scalar::<f32>()?;
-            (max - min) / ((1 << bit_depth) - 1) as f32
-        };
+        let values = block.flatten_all()?.to_vec1::<f32>()?;
 
-        let quantized_block = if symmetric {
-            (block / scale)?.to_dtype(dtype)?
-        } else {
-            let min = block.min(0)?.to_scalar::<f32>()?;
-            ((block - min) / scale)?.to_dtype(dtype)?
-        };
-        quantized.extend_from_slice(&quantized_block.to_vec1::<f32>()?);
+        let quantized_block = quantize_block(&values, bit_depth, symmetric, options, rng);
+        quantized.extend_from_slice(&quantized_block.codes);
     }
 
     Tensor::from_vec(quantized, shape, &Device::Cpu)
 }
 
 // Helper function for QLoRA quantization with QAT
+#[allow(clippy::too_many_arguments)]
 fn qlora_quantize(
     tensor: &Tensor,
     bit_depth: u8,
@@ -110,17 +358,17 @@ fn qlora_quantize(
     lora_rank: usize,
     lora_checkpoint: &str,  // Path to adapter_model.safetensors
     qat_enabled: bool,
-    learning_rate: f32
+    learning_rate: f32,
+    options: &QuantizationOptions,
+    rng: &mut StdRng,
 ) -> Result<Tensor, CandleError> {
-    let dtype = match bit_depth {
-        4 => DType::QInt4,
-        8 => DType::QInt8,
-        _ => return Err(CandleError::UnsupportedDType),
-    };
+    if bit_depth != 4 && bit_depth != 8 {
+        return Err(CandleError::Msg(format!("Unsupported bit depth: {}", bit_depth)));
+    }
 
     let shape = tensor.shape();
-    let [rows, cols] = shape.as_slice() else {
-        return Err(CandleError::ShapeMismatch(format!("Expected 2D tensor, got {:?}", shape)));
+    let [rows, cols] = shape.dims() else {
+        return Err(CandleError::Msg(format!("Expected 2D tensor, got {:?}", shape)));
     };
 
     // Load LoRA config from adapter_config.json
@@ -136,7 +384,7 @@ fn qlora_quantize(
     // Load pretrained LoRA matrices from Safetensors
     let checkpoint = load_safetensors_checkpoint(lora_checkpoint, *rows, *cols, lora_rank)?;
     if checkpoint.a_shape != (*rows, lora_rank) || checkpoint.b_shape != (lora_rank, *cols) {
-        return Err(CandleError::ShapeMismatch(format!(
+        return Err(CandleError::Msg(format!(
             "LoRA checkpoint shapes mismatch: A={:?}, B={:?}, expected ({}, {}), ({}, {})",
             checkpoint.a_shape, checkpoint.b_shape, rows, lora_rank, lora_rank, cols
         )));
@@ -149,79 +397,59 @@ fn qlora_quantize(
     // Compute LoRA update: ΔW = A * B
     let a = a_var.as_tensor();
     let b = b_var.as_tensor();
-    let delta_w = a.matmul(&b)?;
+    let delta_w = a.matmul(b)?;
 
     // Apply LoRA update: W' = W + ΔW
     let updated_tensor = tensor.add(&delta_w)?;
 
     // Quantize block-wise
-    let block_count = (rows + block_size - 1) / block_size;
+    let block_count = rows.div_ceil(block_size);
     let mut quantized = Vec::new();
 
     for i in 0..block_count {
         let start = i * block_size;
         let end = std::cmp::min(start + block_size, *rows);
         let block = updated_tensor.narrow(0, start, end - start)?;
+        let values = block.flatten_all()?.to_vec1::<f32>()?;
 
-        let scale = if symmetric {
-            block.abs()?.max(0)?.to_scalar::<f32>()? / ((1 << (bit_depth - 1)) - 1) as f32
-        } else {
-            let min = block.min(0)?.to_scalar::<f32>()?;
-            let max = block.max(0)?.to_scalar::<f32>()?;
-            (max - min) / ((1 << bit_depth) - 1) as f32
-        };
-
-        let quantized_block = if symmetric {
-            (block / scale)?.to_dtype(dtype)?
-        } else {
-            let min = block.min(0)?.to_scalar::<f32>()?;
-            ((block - min) / scale)?.to_dtype(dtype)?
-        };
+        let codes = quantize_block(&values, bit_depth, symmetric, options, rng).codes;
+        let quantized_block = Tensor::from_vec(codes, block.shape(), &Device::Cpu)?;
 
         if qat_enabled {
             // Apply straight-through estimator for QAT
             let q_block = straight_through_estimator(&block, &quantized_block)?;
-            quantized.extend_from_slice(&q_block.to_vec1::<f32>()?);
+            quantized.extend_from_slice(&q_block.flatten_all()?.to_vec1::<f32>()?);
 
             // Mock gradient update (simplified for WebAssembly)
             let loss = q_block.sqr()?.mean_all()?; // Mock loss
             let grad = loss.backward()?;
             if let Some(a_grad) = grad.get(&a_var) {
-                let update = a_grad.mul(&learning_rate)?;
-                a_var.sub_assign(&update)?;
+                let update = a_grad.affine(learning_rate as f64, 0.0)?;
+                a_var.set(&a_var.as_tensor().sub(&update)?)?;
             }
             if let Some(b_grad) = grad.get(&b_var) {
-                let update = b_grad.mul(&learning_rate)?;
-                b_var.sub_assign(&update)?;
+                let update = b_grad.affine(learning_rate as f64, 0.0)?;
+                b_var.set(&b_var.as_tensor().sub(&update)?)?;
             }
         } else {
-            quantized.extend_from_slice(&quantized_block.to_vec1::<f32>()?);
+            quantized.extend_from_slice(&quantized_block.flatten_all()?.to_vec1::<f32>()?);
         }
     }
 
     // Serialize updated LoRA matrices back to Safetensors (for QAT persistence)
-    let updated_checkpoint = LoRACheckpoint {
-        a: a_var.as_tensor().to_vec1::<f32>()?,
-        b: b_var.as_tensor().to_vec1::<f32>()?,
-        a_shape: checkpoint.a_shape,
-        b_shape: checkpoint.b_shape,
-    };
-    let mut safetensors_data = Vec::new();
-    safetensors::serialize(
-        &[
-            ("lora_A", updated_checkpoint.a.as_slice(), updated_checkpoint.a_shape),
-            ("lora_B", updated_checkpoint.b.as_slice(), updated_checkpoint.b_shape),
-        ],
-        &mut safetensors_data,
+    let _safetensors_data = safetensors::serialize(
+        [("lora_A", a_var.as_tensor()), ("lora_B", b_var.as_tensor())],
+        &None,
     )
     .map_err(|e| CandleError::Msg(e.to_string()))?;
     // Note: Writing to file in WebAssembly requires WASI; mock for now
-    // std::fs::write(lora_checkpoint, safetensors_data).map_err(|e| CandleError::Io(e))?;
+    // std::fs::write(lora_checkpoint, _safetensors_data).map_err(CandleError::Io)?;
 
     Tensor::from_vec(quantized, shape, &Device::Cpu)
 }
 
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn quantize_batch(
     weights: Vec<f32>,
     batch_size: usize,
@@ -233,12 +461,47 @@ pub fn quantize_batch(
     lora_checkpoint: &str,  // Path to adapter_model.safetensors
     qat_enabled: bool,
     learning_rate: f32
+) -> Result<Vec<f32>, JsValue> {
+    quantize_batch_with_options(
+        weights,
+        batch_size,
+        bit_depth,
+        technique,
+        block_size,
+        mode,
+        lora_rank,
+        lora_checkpoint,
+        qat_enabled,
+        learning_rate,
+        "{}",
+    )
+}
+
+// Same as quantize_batch, with rounding and scale search controlled by a JSON
+// `QuantizationOptions` object, e.g. {"rounding":"stochastic","seed":7,"scale_method":"clip_search"}
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn quantize_batch_with_options(
+    weights: Vec<f32>,
+    batch_size: usize,
+    bit_depth: u8,
+    technique: &str,
+    block_size: usize,
+    mode: &str,
+    lora_rank: usize,
+    lora_checkpoint: &str,  // Path to adapter_model.safetensors
+    qat_enabled: bool,
+    learning_rate: f32,
+    options: &str,
 ) -> Result<Vec<f32>, JsValue> {
     let device = Device::Cpu;
     let symmetric = mode == "symmetric";
+    let options: QuantizationOptions = serde_json::from_str(options)
+        .map_err(|e| JsValue::from_str(&format!("Invalid quantization options: {}", e)))?;
+    let mut rng = options.rng();
 
     // Validate inputs
-    if weights.len() % batch_size != 0 {
+    if !weights.len().is_multiple_of(batch_size) {
         return Err(JsValue::from_str("Weights length must be divisible by batch_size"));
     }
     let weights_per_batch = weights.len() / batch_size;
@@ -255,15 +518,20 @@ pub fn quantize_batch(
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         let quantized = match technique {
-            "gptq" => gptq_quantize(&tensor, bit_depth, block_size, symmetric),
-            "qlora" => qlora_quantize(&tensor, bit_depth, block_size, symmetric, lora_rank, lora_checkpoint, qat_enabled, learning_rate),
-            "awq" => gptq_quantize(&tensor, bit_depth, block_size, symmetric),
-            "qat" => qlora_quantize(&tensor, bit_depth, block_size, symmetric, lora_rank, lora_checkpoint, true, learning_rate),
+            "gptq" => gptq_quantize(&tensor, bit_depth, block_size, symmetric, &options, &mut rng),
+            "qlora" => qlora_quantize(&tensor, bit_depth, block_size, symmetric, lora_rank, lora_checkpoint, qat_enabled, learning_rate, &options, &mut rng),
+            "awq" => gptq_quantize(&tensor, bit_depth, block_size, symmetric, &options, &mut rng),
+            "qat" => qlora_quantize(&tensor, bit_depth, block_size, symmetric, lora_rank, lora_checkpoint, true, learning_rate, &options, &mut rng),
             _ => return Err(JsValue::from_str(&format!("Unsupported technique: {}", technique))),
         }
         .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
-        result.extend_from_slice(&quantized.to_vec1::<f32>().map_err(|e| JsValue::from_str(&e.to_string()))?);
+        result.extend_from_slice(
+            &quantized
+                .flatten_all()
+                .and_then(|t| t.to_vec1::<f32>())
+                .map_err(|e| JsValue::from_str(&e.to_string()))?,
+        );
     }
 
     Ok(result)
@@ -0,0 +1,374 @@
+use crate::hardware::HardwareClass;
+use crate::trace_buffer::InferenceTrace;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// One reward model shared by every policy, so they all optimize the same objective
+pub type SharedReward = Rc<RefCell<dyn RewardModel>>;
+
+// Scores an inference outcome. `observe` feeds running statistics (e.g. normalization) and
+// must be called once per trace by whoever owns the shared model, before the policies see it.
+pub trait RewardModel {
+    fn reward(&self, trace: &InferenceTrace) -> f32;
+    fn observe(&mut self, _trace: &InferenceTrace) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RewardMetric {
+    Accuracy,
+    Latency,
+    TokenLoss,
+    Memory,
+    Energy,
+    ModelSize,
+}
+
+const METRICS: usize = 6;
+
+impl RewardMetric {
+    fn index(&self) -> usize {
+        match self {
+            RewardMetric::Accuracy => 0,
+            RewardMetric::Latency => 1,
+            RewardMetric::TokenLoss => 2,
+            RewardMetric::Memory => 3,
+            RewardMetric::Energy => 4,
+            RewardMetric::ModelSize => 5,
+        }
+    }
+}
+
+// Raw metric values of a trace, indexed by `RewardMetric::index`.
+// Model size is the expert's storage relative to FP16 (INT4 = 0.25).
+fn metrics(trace: &InferenceTrace) -> [f32; METRICS] {
+    [
+        trace.accuracy,
+        trace.latency,
+        trace.token_loss,
+        trace.memory_usage,
+        trace.energy_usage,
+        trace.bit_depth.bits() as f32 / 16.0,
+    ]
+}
+
+// Weight of each objective: accuracy is rewarded, every other metric is a cost
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RewardWeights {
+    pub accuracy: f32,
+    pub latency: f32,
+    pub token_loss: f32,
+    pub memory: f32,
+    pub energy: f32,
+    pub model_size: f32,
+}
+
+impl Default for RewardWeights {
+    fn default() -> Self {
+        RewardWeights {
+            accuracy: 1.0,
+            latency: 0.1,
+            token_loss: 0.05,
+            memory: 0.0,
+            energy: 0.0,
+            model_size: 0.0,
+        }
+    }
+}
+
+impl RewardWeights {
+    fn signed(&self) -> [f32; METRICS] {
+        [
+            self.accuracy,
+            -self.latency,
+            -self.token_loss,
+            -self.memory,
+            -self.energy,
+            -self.model_size,
+        ]
+    }
+}
+
+// Hard bound on a raw metric; every violated bound subtracts `penalty` from the reward
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewardConstraint {
+    pub metric: RewardMetric,
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+    #[serde(default = "default_penalty")]
+    pub penalty: f32,
+}
+
+fn default_penalty() -> f32 {
+    10.0
+}
+
+impl RewardConstraint {
+    fn violated(&self, value: f32) -> bool {
+        self.min.is_some_and(|min| value < min) || self.max.is_some_and(|max| value > max)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RewardConfig {
+    pub weights: RewardWeights,
+    pub normalize: bool, // standardize each metric by its running mean/std before weighting
+    pub constraints: Vec<RewardConstraint>,
+}
+
+// Welford running mean and variance of one metric
+#[derive(Debug, Clone, Copy, Default)]
+struct RunningStat {
+    count: u64,
+    mean: f32,
+    m2: f32,
+}
+
+impl RunningStat {
+    fn push(&mut self, value: f32) {
+        if !value.is_finite() {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Metrics are left unscaled until two samples have been seen
+    fn standardize(&self, value: f32) -> f32 {
+        if self.count < 2 {
+            return value;
+        }
+        let std = (self.m2 / (self.count - 1) as f32).sqrt().max(1e-6);
+        (value - self.mean) / std
+    }
+}
+
+// reward = Σ wᵢ·metricᵢ (costs negated) - Σ penalties of violated constraints
+pub struct WeightedSumReward {
+    config: RewardConfig,
+    stats: [RunningStat; METRICS],
+}
+
+impl WeightedSumReward {
+    pub fn new(config: RewardConfig) -> Self {
+        WeightedSumReward {
+            config,
+            stats: [RunningStat::default(); METRICS],
+        }
+    }
+
+    // The previous fixed objective: accuracy - λ1·latency - λ2·token_loss
+    pub fn from_lambdas(lambda1: f32, lambda2: f32) -> Self {
+        Self::new(RewardConfig {
+            weights: RewardWeights {
+                latency: lambda1,
+                token_loss: lambda2,
+                ..RewardWeights::default()
+            },
+            ..RewardConfig::default()
+        })
+    }
+
+    pub fn shared(self) -> SharedReward {
+        Rc::new(RefCell::new(self))
+    }
+
+    pub fn config(&self) -> &RewardConfig {
+        &self.config
+    }
+}
+
+impl RewardModel for WeightedSumReward {
+    fn reward(&self, trace: &InferenceTrace) -> f32 {
+        let raw = metrics(trace);
+        let weighted: f32 = self
+            .config
+            .weights
+            .signed()
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let value = if self.config.normalize { self.stats[i].standardize(raw[i]) } else { raw[i] };
+                w * value
+            })
+            .sum();
+        let penalty: f32 = self
+            .config
+            .constraints
+            .iter()
+            .filter(|c| c.violated(raw[c.metric.index()]))
+            .map(|c| c.penalty)
+            .sum();
+        weighted - penalty
+    }
+
+    fn observe(&mut self, trace: &InferenceTrace) {
+        for (stat, value) in self.stats.iter_mut().zip(metrics(trace)) {
+            stat.push(value);
+        }
+    }
+}
+
+// Latency SLO per hardware class: the `percentile` latency over a sliding window must stay within budget
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LatencySloConfig {
+    pub budgets: HashMap<String, f32>, // hardware class ("cpu", "gpu", ...) -> latency budget
+    pub default_budget: Option<f32>,   // for classes without their own budget; None leaves them unconstrained
+    pub percentile: f32,
+    pub window: usize,
+    pub dual_learning_rate: f32,
+    pub initial_multiplier: f32,
+    pub max_multiplier: f32,
+}
+
+impl Default for LatencySloConfig {
+    fn default() -> Self {
+        LatencySloConfig {
+            budgets: HashMap::new(),
+            default_budget: None,
+            percentile: 0.95,
+            window: 200,
+            dual_learning_rate: 0.05,
+            initial_multiplier: 0.0,
+            max_multiplier: 100.0,
+        }
+    }
+}
+
+// Constraint state of one hardware class
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SloStatus {
+    pub hardware_class: String,
+    pub budget: f32,
+    pub multiplier: f32,
+    pub observed_percentile: f32,
+    pub violation_rate: f32, // fraction of windowed traces over budget
+}
+
+#[derive(Debug, Clone, Default)]
+struct SloState {
+    latencies: VecDeque<f32>,
+    multiplier: f32,
+    observed: f32,
+}
+
+// Lagrangian relaxation of the latency SLO on top of a base reward, per `HardwareClass`:
+// reward = base - λ·(latency - budget) / budget, with the dual ascent step
+// λ ← clamp(λ + η·(p_latency - budget) / budget, 0, max) after every observed trace
+pub struct LatencySloReward {
+    base: SharedReward,
+    config: LatencySloConfig,
+    states: HashMap<String, SloState>,
+}
+
+impl LatencySloReward {
+    pub fn new(base: SharedReward, mut config: LatencySloConfig) -> Self {
+        config.budgets = config.budgets.into_iter().map(|(t, b)| (class_key(&t).to_string(), b)).collect();
+        LatencySloReward {
+            base,
+            config,
+            states: HashMap::new(),
+        }
+    }
+
+    // Lookups take any hardware type and resolve it to its class, as budgets and state are per class
+    pub fn budget(&self, hardware_class: &str) -> Option<f32> {
+        self.config
+            .budgets
+            .get(class_key(hardware_class))
+            .copied()
+            .or(self.config.default_budget)
+            .filter(|b| *b > 0.0)
+    }
+
+    pub fn multiplier(&self, hardware_class: &str) -> f32 {
+        self.states
+            .get(class_key(hardware_class))
+            .map_or(self.config.initial_multiplier, |s| s.multiplier)
+    }
+
+    pub fn status(&self, hardware_class: &str) -> Option<SloStatus> {
+        let budget = self.budget(hardware_class)?;
+        let state = self.states.get(class_key(hardware_class))?;
+        let violations = state.latencies.iter().filter(|&&l| l > budget).count();
+        Some(SloStatus {
+            hardware_class: class_key(hardware_class).to_string(),
+            budget,
+            multiplier: state.multiplier,
+            observed_percentile: state.observed,
+            violation_rate: violations as f32 / state.latencies.len().max(1) as f32,
+        })
+    }
+
+    // Status of every constrained hardware class seen so far
+    pub fn statuses(&self) -> Vec<SloStatus> {
+        let mut classes: Vec<&String> = self.states.keys().collect();
+        classes.sort();
+        classes.into_iter().filter_map(|c| self.status(c)).collect()
+    }
+}
+
+fn class_key(hardware_type: &str) -> &'static str {
+    HardwareClass::from_type(hardware_type).as_str()
+}
+
+fn percentile(values: &VecDeque<f32>, q: f32) -> f32 {
+    let mut sorted: Vec<f32> = values.iter().copied().collect();
+    sorted.sort_by(f32::total_cmp);
+    let rank = (q.clamp(0.0, 1.0) * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl RewardModel for LatencySloReward {
+    fn reward(&self, trace: &InferenceTrace) -> f32 {
+        let base = self.base.borrow().reward(trace);
+        let hardware_class = trace.hardware_profile.class().as_str();
+        match self.budget(hardware_class) {
+            Some(budget) => base - self.multiplier(hardware_class) * (trace.latency - budget) / budget,
+            None => base,
+        }
+    }
+
+    fn observe(&mut self, trace: &InferenceTrace) {
+        self.base.borrow_mut().observe(trace);
+        let hardware_class = trace.hardware_profile.class().as_str();
+        let Some(budget) = self.budget(hardware_class) else {
+            return;
+        };
+        if !trace.latency.is_finite() {
+            return;
+        }
+        let initial = self.config.initial_multiplier;
+        let state = self.states.entry(hardware_class.to_string()).or_insert_with(|| SloState {
+            multiplier: initial,
+            ..SloState::default()
+        });
+        state.latencies.push_back(trace.latency);
+        while state.latencies.len() > self.config.window.max(1) {
+            state.latencies.pop_front();
+        }
+        state.observed = percentile(&state.latencies, self.config.percentile);
+        let step = self.config.dual_learning_rate * (state.observed - budget) / budget;
+        state.multiplier = (state.multiplier + step).clamp(0.0, self.config.max_multiplier);
+    }
+}
+
+// Score a JSON `InferenceTrace` under a JSON `RewardConfig`, without normalization history
+#[wasm_bindgen]
+pub fn compute_reward(trace: &str, config: &str) -> Result<f32, JsValue> {
+    let trace: InferenceTrace = serde_json::from_str(trace).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let config: RewardConfig = serde_json::from_str(config).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(WeightedSumReward::new(config).reward(&trace))
+}
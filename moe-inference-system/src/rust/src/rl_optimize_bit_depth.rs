@@ -1,17 +1,423 @@
-use crate::policy_engine::{BitPrecisionPolicy, QLearningPolicy, PPOPolicy, QuantizationDecision, ExpertId, BitDepth, HardwareProfile};
+use crate::bandit::{BanditConfig, LinUcbPolicy, ThompsonSamplingPolicy};
+use crate::dqn::DqnPolicy;
+use crate::policy_engine::{update_shared_ppo, with_shared_ppo, bit_depth_index, BitDepth, BitPrecisionPolicy, QLearningPolicy, PPOPolicy, QuantizationDecision, HardwareProfile, BIT_DEPTHS, NUM_BIT_DEPTHS};
 use crate::trace_buffer::InferenceTrace;
-use candle_core::Tensor;
+use crate::expert_registry::{ExpertRegistry, SharedRegistry};
+use crate::checkpoint::{checkpoint_policy, PolicyCheckpoint};
+use crate::drift::{DriftAlarm, DriftConfig};
+use crate::exploration::ExplorationConfig;
+use crate::cost_model::{CostModel, SharedCostModel};
+use crate::gating::{GatingFunction, SoftmaxTopKGate};
+use crate::guardrails::{GuardrailConfig, Guardrails};
+use crate::reward::{LatencySloConfig, LatencySloReward, SharedReward, SloStatus, WeightedSumReward};
+use crate::rng::{default_rng, SharedRng};
+use candle_core::{Device, Error as CandleError, Tensor};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyKind {
+    QLearning,
+    Ppo,
+    LinUcb,
+    Thompson,
+    Dqn,
+}
+
+// How the primary and shadow policies turn their proposals into the optimizer's decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingMode {
+    Primary,  // the primary acts; shadows only learn and are scored
+    Majority, // plurality of every member's proposal, ties going to the primary's
+    Average,  // sample from the members' mean bit-depth distribution
+    Weighted, // as `Average`, with members weighted by their estimated reward
+}
+
+// Policies the optimizer runs. Only these learn from traces; the others stay as they are.
+// Only the primary by default: every shadow trains on every trace, so they are opt-in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PolicySetup {
+    pub primary: PolicyKind,
+    pub shadows: Vec<PolicyKind>, // updated and scored on every trace, acting only through votes
+    pub voting: VotingMode,
+}
+
+impl Default for PolicySetup {
+    fn default() -> Self {
+        PolicySetup {
+            primary: PolicyKind::QLearning,
+            shadows: Vec::new(),
+            voting: VotingMode::Primary,
+        }
+    }
+}
+
+// How one policy fared on the traces the optimizer has seen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyReport {
+    pub policy: PolicyKind,
+    pub primary: bool,
+    pub decisions: u64,
+    pub agreement: f32,                // share of its proposals matching the optimizer's choice
+    pub estimated_reward: Option<f32>, // self-normalized IPS over the scored traces
+    pub scored: u64,                   // traces with a known logging propensity
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnsembleReport {
+    pub voting: VotingMode,
+    pub logged_reward: Option<f32>, // mean reward actually obtained on the scored traces
+    pub scored: u64,
+    pub policies: Vec<PolicyReport>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PolicyScore {
+    decisions: u64,
+    agreements: u64,
+    scored: u64,
+    weight: f32,          // Σ π(a|x) / μ(a|x)
+    weighted_reward: f32, // Σ π(a|x) / μ(a|x) · r
+}
+
+// Depth the optimizer set for an expert, with its probability under the acting distribution and
+// under each member, credited when the expert's next trace reports the reward
+struct PendingDecision {
+    bit_depth: BitDepth,
+    propensity: f32,
+    probabilities: Vec<(PolicyKind, f32)>,
+}
 
 pub struct RLOptimizer {
+    registry: SharedRegistry,
+    reward: SharedReward,
+    slo: Option<Rc<RefCell<LatencySloReward>>>, // set in latency-SLO mode, where it is also `reward`
     q_learning: QLearningPolicy,
     ppo: PPOPolicy,
+    lin_ucb: LinUcbPolicy,
+    thompson: ThompsonSamplingPolicy,
+    dqn: DqnPolicy,
+    setup: PolicySetup,
+    scores: HashMap<PolicyKind, PolicyScore>,
+    logged: (f32, u64), // reward sum and count over scored traces
+    pending: HashMap<String, PendingDecision>,
+    guardrails: Option<Guardrails>,
+    rng: SharedRng, // shared by every stochastic component, so one seed replays a run
+    cost_model: SharedCostModel, // refined from every trace
 }
 
 impl RLOptimizer {
-    pub fn new(lambda1: f32, lambda2: f32) -> Self {
-        RLOptimizer {
-            q_learning: QLearningPolicy::new(lambda1, lambda2, 0.1),
-            ppo: PPOPolicy::new(lambda1, lambda2),
+    pub fn new(gate: Rc<dyn GatingFunction>, registry: SharedRegistry, reward: SharedReward) -> Result<Self, CandleError> {
+        Self::with_bandit_config(gate, registry, reward, BanditConfig::default())
+    }
+
+    pub fn with_bandit_config(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        bandit_config: BanditConfig,
+    ) -> Result<Self, CandleError> {
+        Self::build(gate, registry, reward, bandit_config, None)
+    }
+
+    // Constrained mode: maximize `reward` subject to a latency SLO per hardware class, with the
+    // latency multiplier learned online instead of fixed
+    pub fn with_latency_slo(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        slo: LatencySloConfig,
+    ) -> Result<Self, CandleError> {
+        let slo = Rc::new(RefCell::new(LatencySloReward::new(reward, slo)));
+        Self::build(gate, registry, slo.clone(), BanditConfig::default(), Some(slo))
+    }
+
+    fn build(
+        gate: Rc<dyn GatingFunction>,
+        registry: SharedRegistry,
+        reward: SharedReward,
+        bandit_config: BanditConfig,
+        slo: Option<Rc<RefCell<LatencySloReward>>>,
+    ) -> Result<Self, CandleError> {
+        let mut optimizer = RLOptimizer {
+            q_learning: QLearningPolicy::new(gate.clone(), registry.clone(), reward.clone(), 0.1),
+            ppo: PPOPolicy::new(gate.clone(), registry.clone(), reward.clone())?,
+            lin_ucb: LinUcbPolicy::with_config(gate.clone(), registry.clone(), reward.clone(), bandit_config.clone()),
+            thompson: ThompsonSamplingPolicy::with_config(gate.clone(), registry.clone(), reward.clone(), bandit_config),
+            dqn: DqnPolicy::new(gate, registry.clone(), reward.clone())?,
+            registry,
+            reward,
+            slo,
+            setup: PolicySetup::default(),
+            scores: HashMap::new(),
+            logged: (0.0, 0),
+            pending: HashMap::new(),
+            guardrails: None,
+            rng: default_rng(),
+            cost_model: CostModel::default().shared(),
+        };
+        optimizer.set_rng(optimizer.rng.clone());
+        Ok(optimizer)
+    }
+
+    pub fn registry(&self) -> &SharedRegistry {
+        &self.registry
+    }
+
+    pub fn reward(&self) -> &SharedReward {
+        &self.reward
+    }
+
+    // Current Lagrange multiplier on latency for a hardware class (None outside latency-SLO mode)
+    pub fn latency_multiplier(&self, hardware_class: &str) -> Option<f32> {
+        self.slo.as_ref().map(|slo| slo.borrow().multiplier(hardware_class))
+    }
+
+    pub fn cost_model(&self) -> &SharedCostModel {
+        &self.cost_model
+    }
+
+    // Plan with (and keep refining) a calibrated or shared cost model instead of the default priors
+    pub fn set_cost_model(&mut self, cost_model: SharedCostModel) {
+        self.cost_model = cost_model;
+    }
+
+    pub fn slo_status(&self, hardware_class: &str) -> Option<SloStatus> {
+        self.slo.as_ref().and_then(|slo| slo.borrow().status(hardware_class))
+    }
+
+    pub fn slo_statuses(&self) -> Vec<SloStatus> {
+        self.slo.as_ref().map_or_else(Vec::new, |slo| slo.borrow().statuses())
+    }
+
+    // Draw exploration, sampling and guardrail randomness from `rng`, e.g. `rng::seeded_rng(seed)`
+    // to replay a reported run
+    pub fn set_rng(&mut self, rng: SharedRng) {
+        self.q_learning.set_rng(rng.clone());
+        self.ppo.set_rng(rng.clone());
+        self.thompson.set_rng(rng.clone());
+        self.dqn.set_rng(rng.clone());
+        if let Some(guardrails) = &mut self.guardrails {
+            guardrails.set_rng(rng.clone());
+        }
+        self.rng = rng;
+    }
+
+    // How Q-learning explores the bit-depth ladder
+    pub fn set_exploration(&mut self, config: &ExplorationConfig) {
+        self.q_learning.set_exploration(config.build());
+    }
+
+    // Re-explore Q-learning experts whose reward shifts, e.g. after a model update or traffic change
+    pub fn set_drift_detection(&mut self, config: DriftConfig) {
+        self.q_learning.set_drift_detection(config);
+    }
+
+    pub fn clear_drift_detection(&mut self) {
+        self.q_learning.clear_drift_detection();
+    }
+
+    pub fn drift_history(&self) -> &[DriftAlarm] {
+        self.q_learning.drift_history()
+    }
+
+    // Make `kind` the primary in place of the current one, keeping the other shadows; use
+    // `set_policy_setup` to keep the previous primary learning as a shadow
+    pub fn set_policy(&mut self, kind: PolicyKind) {
+        self.setup.shadows.retain(|k| *k != kind);
+        self.setup.primary = kind;
+    }
+
+    pub fn policy(&self) -> PolicyKind {
+        self.setup.primary
+    }
+
+    pub fn set_policy_setup(&mut self, mut setup: PolicySetup) {
+        let mut seen = vec![setup.primary];
+        setup.shadows.retain(|k| {
+            let new = !seen.contains(k);
+            seen.push(*k);
+            new
+        });
+        self.setup = setup;
+        self.reset_policy_scores();
+    }
+
+    pub fn policy_setup(&self) -> &PolicySetup {
+        &self.setup
+    }
+
+    // Primary first, then shadows
+    fn members(&self) -> Vec<PolicyKind> {
+        std::iter::once(self.setup.primary).chain(self.setup.shadows.iter().copied()).collect()
+    }
+
+    pub fn policy_report(&self) -> EnsembleReport {
+        let policies = self
+            .members()
+            .into_iter()
+            .map(|kind| {
+                let score = self.scores.get(&kind).copied().unwrap_or_default();
+                PolicyReport {
+                    policy: kind,
+                    primary: kind == self.setup.primary,
+                    decisions: score.decisions,
+                    agreement: score.agreements as f32 / score.decisions.max(1) as f32,
+                    estimated_reward: (score.weight > 0.0).then(|| score.weighted_reward / score.weight),
+                    scored: score.scored,
+                }
+            })
+            .collect();
+        EnsembleReport {
+            voting: self.setup.voting,
+            logged_reward: (self.logged.1 > 0).then(|| self.logged.0 / self.logged.1 as f32),
+            scored: self.logged.1,
+            policies,
+        }
+    }
+
+    pub fn reset_policy_scores(&mut self) {
+        self.scores.clear();
+        self.logged = (0.0, 0);
+        self.pending.clear();
+    }
+
+    // Pass every decision of the optimizer through the given guardrails
+    pub fn set_guardrails(&mut self, config: GuardrailConfig) {
+        let mut guardrails = Guardrails::new(self.registry.clone(), config);
+        guardrails.set_rng(self.rng.clone());
+        self.guardrails = Some(guardrails);
+    }
+
+    pub fn clear_guardrails(&mut self) {
+        self.guardrails = None;
+    }
+
+    pub fn guardrails(&self) -> Option<&Guardrails> {
+        self.guardrails.as_ref()
+    }
+
+    fn checkpointable(&mut self, kind: PolicyKind) -> &mut dyn PolicyCheckpoint {
+        match kind {
+            PolicyKind::QLearning => &mut self.q_learning,
+            PolicyKind::Ppo => &mut self.ppo,
+            PolicyKind::LinUcb => &mut self.lin_ucb,
+            PolicyKind::Thompson => &mut self.thompson,
+            PolicyKind::Dqn => &mut self.dqn,
+        }
+    }
+
+    pub fn save_policy(&mut self, kind: PolicyKind) -> Result<Vec<u8>, CandleError> {
+        self.checkpointable(kind).save_checkpoint()
+    }
+
+    // Warm start one policy from a checkpoint; it keeps learning from subsequent traces
+    pub fn load_policy(&mut self, kind: PolicyKind, data: &[u8]) -> Result<(), CandleError> {
+        self.checkpointable(kind).load_checkpoint(data)
+    }
+
+    // Depth one member would run a trace's expert at, drawn from its own distribution
+    pub fn sample_policy(&self, kind: PolicyKind, trace: &InferenceTrace) -> BitDepth {
+        let probabilities = self.member(kind).bit_depth_probabilities(trace);
+        BIT_DEPTHS[sample_index(&probabilities, self.rng.borrow_mut().gen::<f32>())]
+    }
+
+    // Train one member on a trace outside `optimize_bit_depth`, e.g. one it chose through
+    // `sample_policy`; the shared reward observes the trace first
+    pub fn update_member(&mut self, kind: PolicyKind, trace: InferenceTrace) {
+        self.reward.borrow_mut().observe(&trace);
+        self.member_mut(kind).update_policy(trace);
+    }
+
+    fn member(&self, kind: PolicyKind) -> &dyn BitPrecisionPolicy {
+        match kind {
+            PolicyKind::QLearning => &self.q_learning,
+            PolicyKind::Ppo => &self.ppo,
+            PolicyKind::LinUcb => &self.lin_ucb,
+            PolicyKind::Thompson => &self.thompson,
+            PolicyKind::Dqn => &self.dqn,
+        }
+    }
+
+    fn member_mut(&mut self, kind: PolicyKind) -> &mut dyn BitPrecisionPolicy {
+        match kind {
+            PolicyKind::QLearning => &mut self.q_learning,
+            PolicyKind::Ppo => &mut self.ppo,
+            PolicyKind::LinUcb => &mut self.lin_ucb,
+            PolicyKind::Thompson => &mut self.thompson,
+            PolicyKind::Dqn => &mut self.dqn,
+        }
+    }
+
+    // Distribution the optimizer draws its proposal from for a trace, to log as its propensity
+    pub fn bit_depth_probabilities(&self, trace: &InferenceTrace) -> [f32; NUM_BIT_DEPTHS] {
+        let members = self.members();
+        let distributions: Vec<[f32; NUM_BIT_DEPTHS]> = members.iter().map(|k| self.member(*k).bit_depth_probabilities(trace)).collect();
+        self.acting_distribution(&members, &distributions)
+    }
+
+    fn acting_distribution(&self, members: &[PolicyKind], distributions: &[[f32; NUM_BIT_DEPTHS]]) -> [f32; NUM_BIT_DEPTHS] {
+        match self.setup.voting {
+            VotingMode::Primary => distributions[0],
+            VotingMode::Majority => majority_distribution(distributions),
+            VotingMode::Average => mixture(distributions, &vec![1.0; members.len()]),
+            VotingMode::Weighted => mixture(distributions, &self.vote_weights(members)),
+        }
+    }
+
+    // Estimated reward of each member; unscored members get the mean of the scored ones
+    fn vote_weights(&self, members: &[PolicyKind]) -> Vec<f32> {
+        let estimates: Vec<Option<f32>> = members
+            .iter()
+            .map(|k| {
+                let score = self.scores.get(k).copied().unwrap_or_default();
+                (score.weight > 0.0).then(|| (score.weighted_reward / score.weight).max(0.0) + 1e-3)
+            })
+            .collect();
+        let known: Vec<f32> = estimates.iter().flatten().copied().collect();
+        let fallback = if known.is_empty() { 1.0 } else { known.iter().sum::<f32>() / known.len() as f32 };
+        estimates.into_iter().map(|e| e.unwrap_or(fallback)).collect()
+    }
+
+    // Off-policy credit for the trace's reward: against its logged propensity when it carries
+    // one, otherwise against the decision the optimizer itself made for the expert
+    fn score_trace(&mut self, trace: &InferenceTrace) {
+        let pending = self.pending.remove(&trace.expert_id.0);
+        let reward = self.reward.borrow().reward(trace);
+        if !reward.is_finite() {
+            return;
+        }
+        let (propensity, probabilities) = match trace.propensity.filter(|p| *p > 0.0 && *p <= 1.0) {
+            Some(propensity) => {
+                let action = bit_depth_index(trace.bit_depth);
+                let probabilities = self
+                    .members()
+                    .into_iter()
+                    .map(|k| (k, self.member(k).bit_depth_probabilities(trace)[action]))
+                    .collect();
+                (propensity, probabilities)
+            }
+            None => match pending {
+                Some(p) if p.bit_depth == trace.bit_depth && p.propensity > 0.0 => (p.propensity, p.probabilities),
+                _ => return,
+            },
+        };
+        self.logged = (self.logged.0 + reward, self.logged.1 + 1);
+        for (kind, probability) in probabilities {
+            let score = self.scores.entry(kind).or_default();
+            let weight = probability / propensity;
+            score.scored += 1;
+            score.weight += weight;
+            score.weighted_reward += weight * reward;
         }
     }
 
@@ -20,21 +426,290 @@ impl RLOptimizer {
         trace: InferenceTrace,
         input_tensor: &Tensor,
         hardware_profile: &HardwareProfile,
-    ) -> QuantizationDecision {
-        // Update both policies
-        self.q_learning.update_policy(trace.clone());
-        self.ppo.update_policy(trace.clone());
-
-        // Select action using Q-learning (or switch to PPO based on config)
-        let experts = self.q_learning.select_experts(input_tensor, hardware_profile);
-        experts
-            .into_iter()
-            .find(|(id, _)| id.0 == trace.expert_id.0)
-            .map(|(_, bit_depth)| match (trace.bit_depth, bit_depth) {
-                (BitDepth::INT4, BitDepth::INT8) | (BitDepth::INT4, BitDepth::FP16) => QuantizationDecision::Up,
-                (BitDepth::INT8, BitDepth::INT4) | (BitDepth::FP16, BitDepth::INT4) => QuantizationDecision::Down,
-                _ => QuantizationDecision::Hold,
+    ) -> Result<QuantizationDecision, CandleError> {
+        self.registry.borrow_mut().record_usage(&trace);
+        self.reward.borrow_mut().observe(&trace);
+        let num_params = self.registry.borrow().get(&trace.expert_id).map(|e| e.num_params());
+        if let Some(num_params) = num_params {
+            self.cost_model.borrow_mut().observe(&trace, num_params);
+        }
+
+        // Score every member before it learns from the trace, then update them all so they are
+        // compared on the same traffic
+        self.score_trace(&trace);
+        let members = self.members();
+        for kind in &members {
+            self.member_mut(*kind).update_policy(trace.clone());
+        }
+        if let Some(guardrails) = &self.guardrails {
+            guardrails.observe(&trace);
+        }
+
+        let proposals: Vec<Option<BitDepth>> = members
+            .iter()
+            .map(|k| {
+                self.member(*k)
+                    .select_experts(input_tensor, hardware_profile)
+                    .into_iter()
+                    .find(|(id, _)| id.0 == trace.expert_id.0)
+                    .map(|(_, bit_depth)| bit_depth)
             })
-            .unwrap_or(QuantizationDecision::Hold)
+            .collect();
+        let distributions: Vec<[f32; NUM_BIT_DEPTHS]> =
+            members.iter().map(|k| self.member(*k).bit_depth_probabilities(&trace)).collect();
+        let chosen = match self.setup.voting {
+            VotingMode::Primary => proposals[0],
+            VotingMode::Majority => proposals[0].map(|primary| majority_vote(primary, &proposals)),
+            VotingMode::Average | VotingMode::Weighted => proposals[0].map(|_| {
+                let distribution = self.acting_distribution(&members, &distributions);
+                BIT_DEPTHS[sample_index(&distribution, self.rng.borrow_mut().gen::<f32>())]
+            }),
+        };
+        for (kind, proposal) in members.iter().zip(&proposals) {
+            if let (Some(proposal), Some(chosen)) = (proposal, chosen) {
+                let score = self.scores.entry(*kind).or_default();
+                score.decisions += 1;
+                score.agreements += u64::from(proposal == &chosen);
+            }
+        }
+
+        let planned = chosen.map(|proposed| {
+            let planned = self.plan_within_slo(proposed, num_params, &trace, hardware_profile);
+            let checked = match &self.guardrails {
+                Some(guardrails) => guardrails.check(&trace.expert_id, planned),
+                None => planned,
+            };
+            (proposed, checked)
+        });
+        // Only depths drawn from the acting distribution have a known propensity
+        match planned {
+            Some((proposed, bit_depth)) if proposed == bit_depth => {
+                let action = bit_depth_index(bit_depth);
+                let pending = PendingDecision {
+                    bit_depth,
+                    propensity: self.acting_distribution(&members, &distributions)[action],
+                    probabilities: members.iter().zip(&distributions).map(|(k, d)| (*k, d[action])).collect(),
+                };
+                self.pending.insert(trace.expert_id.0.clone(), pending);
+            }
+            _ => {
+                self.pending.remove(&trace.expert_id.0);
+            }
+        }
+        // Moves are relative to the depth the registry holds the expert at, which the trace ran at
+        // unless something requantized it since. A depth the SLO or guardrails imposed in place of
+        // the policy's proposal is absolute, so it is returned as `Set`.
+        let current = self.registry.borrow().bit_depth(&trace.expert_id).unwrap_or(trace.bit_depth);
+        let decision = match planned {
+            Some((_, bit_depth)) if bit_depth == current => QuantizationDecision::Hold,
+            Some((proposed, bit_depth)) if proposed != bit_depth => QuantizationDecision::Set(bit_depth),
+            Some((_, bit_depth)) => QuantizationDecision::between(current, bit_depth),
+            None => QuantizationDecision::Hold,
+        };
+
+        // Requantize the expert in place so the next inference runs at the new depth
+        if decision != QuantizationDecision::Hold {
+            self.registry
+                .borrow_mut()
+                .requantize(&trace.expert_id, decision.apply(current))
+                .map_err(CandleError::Msg)?;
+        }
+        Ok(decision)
     }
-}
\ No newline at end of file
+
+    // In latency-SLO mode, step a proposal down to the highest depth whose predicted latency fits the
+    // budget. Only depths the cost model has measured are trusted; the proposal stands otherwise.
+    fn plan_within_slo(
+        &self,
+        proposed: BitDepth,
+        num_params: Option<usize>,
+        trace: &InferenceTrace,
+        hardware_profile: &HardwareProfile,
+    ) -> BitDepth {
+        let (Some(slo), Some(num_params)) = (&self.slo, num_params) else {
+            return proposed;
+        };
+        let class = hardware_profile.class();
+        let Some(budget) = slo.borrow().budget(class.as_str()) else {
+            return proposed;
+        };
+        let model = self.cost_model.borrow();
+        let fits = |d: BitDepth| {
+            !model.is_fitted(class, d) || model.predict(num_params, trace.input_size, d, hardware_profile).latency <= budget
+        };
+        if fits(proposed) {
+            return proposed;
+        }
+        BIT_DEPTHS
+            .iter()
+            .rev()
+            .copied()
+            .filter(|d| d.bits() < proposed.bits() && model.is_fitted(class, *d))
+            .find(|d| fits(*d))
+            .unwrap_or(BitDepth::INT4)
+    }
+}
+
+// Weighted mean of the members' distributions
+fn mixture(distributions: &[[f32; NUM_BIT_DEPTHS]], weights: &[f32]) -> [f32; NUM_BIT_DEPTHS] {
+    let total: f32 = weights.iter().sum::<f32>().max(f32::MIN_POSITIVE);
+    std::array::from_fn(|a| distributions.iter().zip(weights).map(|(d, w)| d[a] * w).sum::<f32>() / total)
+}
+
+fn sample_index(probabilities: &[f32; NUM_BIT_DEPTHS], u: f32) -> usize {
+    let mut u = u * probabilities.iter().sum::<f32>();
+    for (i, p) in probabilities.iter().enumerate() {
+        if u < *p {
+            return i;
+        }
+        u -= p;
+    }
+    probabilities.len() - 1
+}
+
+// Most proposed depth; ties go to the primary's proposal if it is among them, else the lowest depth
+fn plurality(primary: usize, votes: &[usize]) -> usize {
+    let mut counts = [0usize; NUM_BIT_DEPTHS];
+    for v in votes {
+        counts[*v] += 1;
+    }
+    plurality_of_counts(primary, &counts)
+}
+
+fn plurality_of_counts(primary: usize, counts: &[usize; NUM_BIT_DEPTHS]) -> usize {
+    let most = counts.iter().copied().max().unwrap_or(0);
+    if counts[primary] == most {
+        primary
+    } else {
+        counts.iter().position(|c| *c == most).unwrap_or(primary)
+    }
+}
+
+fn majority_vote(primary: BitDepth, proposals: &[Option<BitDepth>]) -> BitDepth {
+    let votes: Vec<usize> = proposals.iter().flatten().map(|d| bit_depth_index(*d)).collect();
+    BIT_DEPTHS[plurality(bit_depth_index(primary), &votes)]
+}
+
+// Distribution of the plurality outcome when every member samples its own proposal (the first
+// distribution is the primary's). The outcome only depends on the primary's vote and the vote
+// counts, so the shadows are folded into a distribution over counts instead of enumerating
+// every joint vote.
+pub(crate) fn majority_distribution(distributions: &[[f32; NUM_BIT_DEPTHS]]) -> [f32; NUM_BIT_DEPTHS] {
+    let mut result = [0.0f32; NUM_BIT_DEPTHS];
+    let Some((primary, shadows)) = distributions.split_first() else {
+        return result;
+    };
+    let mut counts: HashMap<[usize; NUM_BIT_DEPTHS], f32> = HashMap::from([([0; NUM_BIT_DEPTHS], 1.0)]);
+    for distribution in shadows {
+        let mut next = HashMap::with_capacity(counts.len() * 2);
+        for (tally, probability) in &counts {
+            for (vote, p) in distribution.iter().enumerate().filter(|(_, p)| **p > 0.0) {
+                let mut tally = *tally;
+                tally[vote] += 1;
+                *next.entry(tally).or_insert(0.0) += probability * p;
+            }
+        }
+        counts = next;
+    }
+    for (vote, p) in primary.iter().enumerate().filter(|(_, p)| **p > 0.0) {
+        for (tally, probability) in &counts {
+            let mut tally = *tally;
+            tally[vote] += 1;
+            result[plurality_of_counts(vote, &tally)] += p * probability;
+        }
+    }
+    result
+}
+
+thread_local! {
+    // Holds the policies behind the policy-tagged wasm exports; built on first use
+    static SHARED_OPTIMIZER: RefCell<Option<RLOptimizer>> = const { RefCell::new(None) };
+}
+
+// Like the shared PPO policy, the shared optimizer is only queried per trace, so it needs no
+// experts behind its gate
+fn new_shared_optimizer() -> Result<RLOptimizer, CandleError> {
+    let router = Tensor::zeros((1, 0), candle_core::DType::F32, &Device::Cpu)?;
+    RLOptimizer::new(
+        Rc::new(SoftmaxTopKGate::new(Vec::new(), router, 0)?),
+        ExpertRegistry::new(1, true).shared(),
+        WeightedSumReward::from_lambdas(0.1, 0.05).shared(),
+    )
+}
+
+fn with_shared_optimizer<R>(f: impl FnOnce(&mut RLOptimizer) -> R) -> Result<R, JsValue> {
+    SHARED_OPTIMIZER.with(|o| {
+        let mut slot = o.borrow_mut();
+        let optimizer = match &mut *slot {
+            Some(optimizer) => optimizer,
+            None => slot.insert(new_shared_optimizer().map_err(|e| JsValue::from_str(&e.to_string()))?),
+        };
+        Ok(f(optimizer))
+    })
+}
+
+fn parse_policy_kind(policy: &str) -> Result<PolicyKind, JsValue> {
+    serde_json::from_value(serde_json::Value::String(policy.to_string()))
+        .map_err(|_| JsValue::from_str(&format!("Unknown policy: {}", policy)))
+}
+
+// Warm start the policy a checkpoint was saved from (Q-table, bandit, DQN or PPO; for
+// `train_offline_policy` output pass the bytes of its `checkpoint` JSON). Returns the policy name.
+// PPO checkpoints go to the shared policy behind `invoke_ppo_policy`. A loaded policy keeps
+// learning through `policy_select_bit_depth` and `policy_update`.
+#[wasm_bindgen]
+pub fn load_policy(data: Vec<u8>) -> Result<String, JsValue> {
+    let policy = checkpoint_policy(&data).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let result = match parse_policy_kind(&policy)? {
+        PolicyKind::Ppo => with_shared_ppo(|p| p.load_checkpoint(&data))?,
+        kind => with_shared_optimizer(|o| o.load_policy(kind, &data))?,
+    };
+    result.map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(policy)
+}
+
+// Checkpoint of one shared policy by name ("q_learning", "ppo", "lin_ucb", "thompson", "dqn")
+#[wasm_bindgen]
+pub fn save_policy(policy: &str) -> Result<Vec<u8>, JsValue> {
+    let result = match parse_policy_kind(policy)? {
+        PolicyKind::Ppo => with_shared_ppo(|p| p.save_checkpoint())?,
+        kind => with_shared_optimizer(|o| o.save_policy(kind))?,
+    };
+    result.map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+// INT4/INT8/FP16 probabilities a shared policy assigns to a JSON `InferenceTrace`'s expert
+#[wasm_bindgen]
+pub fn policy_bit_depth_probabilities(policy: &str, trace: &str) -> Result<Vec<f32>, JsValue> {
+    let trace: InferenceTrace = serde_json::from_str(trace).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let probabilities = match parse_policy_kind(policy)? {
+        PolicyKind::Ppo => with_shared_ppo(|p| p.bit_depth_probabilities(&trace))?,
+        kind => with_shared_optimizer(|o| o.member(kind).bit_depth_probabilities(&trace))?,
+    };
+    Ok(probabilities.to_vec())
+}
+
+// Bit width (4, 8 or 16) a shared policy picks for a JSON `InferenceTrace`'s expert, which the
+// trace reports running at its `bit_depth`
+#[wasm_bindgen]
+pub fn policy_select_bit_depth(policy: &str, trace: &str) -> Result<u8, JsValue> {
+    let trace: InferenceTrace = serde_json::from_str(trace).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let bit_depth = match parse_policy_kind(policy)? {
+        PolicyKind::Ppo => with_shared_ppo(|p| {
+            p.select_bit_depth_from(&trace.expert_id, Some(trace.bit_depth), &trace.hardware_profile)
+        })?,
+        kind => with_shared_optimizer(|o| o.sample_policy(kind, &trace))?,
+    };
+    Ok(bit_depth.bits())
+}
+
+// Feed the observed outcome of a decision (JSON `InferenceTrace`) to a shared policy
+#[wasm_bindgen]
+pub fn policy_update(policy: &str, trace: &str) -> Result<(), JsValue> {
+    let trace: InferenceTrace = serde_json::from_str(trace).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    match parse_policy_kind(policy)? {
+        PolicyKind::Ppo => update_shared_ppo(trace)?,
+        kind => with_shared_optimizer(|o| o.update_member(kind, trace))?,
+    }
+    Ok(())
+}
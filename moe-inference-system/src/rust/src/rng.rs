@@ -0,0 +1,64 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+// Random source of a stochastic component; sharing one between components lets a single
+// seed replay all of their draws
+pub type SharedRng = Rc<RefCell<StdRng>>;
+
+thread_local! {
+    static GLOBAL_SEED: Cell<Option<u64>> = const { Cell::new(None) };
+    static NEXT_STREAM: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn seeded_rng(seed: u64) -> SharedRng {
+    Rc::new(RefCell::new(StdRng::seed_from_u64(seed)))
+}
+
+// SplitMix64 finalizer, so consecutive streams of one seed start far apart
+fn mix(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// RNG for a newly built component: the next stream of the global seed if one is set, so
+// components created in the same order get the same draws; OS entropy otherwise
+pub fn default_rng() -> SharedRng {
+    match GLOBAL_SEED.with(|s| s.get()) {
+        Some(seed) => {
+            let stream = NEXT_STREAM.with(|n| n.replace(n.get() + 1));
+            seeded_rng(mix(seed, stream))
+        }
+        None => Rc::new(RefCell::new(StdRng::from_entropy())),
+    }
+}
+
+pub fn global_seed() -> Option<u64> {
+    GLOBAL_SEED.with(|s| s.get())
+}
+
+// Box-Muller
+pub fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f32 {
+    let u1 = rng.gen::<f32>().max(f32::MIN_POSITIVE);
+    let u2 = rng.gen::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+// Make every component created from now on deterministic, and reseed the sampling of the
+// JS-facing PPO policy. The seed is set first, so a policy built by that call is seeded too.
+#[wasm_bindgen]
+pub fn set_global_seed(seed: u64) {
+    GLOBAL_SEED.with(|s| s.set(Some(seed)));
+    NEXT_STREAM.with(|n| n.set(0));
+    crate::policy_engine::reseed_ppo_policy(seed);
+}
+
+#[wasm_bindgen]
+pub fn clear_global_seed() {
+    GLOBAL_SEED.with(|s| s.set(None));
+    NEXT_STREAM.with(|n| n.set(0));
+}
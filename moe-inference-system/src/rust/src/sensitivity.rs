@@ -0,0 +1,248 @@
+use crate::policy_engine::{BitDepth, BIT_DEPTHS};
+use crate::quantization::{dequantize_block, quantize_block, QuantizationOptions};
+use candle_core::{DType, Device, Error as CandleError, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+// Estimated loss increase of one tensor at each candidate bit depth
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TensorSensitivity {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub num_params: usize,
+    pub loss_impact: Vec<(BitDepth, f32)>,
+}
+
+// Bit depth assigned to one tensor; `bits` is what quantize_batch takes as `bit_depth`
+// (16 means the tensor stays in FP16 and is not passed to the quantizer)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TensorPlan {
+    pub name: String,
+    pub bit_depth: BitDepth,
+    pub bits: u8,
+    pub num_params: usize,
+    pub estimated_loss: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixedPrecisionPlan {
+    pub tensors: Vec<TensorPlan>,
+    pub target_bits: f32,
+    pub average_bits: f32,
+    pub total_estimated_loss: f32,
+}
+
+// Diagonal of the layer Hessian proxy E[x_j^2] from calibration activations of shape (n, cols)
+pub(crate) fn hessian_diagonal(activations: &Tensor, cols: usize) -> Result<Vec<f32>, CandleError> {
+    let dims = activations.dims();
+    if dims.last() != Some(&cols) {
+        return Err(CandleError::Msg(format!(
+            "Activation width {:?} does not match weight input dimension {}",
+            dims, cols
+        )));
+    }
+    let values = activations.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+    let samples = (values.len() / cols).max(1);
+    let mut diagonal = vec![0.0f32; cols];
+    for row in values.chunks(cols) {
+        for (h, x) in diagonal.iter_mut().zip(row) {
+            *h += x * x;
+        }
+    }
+    diagonal.iter_mut().for_each(|h| *h /= samples as f32);
+    Ok(diagonal)
+}
+
+// Quantize-dequantize a row-major (rows, cols) matrix in blocks of `block_size` rows, as
+// ExpertRegistry quantizes an expert (quantize_batch instead reshapes to a near-square matrix)
+fn fake_quantize(
+    values: &[f32],
+    cols: usize,
+    bit_depth: BitDepth,
+    block_size: usize,
+    symmetric: bool,
+) -> Result<Vec<f32>, CandleError> {
+    match bit_depth {
+        BitDepth::FP16 => Tensor::from_slice(values, values.len(), &Device::Cpu)?
+            .to_dtype(DType::F16)?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>(),
+        BitDepth::INT4 | BitDepth::INT8 => {
+            let options = QuantizationOptions::default();
+            let mut rng = options.rng();
+            let mut restored = Vec::with_capacity(values.len());
+            for block in values.chunks(block_size.max(1) * cols) {
+                let quantized = quantize_block(block, bit_depth.bits(), symmetric, &options, &mut rng);
+                restored.extend(dequantize_block(&quantized));
+            }
+            Ok(restored)
+        }
+    }
+}
+
+// Second-order loss estimate ½ Σ h_j (w_ij - q(w_ij))^2
+fn loss_impact(weights: &[f32], restored: &[f32], diagonal: &[f32]) -> f32 {
+    let cols = diagonal.len();
+    0.5 * weights
+        .iter()
+        .zip(restored)
+        .enumerate()
+        .map(|(i, (w, q))| diagonal[i % cols] * (w - q) * (w - q))
+        .sum::<f32>()
+}
+
+// Score every 2D weight tensor at each candidate bit depth. Tensors without calibration
+// activations fall back to an identity Hessian (plain squared error). Tensors of rank 3 or
+// more, e.g. stacked expert weights, are rejected rather than left out of the plan.
+pub fn analyze_sensitivity(
+    weights: &HashMap<String, Tensor>,
+    activations: &HashMap<String, Tensor>,
+    block_size: usize,
+    symmetric: bool,
+) -> Result<Vec<TensorSensitivity>, CandleError> {
+    let mut names: Vec<&String> = weights.keys().collect();
+    names.sort();
+
+    let mut report = Vec::new();
+    for name in names {
+        let tensor = &weights[name];
+        let shape = tensor.dims().to_vec();
+        let cols = match shape.as_slice() {
+            [_, cols] => cols,
+            // Biases and norms are tiny; the planner keeps them in FP16
+            [] | [_] => continue,
+            _ => {
+                return Err(CandleError::Msg(format!(
+                    "Tensor {} has shape {:?}; split it into 2D matrices before planning",
+                    name, shape
+                )))
+            }
+        };
+        let values = tensor.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+        let diagonal = match activations.get(name) {
+            Some(acts) => hessian_diagonal(acts, *cols)?,
+            None => vec![1.0; *cols],
+        };
+
+        // Every supported depth, cheapest first
+        let mut impact = Vec::with_capacity(BIT_DEPTHS.len());
+        for depth in BIT_DEPTHS {
+            let restored = fake_quantize(&values, *cols, depth, block_size, symmetric)?;
+            impact.push((depth, loss_impact(&values, &restored, &diagonal)));
+        }
+
+        report.push(TensorSensitivity {
+            name: name.clone(),
+            num_params: values.len(),
+            shape,
+            loss_impact: impact,
+        });
+    }
+    Ok(report)
+}
+
+// Multiple-choice knapsack over bit depths: start every tensor at its cheapest depth, then
+// repeatedly apply the upgrade with the largest loss reduction per extra bit that still fits
+// within `target_bits` average bits per weight. Fails when even the all-cheapest plan
+// exceeds the target.
+pub fn plan_bit_depths(
+    sensitivities: &[TensorSensitivity],
+    target_bits: f32,
+) -> Result<MixedPrecisionPlan, CandleError> {
+    if let Some(empty) = sensitivities.iter().find(|s| s.loss_impact.is_empty()) {
+        return Err(CandleError::Msg(format!("Tensor {} has no candidate bit depths", empty.name)));
+    }
+    let ascending = |s: &TensorSensitivity| s.loss_impact.windows(2).all(|w| w[0].0.bits() < w[1].0.bits());
+    if let Some(unsorted) = sensitivities.iter().find(|s| !ascending(s)) {
+        return Err(CandleError::Msg(format!(
+            "Candidate bit depths of tensor {} must be distinct and cheapest first",
+            unsorted.name
+        )));
+    }
+    let total_params: usize = sensitivities.iter().map(|s| s.num_params).sum();
+    let budget = target_bits as f64 * total_params as f64;
+    let mut choice = vec![0usize; sensitivities.len()];
+    let mut used: f64 = sensitivities
+        .iter()
+        .map(|s| s.num_params as f64 * s.loss_impact[0].0.bits() as f64)
+        .sum();
+    if used > budget {
+        return Err(CandleError::Msg(format!(
+            "Target of {} bits per weight is infeasible: the cheapest plan needs {:.2}",
+            target_bits,
+            used / total_params as f64
+        )));
+    }
+
+    loop {
+        let mut best: Option<(usize, usize, f64)> = None;
+        for (t, sensitivity) in sensitivities.iter().enumerate() {
+            let (current_depth, current_loss) = sensitivity.loss_impact[choice[t]];
+            for (option, (depth, loss)) in sensitivity.loss_impact.iter().enumerate().skip(choice[t] + 1) {
+                let extra_bits = sensitivity.num_params as f64 * (depth.bits() as i32 - current_depth.bits() as i32) as f64;
+                let gain = (current_loss - loss) as f64;
+                if used + extra_bits > budget || gain <= 0.0 {
+                    continue;
+                }
+                let ratio = gain / extra_bits;
+                if best.is_none_or(|(_, _, r)| ratio > r) {
+                    best = Some((t, option, ratio));
+                }
+            }
+        }
+        let Some((t, option, _)) = best else { break };
+        let sensitivity = &sensitivities[t];
+        let old_bits = sensitivity.loss_impact[choice[t]].0.bits();
+        let new_bits = sensitivity.loss_impact[option].0.bits();
+        used += sensitivity.num_params as f64 * (new_bits as i32 - old_bits as i32) as f64;
+        choice[t] = option;
+    }
+
+    let tensors: Vec<TensorPlan> = sensitivities
+        .iter()
+        .zip(&choice)
+        .map(|(s, &c)| {
+            let (bit_depth, estimated_loss) = s.loss_impact[c];
+            TensorPlan {
+                name: s.name.clone(),
+                bit_depth,
+                bits: bit_depth.bits(),
+                num_params: s.num_params,
+                estimated_loss,
+            }
+        })
+        .collect();
+
+    Ok(MixedPrecisionPlan {
+        average_bits: if total_params == 0 { 0.0 } else { (used / total_params as f64) as f32 },
+        total_estimated_loss: tensors.iter().map(|t| t.estimated_loss).sum(),
+        target_bits,
+        tensors,
+    })
+}
+
+// Build a per-tensor bit-depth plan from a safetensors model and a safetensors file of
+// calibration activations keyed by the same tensor names. Returns the plan as JSON.
+#[wasm_bindgen]
+pub fn plan_mixed_precision(
+    model: Vec<u8>,
+    activations: Vec<u8>,
+    target_bits: f32,
+    block_size: usize,
+    mode: &str,
+) -> Result<String, JsValue> {
+    let weights = candle_core::safetensors::load_buffer(&model, &Device::Cpu)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let activations = if activations.is_empty() {
+        HashMap::new()
+    } else {
+        candle_core::safetensors::load_buffer(&activations, &Device::Cpu)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    let sensitivities = analyze_sensitivity(&weights, &activations, block_size, mode == "symmetric")
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let plan = plan_bit_depths(&sensitivities, target_bits).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    serde_json::to_string(&plan).map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,433 @@
+use crate::expert_registry::{ExpertRegistry, SharedRegistry};
+use crate::gating::{GatingFunction, SoftmaxTopKGate};
+use crate::policy_engine::{bit_depth_index, BitDepth, BitPrecisionPolicy, ExpertId, HardwareProfile, QuantizationDecision, NUM_BIT_DEPTHS};
+use crate::reward::{RewardConfig, RewardModel, WeightedSumReward};
+use crate::rl_optimize_bit_depth::RLOptimizer;
+use crate::rng::standard_normal;
+use crate::trace_buffer::InferenceTrace;
+use candle_core::{Device, Error as CandleError, Tensor};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Expert with its accuracy curve over INT4, INT8, FP16
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimExpertConfig {
+    pub id: String,
+    pub params: f32, // millions of weights, scales latency and memory
+    pub accuracy: [f32; NUM_BIT_DEPTHS],
+    #[serde(default)]
+    pub token_loss: [f32; NUM_BIT_DEPTHS],
+}
+
+// Cost of running one million weights on a hardware class, per bit depth
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HardwareCostModel {
+    pub base_latency: f32,           // ms per request
+    pub latency_per_token: [f32; NUM_BIT_DEPTHS], // ms per token per million weights
+    pub memory_overhead: f32,        // MB on top of the weights
+    pub power: [f32; NUM_BIT_DEPTHS],             // watts while running
+}
+
+// Share of requests coming from one hardware class, with their length range in tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficClass {
+    pub hardware_class: String,
+    pub weight: f32,
+    pub min_tokens: usize,
+    pub max_tokens: usize,
+}
+
+// Change to the workload from `start_step` of every episode on. Events accumulate:
+// accuracy shifts add up, latency scales multiply and the latest traffic mix wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DriftEvent {
+    pub start_step: usize,
+    pub expert: Option<String>,         // None affects every expert
+    pub hardware_class: Option<String>, // None affects every hardware class
+    pub accuracy_shift: [f32; NUM_BIT_DEPTHS],
+    pub latency_scale: f32,
+    pub traffic: Option<Vec<TrafficClass>>,
+}
+
+impl Default for DriftEvent {
+    fn default() -> Self {
+        DriftEvent {
+            start_step: 0,
+            expert: None,
+            hardware_class: None,
+            accuracy_shift: [0.0; NUM_BIT_DEPTHS],
+            latency_scale: 1.0,
+            traffic: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SimulatorConfig {
+    pub seed: u64, // fixes the router; `reset` reseeds the traffic and noise
+    pub episode_length: usize,
+    pub hidden_size: usize,
+    pub initial_bit_depth: BitDepth,
+    pub experts: Vec<SimExpertConfig>,
+    pub hardware: HashMap<String, HardwareCostModel>,
+    pub traffic: Vec<TrafficClass>,
+    pub drift: Vec<DriftEvent>,
+    pub accuracy_noise: f32, // std of additive accuracy noise
+    pub latency_noise: f32,  // std of multiplicative latency noise
+    pub reward: RewardConfig,
+}
+
+impl Default for SimulatorConfig {
+    // Four experts of increasing quantization sensitivity; INT8 pays off on cpu, FP16 on gpu
+    fn default() -> Self {
+        let expert = |id: &str, params: f32, accuracy: [f32; NUM_BIT_DEPTHS]| SimExpertConfig {
+            id: id.to_string(),
+            params,
+            accuracy,
+            token_loss: [0.02, 0.005, 0.0],
+        };
+        let mut hardware = HashMap::new();
+        hardware.insert(
+            "cpu".to_string(),
+            HardwareCostModel {
+                base_latency: 0.2,
+                latency_per_token: [0.02, 0.04, 0.1],
+                memory_overhead: 16.0,
+                power: [15.0, 18.0, 25.0],
+            },
+        );
+        hardware.insert(
+            "gpu".to_string(),
+            HardwareCostModel {
+                base_latency: 0.1,
+                latency_per_token: [0.01, 0.008, 0.01],
+                memory_overhead: 64.0,
+                power: [120.0, 150.0, 200.0],
+            },
+        );
+        let traffic = |hardware_class: &str, weight: f32| TrafficClass {
+            hardware_class: hardware_class.to_string(),
+            weight,
+            min_tokens: 8,
+            max_tokens: 32,
+        };
+        SimulatorConfig {
+            seed: 0,
+            episode_length: 1000,
+            hidden_size: 16,
+            initial_bit_depth: BitDepth::INT8,
+            experts: vec![
+                expert("expert0", 0.5, [0.91, 0.93, 0.94]),
+                expert("expert1", 1.0, [0.85, 0.93, 0.95]),
+                expert("expert2", 1.0, [0.75, 0.92, 0.95]),
+                expert("expert3", 2.0, [0.6, 0.9, 0.95]),
+            ],
+            hardware,
+            traffic: vec![traffic("cpu", 0.7), traffic("gpu", 0.3)],
+            drift: Vec::new(),
+            accuracy_noise: 0.01,
+            latency_noise: 0.05,
+            reward: RewardConfig::default(),
+        }
+    }
+}
+
+// What a policy sees before deciding: the request and the expert the gate routes it to
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub step: usize,
+    pub expert_id: ExpertId,
+    pub bit_depth: BitDepth, // the expert's depth in the registry
+    pub hardware_profile: HardwareProfile,
+    pub input_size: usize,
+    pub input: Tensor, // (input_size, hidden_size)
+}
+
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub trace: InferenceTrace,
+    pub reward: f32,
+    pub observation: Observation, // next request
+    pub done: bool,               // the episode is over; call `reset` before stepping on
+}
+
+// Deterministic MoE serving workload with a gym-like `reset`/`step` API. Every expert is in
+// the simulator's registry, whose depths are the ground truth `step` runs at; policies and
+// optimizers under test should share `gate()` and `registry()`.
+pub struct WorkloadSimulator {
+    config: SimulatorConfig,
+    gate: Rc<SoftmaxTopKGate>,
+    registry: SharedRegistry,
+    reward: WeightedSumReward,
+    rng: StdRng,
+    seed: u64,
+    step: usize,
+    observation: Option<Observation>,
+}
+
+impl WorkloadSimulator {
+    pub fn new(config: SimulatorConfig) -> Result<Self, CandleError> {
+        if config.experts.is_empty() || config.traffic.is_empty() {
+            return Err(CandleError::Msg("Simulator needs at least one expert and one traffic class".to_string()));
+        }
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let hidden = config.hidden_size.max(1);
+        let ids: Vec<ExpertId> = config.experts.iter().map(|e| ExpertId(e.id.clone())).collect();
+
+        let std = 1.0 / (hidden as f32).sqrt();
+        let router: Vec<f32> = (0..hidden * ids.len()).map(|_| std * standard_normal(&mut rng)).collect();
+        let router = Tensor::from_vec(router, (hidden, ids.len()), &Device::Cpu)?;
+        let gate = Rc::new(SoftmaxTopKGate::new(ids.clone(), router, 1)?);
+
+        // The weights only exist so requantization has something to do; costs come from the config
+        let mut registry = ExpertRegistry::new(32, true);
+        for id in ids {
+            let weights: Vec<f32> = (0..64).map(|_| 0.1 * standard_normal(&mut rng)).collect();
+            registry
+                .register(id, 0, vec![8, 8], weights, config.initial_bit_depth)
+                .map_err(CandleError::Msg)?;
+        }
+
+        let seed = config.seed;
+        let mut simulator = WorkloadSimulator {
+            reward: WeightedSumReward::new(config.reward.clone()),
+            config,
+            gate,
+            registry: registry.shared(),
+            rng,
+            seed,
+            step: 0,
+            observation: None,
+        };
+        simulator.reset(seed)?;
+        Ok(simulator)
+    }
+
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    pub fn gate(&self) -> Rc<SoftmaxTopKGate> {
+        self.gate.clone()
+    }
+
+    pub fn registry(&self) -> SharedRegistry {
+        self.registry.clone()
+    }
+
+    pub fn observation(&self) -> Option<&Observation> {
+        self.observation.as_ref()
+    }
+
+    // Start a new episode: every expert back at the initial depth, traffic and noise reseeded
+    pub fn reset(&mut self, seed: u64) -> Result<Observation, CandleError> {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.seed = seed;
+        self.step = 0;
+        {
+            let mut registry = self.registry.borrow_mut();
+            for expert in &self.config.experts {
+                registry
+                    .requantize(&ExpertId(expert.id.clone()), self.config.initial_bit_depth)
+                    .map_err(CandleError::Msg)?;
+            }
+        }
+        let observation = self.next_observation()?;
+        self.observation = Some(observation.clone());
+        Ok(observation)
+    }
+
+    // Run the current request with its expert at `bit_depth`, which the expert keeps afterwards
+    pub fn step(&mut self, bit_depth: BitDepth) -> Result<StepResult, CandleError> {
+        let observation = self
+            .observation
+            .take()
+            .ok_or_else(|| CandleError::Msg("Simulator has no pending request".to_string()))?;
+        let current = self.registry.borrow().bit_depth(&observation.expert_id).unwrap_or(observation.bit_depth);
+        self.registry
+            .borrow_mut()
+            .requantize(&observation.expert_id, bit_depth)
+            .map_err(CandleError::Msg)?;
+
+        let trace = self.outcome(&observation, current, bit_depth)?;
+        self.reward.observe(&trace);
+        let reward = self.reward.reward(&trace);
+
+        self.step += 1;
+        let next = self.next_observation()?;
+        self.observation = Some(next.clone());
+        Ok(StepResult {
+            trace,
+            reward,
+            observation: next,
+            done: self.step >= self.config.episode_length,
+        })
+    }
+
+    fn drift(&self) -> impl Iterator<Item = &DriftEvent> {
+        let step = self.step;
+        self.config.drift.iter().filter(move |d| d.start_step <= step)
+    }
+
+    fn traffic(&self) -> &[TrafficClass] {
+        self.drift()
+            .filter_map(|d| d.traffic.as_deref())
+            .last()
+            .filter(|t| !t.is_empty())
+            .unwrap_or(&self.config.traffic)
+    }
+
+    fn next_observation(&mut self) -> Result<Observation, CandleError> {
+        let traffic = self.traffic().to_vec();
+        let total: f32 = traffic.iter().map(|t| t.weight.max(0.0)).sum();
+        let mut draw = self.rng.gen::<f32>() * total;
+        let class = traffic
+            .iter()
+            .find(|t| {
+                draw -= t.weight.max(0.0);
+                draw < 0.0
+            })
+            .unwrap_or(&traffic[traffic.len() - 1]);
+
+        let min_tokens = class.min_tokens.max(1);
+        let input_size = self.rng.gen_range(min_tokens..=class.max_tokens.max(min_tokens));
+        let hidden = self.config.hidden_size.max(1);
+        let values: Vec<f32> = (0..input_size * hidden).map(|_| standard_normal(&mut self.rng)).collect();
+        let input = Tensor::from_vec(values, (input_size, hidden), &Device::Cpu)?;
+
+        let expert_id = self
+            .gate
+            .route(&input)?
+            .into_iter()
+            .next()
+            .map(|(id, _)| id)
+            .ok_or_else(|| CandleError::Msg("Gate routed the request to no expert".to_string()))?;
+        let bit_depth = self
+            .registry
+            .borrow()
+            .bit_depth(&expert_id)
+            .unwrap_or(self.config.initial_bit_depth);
+        Ok(Observation {
+            step: self.step,
+            expert_id,
+            bit_depth,
+            hardware_profile: HardwareProfile::new(&class.hardware_class),
+            input_size,
+            input,
+        })
+    }
+
+    fn outcome(
+        &mut self,
+        observation: &Observation,
+        current: BitDepth,
+        bit_depth: BitDepth,
+    ) -> Result<InferenceTrace, CandleError> {
+        let hardware_class = &observation.hardware_profile.hardware_type;
+        let expert = self
+            .config
+            .experts
+            .iter()
+            .find(|e| e.id == observation.expert_id.0)
+            .ok_or_else(|| CandleError::Msg(format!("Unknown expert {}", observation.expert_id.0)))?;
+        let cost = self
+            .config
+            .hardware
+            .get(hardware_class)
+            .ok_or_else(|| CandleError::Msg(format!("No cost model for hardware class {}", hardware_class)))?;
+        let d = bit_depth_index(bit_depth);
+
+        let (mut accuracy_shift, mut latency_scale) = (0.0, 1.0);
+        for event in self.drift() {
+            let expert_matches = event.expert.as_ref().is_none_or(|e| *e == expert.id);
+            let hardware_matches = event.hardware_class.as_ref().is_none_or(|h| h == hardware_class);
+            if expert_matches && hardware_matches {
+                accuracy_shift += event.accuracy_shift[d];
+                latency_scale *= event.latency_scale;
+            }
+        }
+
+        let accuracy_noise = self.config.accuracy_noise * standard_normal(&mut self.rng);
+        let latency_noise = (1.0 + self.config.latency_noise * standard_normal(&mut self.rng)).max(0.0);
+        let accuracy = (expert.accuracy[d] + accuracy_shift + accuracy_noise).clamp(0.0, 1.0);
+        let latency = (cost.base_latency + observation.input_size as f32 * expert.params * cost.latency_per_token[d])
+            * latency_scale
+            * latency_noise;
+        let decision = QuantizationDecision::between(current, bit_depth);
+
+        Ok(InferenceTrace {
+            expert_id: observation.expert_id.clone(),
+            bit_depth,
+            hardware_profile: observation.hardware_profile.clone(),
+            accuracy,
+            latency,
+            token_loss: expert.token_loss[d],
+            decision,
+            input_size: observation.input_size,
+            memory_usage: expert.params * bit_depth.bits() as f32 / 8.0 + cost.memory_overhead,
+            energy_usage: latency / 1000.0 * cost.power[d],
+            propensity: None,
+        })
+    }
+
+    fn pending(&mut self) -> Result<Observation, CandleError> {
+        match &self.observation {
+            Some(observation) => Ok(observation.clone()),
+            None => self.reset(self.seed),
+        }
+    }
+
+    // Ends of episodes roll over into the next seed, so long runs see fresh traffic
+    fn advance(&mut self, result: &StepResult) -> Result<(), CandleError> {
+        if result.done {
+            self.reset(self.seed.wrapping_add(1))?;
+        }
+        Ok(())
+    }
+
+    // Let `policy` pick the depth of every request for `steps` steps and learn from the traces.
+    // Returns the simulator's reward per step. The policy's reward model is not observed here.
+    pub fn run_policy(&mut self, policy: &mut dyn BitPrecisionPolicy, steps: usize) -> Result<Vec<f32>, CandleError> {
+        let mut rewards = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            let observation = self.pending()?;
+            let bit_depth = policy
+                .select_experts(&observation.input, &observation.hardware_profile)
+                .into_iter()
+                .find(|(id, _)| id.0 == observation.expert_id.0)
+                .map_or(observation.bit_depth, |(_, bit_depth)| bit_depth);
+            let result = self.step(bit_depth)?;
+            policy.update_policy(result.trace.clone());
+            rewards.push(result.reward);
+            self.advance(&result)?;
+        }
+        Ok(rewards)
+    }
+
+    // Serve every request at the depth the optimizer left its expert at, feeding each trace back
+    // into it. The optimizer must have been built on this simulator's gate and registry.
+    pub fn run_optimizer(&mut self, optimizer: &mut RLOptimizer, steps: usize) -> Result<Vec<f32>, CandleError> {
+        let mut rewards = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            let observation = self.pending()?;
+            let bit_depth = self
+                .registry
+                .borrow()
+                .bit_depth(&observation.expert_id)
+                .unwrap_or(observation.bit_depth);
+            let result = self.step(bit_depth)?;
+            optimizer.optimize_bit_depth(result.trace.clone(), &observation.input, &observation.hardware_profile)?;
+            rewards.push(result.reward);
+            self.advance(&result)?;
+        }
+        Ok(rewards)
+    }
+}
@@ -1,45 +1,2318 @@
+// Test traces: expert e0 served at INT8 on the CPU with accuracy 0.9 and a 1 ms latency, with
+// whatever a test varies overridden through the builder
 #[cfg(test)]
-mod tests {
-    use super::*;
-    use candle_core::{Tensor, Device};
+struct TraceBuilder(crate::trace_buffer::InferenceTrace);
+
+#[cfg(test)]
+fn trace() -> TraceBuilder {
+    use crate::policy_engine::{BitDepth, ExpertId, HardwareProfile, QuantizationDecision};
+    TraceBuilder(crate::trace_buffer::InferenceTrace {
+        expert_id: ExpertId("e0".to_string()),
+        bit_depth: BitDepth::INT8,
+        hardware_profile: HardwareProfile::new("cpu"),
+        accuracy: 0.9,
+        latency: 1.0,
+        token_loss: 0.0,
+        decision: QuantizationDecision::Hold,
+        input_size: 1,
+        memory_usage: 0.0,
+        energy_usage: 0.0,
+        propensity: None,
+    })
+}
+
+#[cfg(test)]
+impl TraceBuilder {
+    fn expert(mut self, id: &str) -> Self {
+        self.0.expert_id = crate::policy_engine::ExpertId(id.to_string());
+        self
+    }
+
+    fn bit_depth(mut self, bit_depth: crate::policy_engine::BitDepth) -> Self {
+        self.0.bit_depth = bit_depth;
+        self
+    }
+
+    fn hardware(mut self, hardware_type: &str) -> Self {
+        self.0.hardware_profile = crate::policy_engine::HardwareProfile::new(hardware_type);
+        self
+    }
+
+    fn accuracy(mut self, accuracy: f32) -> Self {
+        self.0.accuracy = accuracy;
+        self
+    }
+
+    fn latency(mut self, latency: f32) -> Self {
+        self.0.latency = latency;
+        self
+    }
+
+    fn token_loss(mut self, token_loss: f32) -> Self {
+        self.0.token_loss = token_loss;
+        self
+    }
+
+    fn input_size(mut self, input_size: usize) -> Self {
+        self.0.input_size = input_size;
+        self
+    }
+
+    fn memory_usage(mut self, memory_usage: f32) -> Self {
+        self.0.memory_usage = memory_usage;
+        self
+    }
+
+    fn energy_usage(mut self, energy_usage: f32) -> Self {
+        self.0.energy_usage = energy_usage;
+        self
+    }
+
+    fn propensity(mut self, propensity: f32) -> Self {
+        self.0.propensity = Some(propensity);
+        self
+    }
+
+    fn build(self) -> crate::trace_buffer::InferenceTrace {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod simulator_tests {
+    use crate::policy_engine::{BitDepth, PPOPolicy, QLearningConfig, QLearningPolicy};
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::guardrails::GuardrailConfig;
+    use crate::rl_optimize_bit_depth::{PolicyKind, RLOptimizer};
+    use crate::rng::set_global_seed;
+    use crate::simulator::{DriftEvent, SimulatorConfig, WorkloadSimulator};
+
+    fn mean(values: &[f32]) -> f32 {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    // Mean reward of serving every request at one depth
+    fn fixed_depth_reward(seed: u64, bit_depth: BitDepth, steps: usize) -> f32 {
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(seed).unwrap();
+        let rewards: Vec<f32> = (0..steps).map(|_| sim.step(bit_depth).unwrap().reward).collect();
+        mean(&rewards)
+    }
+
+    #[test]
+    fn test_simulator_is_deterministic() {
+        let mut a = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        let mut b = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        let depths = [BitDepth::INT4, BitDepth::FP16, BitDepth::INT8];
+        let run = |sim: &mut WorkloadSimulator| {
+            sim.reset(42).unwrap();
+            (0..200)
+                .map(|i| {
+                    let step = sim.step(depths[i % 3]).unwrap();
+                    (step.trace.expert_id.0, step.trace.hardware_profile.hardware_type, step.trace.latency, step.reward)
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = run(&mut a);
+        assert_eq!(first, run(&mut b));
+        assert_eq!(first, run(&mut a), "reset with the same seed must replay the episode");
+        b.reset(43).unwrap();
+        assert_ne!(first[0].3, b.step(depths[0]).unwrap().reward);
+    }
+
+    #[test]
+    fn test_simulator_drift() {
+        let config = SimulatorConfig {
+            accuracy_noise: 0.0,
+            drift: vec![DriftEvent {
+                start_step: 50,
+                accuracy_shift: [-0.3, 0.0, 0.0],
+                latency_scale: 2.0,
+                ..DriftEvent::default()
+            }],
+            ..SimulatorConfig::default()
+        };
+        let mut sim = WorkloadSimulator::new(config.clone()).unwrap();
+        let steps: Vec<_> = (0..100).map(|_| sim.step(BitDepth::INT4).unwrap().trace).collect();
+        for (i, trace) in steps.iter().enumerate() {
+            let expert = config.experts.iter().find(|e| e.id == trace.expert_id.0).unwrap();
+            let expected = if i < 50 { expert.accuracy[0] } else { expert.accuracy[0] - 0.3 };
+            assert!((trace.accuracy - expected.max(0.0)).abs() < 1e-6, "step {}", i);
+        }
+        let before = mean(&steps[..50].iter().map(|t| t.latency / t.input_size as f32).collect::<Vec<_>>());
+        let after = mean(&steps[50..].iter().map(|t| t.latency / t.input_size as f32).collect::<Vec<_>>());
+        assert!(after > 1.5 * before);
+    }
+
+    #[test]
+    fn test_q_learning_converges_on_simulator() {
+        set_global_seed(1);
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(7).unwrap();
+        // The state includes the expert's current depth, so there are three rows of target depths
+        // per expert and hardware class to explore. At 0.9995 exploration falls below 5% after about
+        // 4,600 steps, before most rows have been visited, and the learned policy only ties with
+        // always running FP16. At 0.9998 that takes about 11,500 steps, longer than this run.
+        let config = QLearningConfig {
+            exploration_rate: 0.5,
+            exploration_decay: 0.9998,
+            ..QLearningConfig::default()
+        };
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut policy = QLearningPolicy::with_config(sim.gate(), sim.registry(), reward, config);
+        let rewards = sim.run_policy(&mut policy, 10_000).unwrap();
+
+        let learned = mean(&rewards[rewards.len() - 1000..]);
+        let int4 = fixed_depth_reward(7, BitDepth::INT4, 1000);
+        let fp16 = fixed_depth_reward(7, BitDepth::FP16, 1000);
+        assert!(learned > int4 + 0.02, "learned {} vs INT4 {}", learned, int4);
+        assert!(learned > fp16, "learned {} vs FP16 {}", learned, fp16);
+    }
+
+    #[test]
+    fn test_ppo_improves_on_simulator() {
+        set_global_seed(1);
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(7).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut policy = PPOPolicy::new(sim.gate(), sim.registry(), reward).unwrap();
+        let rewards = sim.run_policy(&mut policy, 4000).unwrap();
+
+        let first = mean(&rewards[..500]);
+        let last = mean(&rewards[rewards.len() - 500..]);
+        assert!(last > first + 0.02, "first {} last {}", first, last);
+    }
+
+    #[test]
+    fn test_rl_optimizer_on_simulator() {
+        set_global_seed(1);
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(7).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut optimizer = RLOptimizer::new(sim.gate(), sim.registry(), reward).unwrap();
+        optimizer.set_policy(PolicyKind::Ppo);
+        let rewards = sim.run_optimizer(&mut optimizer, 4000).unwrap();
+
+        let last = mean(&rewards[rewards.len() - 500..]);
+        let int4 = fixed_depth_reward(7, BitDepth::INT4, 1000);
+        assert!(last > int4 + 0.02, "optimizer {} vs INT4 {}", last, int4);
+    }
+
+    #[test]
+    fn test_seeded_runs_replay() {
+        let run = |kind: PolicyKind| {
+            set_global_seed(99);
+            let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+            let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+            let mut optimizer = RLOptimizer::new(sim.gate(), sim.registry(), reward).unwrap();
+            optimizer.set_policy(kind);
+            optimizer.set_guardrails(GuardrailConfig {
+                exploration_fraction: 0.5,
+                ..GuardrailConfig::default()
+            });
+            let rewards = sim.run_optimizer(&mut optimizer, 300).unwrap();
+            let registry = sim.registry();
+            let depths: Vec<_> = registry.borrow().active().map(|e| e.bit_depth).collect();
+            (rewards, depths)
+        };
+        for kind in [PolicyKind::QLearning, PolicyKind::Ppo, PolicyKind::Thompson, PolicyKind::Dqn] {
+            assert_eq!(run(kind), run(kind), "{:?} run was not reproducible", kind);
+        }
+    }
+}
+
+#[cfg(test)]
+mod quantization_tests {
+    use crate::quantization::{
+        dequantize_block, quantize_batch, quantize_block, ClipObjective, QuantizationOptions, RoundingMode,
+        ScaleMethod,
+    };
+    use crate::rng::{clear_global_seed, set_global_seed};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const BIT_DEPTHS: [u8; 2] = [4, 8];
+    const SCALE_METHODS: [ScaleMethod; 3] = [ScaleMethod::MaxAbs, ScaleMethod::ClipSearch, ScaleMethod::MseOptimal];
+
+    fn code_bounds(bit_depth: u8, symmetric: bool) -> (f32, f32) {
+        if symmetric {
+            let qmax = ((1 << (bit_depth - 1)) - 1) as f32;
+            (-qmax, qmax)
+        } else {
+            (0.0, ((1 << bit_depth) - 1) as f32)
+        }
+    }
+
+    fn random_block(rng: &mut StdRng, len: usize) -> Vec<f32> {
+        let magnitude = 10f32.powf(rng.gen_range(-3.0..3.0));
+        (0..len).map(|_| rng.gen_range(-1.0..1.0) * magnitude).collect()
+    }
+
+    fn mse(block: &[f32], restored: &[f32]) -> f32 {
+        block.iter().zip(restored).map(|(w, q)| (w - q) * (w - q)).sum::<f32>() / block.len() as f32
+    }
+
+    fn options(scale_method: ScaleMethod, rounding: RoundingMode) -> QuantizationOptions {
+        QuantizationOptions {
+            scale_method,
+            rounding,
+            ..QuantizationOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip_error_bounded_by_half_step() {
+        let mut data_rng = StdRng::seed_from_u64(1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let opts = options(ScaleMethod::MaxAbs, RoundingMode::Nearest);
+
+        for _ in 0..200 {
+            let len = data_rng.gen_range(1..300);
+            let block = random_block(&mut data_rng, len);
+            for bit_depth in BIT_DEPTHS {
+                for symmetric in [true, false] {
+                    let quantized = quantize_block(&block, bit_depth, symmetric, &opts, &mut rng);
+                    let restored = dequantize_block(&quantized);
+                    let (qmin, qmax) = code_bounds(bit_depth, symmetric);
+
+                    assert!(quantized.scale > 0.0 && quantized.scale.is_finite());
+                    for (w, (q, code)) in block.iter().zip(restored.iter().zip(&quantized.codes)) {
+                        assert!(*code >= qmin && *code <= qmax && code.fract() == 0.0);
+                        assert!(
+                            (w - q).abs() <= quantized.scale * 0.5 * (1.0 + 1e-4),
+                            "{}-bit symmetric={} w={} q={} scale={}",
+                            bit_depth,
+                            symmetric,
+                            w,
+                            q,
+                            quantized.scale
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stochastic_rounding_bounded_and_unbiased() {
+        let block = vec![0.3, -0.77, 0.051, 1.0, -0.4];
+        let opts = options(ScaleMethod::MaxAbs, RoundingMode::Stochastic);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let trials = 4000;
+        let mut mean = vec![0.0f32; block.len()];
+        for _ in 0..trials {
+            let quantized = quantize_block(&block, 4, true, &opts, &mut rng);
+            for (i, (w, q)) in block.iter().zip(dequantize_block(&quantized)).enumerate() {
+                assert!((w - q).abs() <= quantized.scale * (1.0 + 1e-4));
+                mean[i] += q / trials as f32;
+            }
+        }
+        let step = 1.0 / 7.0;
+        for (w, m) in block.iter().zip(&mean) {
+            assert!((w - m).abs() < step * 0.05, "w={} mean={}", w, m);
+        }
+    }
+
+    #[test]
+    fn test_stochastic_rounding_is_reproducible_from_seed() {
+        let block: Vec<f32> = (0..64).map(|i| (i as f32 * 0.37).sin()).collect();
+        let opts = options(ScaleMethod::MaxAbs, RoundingMode::Stochastic);
+
+        let first = quantize_block(&block, 4, false, &opts, &mut StdRng::seed_from_u64(7)).codes;
+        let second = quantize_block(&block, 4, false, &opts, &mut StdRng::seed_from_u64(7)).codes;
+        let other = quantize_block(&block, 4, false, &opts, &mut StdRng::seed_from_u64(8)).codes;
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_unseeded_rounding_follows_the_global_seed() {
+        let unseeded = options(ScaleMethod::MaxAbs, RoundingMode::Stochastic);
+        let draws = || {
+            set_global_seed(11);
+            (unseeded.rng().gen::<u64>(), unseeded.rng().gen::<u64>())
+        };
+        let (first, second) = draws();
+        assert_eq!(draws(), (first, second));
+        assert_ne!(first, second);
+        clear_global_seed();
+
+        let seeded = QuantizationOptions { seed: Some(7), ..unseeded };
+        assert_eq!(seeded.rng().gen::<u64>(), StdRng::seed_from_u64(7).gen::<u64>());
+    }
+
+    #[test]
+    fn test_scale_search_never_worse_than_max_abs() {
+        let mut data_rng = StdRng::seed_from_u64(3);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..50 {
+            let mut block = random_block(&mut data_rng, 128);
+            block[0] *= 20.0; // outlier that inflates the max-abs scale
+            for bit_depth in BIT_DEPTHS {
+                for symmetric in [true, false] {
+                    let baseline = quantize_block(&block, bit_depth, symmetric, &options(ScaleMethod::MaxAbs, RoundingMode::Nearest), &mut rng);
+                    let baseline_mse = mse(&block, &dequantize_block(&baseline));
+                    for method in [ScaleMethod::ClipSearch, ScaleMethod::MseOptimal] {
+                        let quantized = quantize_block(&block, bit_depth, symmetric, &options(method, RoundingMode::Nearest), &mut rng);
+                        assert!(mse(&block, &dequantize_block(&quantized)) <= baseline_mse * (1.0 + 1e-5));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_percentile_clip_search_stays_in_range() {
+        let block: Vec<f32> = (0..256).map(|i| if i == 0 { 50.0 } else { (i as f32 * 0.11).cos() }).collect();
+        let opts = QuantizationOptions {
+            scale_method: ScaleMethod::ClipSearch,
+            clip_objective: ClipObjective::Percentile,
+            error_percentile: 0.9,
+            ..QuantizationOptions::default()
+        };
+        let quantized = quantize_block(&block, 4, true, &opts, &mut StdRng::seed_from_u64(0));
+        assert!(quantized.scale <= 50.0 / 7.0);
+        assert!(quantized.scale >= 50.0 / 7.0 * opts.min_clip_ratio * (1.0 - 1e-5));
+        assert!(quantized.codes.iter().all(|c| c.abs() <= 7.0));
+    }
+
+    #[test]
+    fn test_golden_vectors() {
+        let opts = QuantizationOptions::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // scale = 1/7
+        let q = quantize_block(&[0.0, 0.4, -1.0, 0.7, 0.25], 4, true, &opts, &mut rng);
+        assert_eq!(q.codes, vec![0.0, 3.0, -7.0, 5.0, 2.0]);
+        assert!((q.scale - 1.0 / 7.0).abs() < 1e-7);
+
+        // scale = 1/127
+        let q = quantize_block(&[0.3, -0.25, 1.0, 0.1], 8, true, &opts, &mut rng);
+        assert_eq!(q.codes, vec![38.0, -32.0, 127.0, 13.0]);
+
+        // scale = (4 - 1) / 15, offset = 1
+        let q = quantize_block(&[1.0, 2.35, 4.0, 3.0], 4, false, &opts, &mut rng);
+        assert_eq!(q.codes, vec![0.0, 7.0, 15.0, 10.0]);
+        assert_eq!(q.offset, 1.0);
+        assert!((q.scale - 0.2).abs() < 1e-7);
+
+        // scale = 2 / 255, offset = -1
+        let q = quantize_block(&[-1.0, 0.1, 1.0, 0.5], 8, false, &opts, &mut rng);
+        assert_eq!(q.codes, vec![0.0, 140.0, 255.0, 191.0]);
+    }
+
+    #[test]
+    fn test_all_zero_block() {
+        let block = vec![0.0f32; 16];
+        let mut rng = StdRng::seed_from_u64(0);
+        for method in SCALE_METHODS {
+            for bit_depth in BIT_DEPTHS {
+                for symmetric in [true, false] {
+                    let quantized = quantize_block(&block, bit_depth, symmetric, &options(method, RoundingMode::Nearest), &mut rng);
+                    assert!(quantized.scale > 0.0 && quantized.scale.is_finite());
+                    assert!(quantized.codes.iter().all(|c| *c == 0.0));
+                    assert_eq!(dequantize_block(&quantized), block);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant_block_is_exact() {
+        let block = vec![0.625f32; 9];
+        let mut rng = StdRng::seed_from_u64(0);
+        for method in SCALE_METHODS {
+            for symmetric in [true, false] {
+                let quantized = quantize_block(&block, 4, symmetric, &options(method, RoundingMode::Nearest), &mut rng);
+                let restored = dequantize_block(&quantized);
+                assert!(restored.iter().all(|q| (q - 0.625).abs() < 1e-6), "{:?}", restored);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_finite_inputs() {
+        let block = vec![0.5, f32::NAN, -1.0, f32::INFINITY, f32::NEG_INFINITY, 0.25];
+        let mut rng = StdRng::seed_from_u64(0);
+        for method in SCALE_METHODS {
+            for rounding in [RoundingMode::Nearest, RoundingMode::Stochastic] {
+                let quantized = quantize_block(&block, 8, true, &options(method, rounding), &mut rng);
+                assert!(quantized.scale.is_finite() && quantized.offset.is_finite());
+                assert!(quantized.codes.iter().all(|c| c.is_finite()));
+
+                let restored = dequantize_block(&quantized);
+                assert_eq!(restored[1], 0.0);
+                assert_eq!(quantized.codes[3], 127.0);
+                assert_eq!(quantized.codes[4], -127.0);
+                assert!((restored[2] + 1.0).abs() <= quantized.scale);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiny_blocks() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let opts = QuantizationOptions::default();
+
+        assert!(quantize_block(&[], 4, true, &opts, &mut rng).codes.is_empty());
+        for symmetric in [true, false] {
+            for value in [-3.5f32, 1e-20, 7.0] {
+                let quantized = quantize_block(&[value], 4, symmetric, &opts, &mut rng);
+                let restored = dequantize_block(&quantized)[0];
+                assert!((restored - value).abs() <= value.abs() * 1e-6, "{} -> {}", value, restored);
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_granularity_and_techniques() {
+        // Two 4x4 experts; block_size is the number of rows sharing one scale
+        let weights: Vec<f32> = (0..32).map(|i| ((i as f32) * 0.45).sin() * (1 + i % 5) as f32).collect();
+        for bit_depth in BIT_DEPTHS {
+            for mode in ["symmetric", "asymmetric"] {
+                let (qmin, qmax) = code_bounds(bit_depth, mode == "symmetric");
+                for block_size in [1, 2, 4] {
+                    let gptq = quantize_batch(weights.clone(), 2, bit_depth, "gptq", block_size, mode, 0, "", false, 0.0).unwrap();
+                    let awq = quantize_batch(weights.clone(), 2, bit_depth, "awq", block_size, mode, 0, "", false, 0.0).unwrap();
+                    assert_eq!(gptq.len(), weights.len());
+                    assert_eq!(gptq, awq);
+                    assert!(gptq.iter().all(|c| *c >= qmin && *c <= qmax && c.fract() == 0.0));
+                    // Every block reaches the end of the code range at its largest weight
+                    for block in gptq.chunks(block_size * 4) {
+                        assert!(block.iter().any(|c| *c == qmax || (mode == "symmetric" && *c == qmin)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod hardware_tests {
+    use crate::hardware::{
+        calibrate, parse_cache_size, parse_cpu_flags, parse_meminfo, CalibrationConfig, HardwareClass, HardwareProfile,
+        SimdCapabilities,
+    };
+    use crate::qmatmul::{available_backends, detect_backend, KernelBackend};
+
+    #[test]
+    fn test_parse_cpu_flags() {
+        let x86 = "processor\t: 0\nflags\t\t: fpu sse2 avx avx2 fma avx512f avx512bw\n";
+        let simd = parse_cpu_flags(x86);
+        assert!(simd.avx2 && simd.avx512 && !simd.neon);
+
+        let arm = "processor\t: 0\nFeatures\t: fp asimd evtstrm aes crc32\n";
+        let simd = parse_cpu_flags(arm);
+        assert!(simd.neon && !simd.avx2 && !simd.avx512);
+
+        // Without BW the AVX-512 INT8 kernel can't run, so it doesn't count
+        let foundation_only = "flags\t\t: avx2 avx512f\n";
+        assert!(!parse_cpu_flags(foundation_only).avx512);
+    }
+
+    #[test]
+    fn test_detected_simd_matches_kernel_dispatch() {
+        let simd = HardwareProfile::detect().simd;
+        assert_eq!(simd, SimdCapabilities::from_backends(&available_backends()));
+        assert_eq!(simd.avx512, detect_backend() == KernelBackend::Avx512);
+    }
+
+    #[test]
+    fn test_parse_meminfo_and_cache_sizes() {
+        let meminfo = "MemTotal:       16384000 kB\nMemFree:         1024000 kB\nMemAvailable:    8192000 kB\n";
+        assert_eq!(parse_meminfo(meminfo), (16000.0, 8000.0));
+        assert_eq!(parse_meminfo(""), (0.0, 0.0));
+        assert_eq!(parse_cache_size("48K\n"), Some(48));
+        assert_eq!(parse_cache_size("32M"), Some(32 * 1024));
+        assert_eq!(parse_cache_size("512"), Some(512));
+        assert_eq!(parse_cache_size("big"), None);
+    }
+
+    #[test]
+    fn test_profile_class_and_features() {
+        assert_eq!(HardwareClass::from_type("cuda:0"), HardwareClass::Gpu);
+        assert_eq!(HardwareClass::from_type("TPU-v4"), HardwareClass::Tpu);
+        assert_eq!(HardwareClass::from_type("cpu-avx512"), HardwareClass::Cpu);
+        assert_eq!(HardwareClass::from_type("npu"), HardwareClass::Cpu);
+
+        // Profiles that only carry the type, as sent before detection existed, still parse
+        let legacy: HardwareProfile = serde_json::from_str(r#"{"hardware_type":"gpu"}"#).unwrap();
+        assert_eq!(legacy.class(), HardwareClass::Gpu);
+        assert_eq!(legacy.features(), [0.0; 6]);
+
+        let detected = HardwareProfile::detect();
+        assert!(detected.cores >= 1);
+        assert!(detected.features().iter().all(|f| (0.0..=1.0).contains(f)));
+        if cfg!(target_os = "linux") {
+            assert!(detected.total_memory_mb > 0.0);
+            assert!(detected.available_memory_mb <= detected.total_memory_mb);
+        }
+    }
+
+    #[test]
+    fn test_calibration_measures_bandwidth() {
+        let mut profile = HardwareProfile::new("cpu");
+        let config = CalibrationConfig {
+            buffer_mb: 4,
+            repetitions: 2,
+        };
+        let gbps = calibrate(&mut profile, &config).unwrap();
+        assert!(gbps > 0.0 && gbps.is_finite());
+        assert_eq!(profile.memory_bandwidth_gbps, gbps);
+    }
+}
+
+#[cfg(test)]
+mod cost_model_tests {
+    use crate::cost_model::{estimate_expert_costs, CostCalibrationConfig, CostModel, CostModelConfig};
+    use crate::hardware::{HardwareClass, HardwareProfile};
+    use crate::petri::{PetriNetMonoid, PetriPlace};
+    use crate::policy_engine::BitDepth;
+    use super::trace;
+
+    #[test]
+    fn test_memory_footprint_per_bit_depth() {
+        let model = CostModel::default();
+        let cpu = HardwareProfile::new("cpu");
+        let [int4, int8, fp16] = model.predict_all(1_000_000, 8, &cpu);
+        assert!(int4.memory_mb < int8.memory_mb && int8.memory_mb < fp16.memory_mb);
+        // One f32 scale per 32 weights on top of the codes
+        assert!((int4.memory_mb - (0.5 + 0.125)).abs() < 1e-4);
+        assert!((fp16.memory_mb - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_online_refinement_learns_hardware_costs() {
+        let mut model = CostModel::new(CostModelConfig {
+            forgetting_factor: 1.0,
+            ..CostModelConfig::default()
+        });
+        let gpu = HardwareProfile::new("gpu");
+        assert!(!model.is_fitted(HardwareClass::Gpu, BitDepth::INT8));
+
+        // latency = 0.3 + 2·M + 0.05·tokens·M ms at 120 W with 64 MB runtime overhead
+        let truth = |params: usize, tokens: usize| {
+            let m = params as f32 / 1e6;
+            0.3 + 2.0 * m + 0.05 * tokens as f32 * m
+        };
+        for i in 0..200 {
+            let params = [250_000, 1_000_000, 4_000_000][i % 3];
+            let tokens = 1 + (i * 7) % 64;
+            let latency = truth(params, tokens);
+            let memory = model.weight_memory_mb(params, BitDepth::INT8) + 64.0;
+            let t = trace()
+                .hardware("gpu")
+                .input_size(tokens)
+                .latency(latency)
+                .memory_usage(memory)
+                .energy_usage(latency / 1000.0 * 120.0)
+                .build();
+            model.observe(&t, params);
+        }
+        assert!(model.is_fitted(HardwareClass::Gpu, BitDepth::INT8));
+        assert!(!model.is_fitted(HardwareClass::Cpu, BitDepth::INT8));
+
+        let estimate = model.predict(2_000_000, 32, BitDepth::INT8, &gpu);
+        let expected = truth(2_000_000, 32);
+        assert!((estimate.latency - expected).abs() / expected < 0.02, "{} vs {}", estimate.latency, expected);
+        assert!((estimate.energy - expected / 1000.0 * 120.0).abs() < 0.01 * estimate.energy);
+        assert!((estimate.memory_mb - model.weight_memory_mb(2_000_000, BitDepth::INT8) - 64.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_calibration_fits_every_bit_depth() {
+        let mut model = CostModel::default();
+        let mut profile = HardwareProfile::detect();
+        assert!(model.calibrate(&mut profile, &CostCalibrationConfig::default()).unwrap());
+        assert!(profile.memory_bandwidth_gbps > 0.0);
+        for bit_depth in [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16] {
+            assert!(model.is_fitted(profile.class(), bit_depth));
+            let short = model.predict(4_000_000, 1, bit_depth, &profile);
+            let long = model.predict(4_000_000, 64, bit_depth, &profile);
+            assert!(short.latency.is_finite() && short.latency > 0.0);
+            assert!(long.latency >= short.latency);
+        }
+    }
+
+    #[test]
+    fn test_expert_cost_estimates_reuse_one_calibration() {
+        // A second calibration would time the kernels again and almost surely differ
+        let first = estimate_expert_costs(1_000_000, 8).unwrap();
+        assert_eq!(estimate_expert_costs(1_000_000, 8).unwrap(), first);
+    }
+
+    #[test]
+    fn test_petri_weights_come_from_cost_model() {
+        let model = CostModel::default().shared();
+        let cpu = HardwareProfile::new("cpu");
+        let mut net = PetriNetMonoid::new(model.clone());
+        net.log_transition(PetriPlace::ExpertDispatch, PetriPlace::Infer, BitDepth::INT4, 1_000_000, 16, &cpu);
+        net.log_transition(PetriPlace::Infer, PetriPlace::CompressResult, BitDepth::FP16, 1_000_000, 16, &cpu);
+
+        let [int4, fp16] = [0, 1].map(|i| net.get_transitions()[i].clone());
+        assert_eq!(int4.weight, model.borrow().predict(1_000_000, 16, BitDepth::INT4, &cpu).latency);
+        assert!(int4.weight < fp16.weight);
+        let total = net.total_cost();
+        assert!((total.latency - int4.weight - fp16.weight).abs() < 1e-5);
+        assert_eq!(total.memory_mb, fp16.cost.memory_mb);
+    }
+}
+
+#[cfg(test)]
+mod drift_tests {
+    use crate::drift::{DriftConfig, DriftDetectorKind, DriftMonitor};
+    use crate::policy_engine::{BitDepth, QLearningConfig, QLearningPolicy};
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rng::{set_global_seed, standard_normal};
+    use crate::simulator::{DriftEvent, SimulatorConfig, WorkloadSimulator};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use super::trace;
+
+    // Alarms over 1000 noisy rewards whose mean drops by 0.3 at step 500
+    fn alarms(detector: DriftDetectorKind) -> Vec<u64> {
+        let mut monitor = DriftMonitor::new(DriftConfig {
+            detector,
+            ..DriftConfig::default()
+        });
+        let mut rng = StdRng::seed_from_u64(3);
+        let t = trace().build();
+        for i in 0..1000 {
+            let mean = if i < 500 { 0.8 } else { 0.5 };
+            monitor.observe(&t, mean + 0.05 * standard_normal(&mut rng));
+        }
+        monitor.history().iter().map(|a| a.step).collect()
+    }
+
+    #[test]
+    fn test_detectors_find_reward_shift() {
+        for detector in [DriftDetectorKind::PageHinkley, DriftDetectorKind::Adwin] {
+            let steps = alarms(detector);
+            assert_eq!(steps.len(), 1, "{:?} alarms at {:?}", detector, steps);
+            assert!(steps[0] > 500 && steps[0] < 560, "{:?} alarm at {}", detector, steps[0]);
+        }
+    }
+
+    #[test]
+    fn test_alarm_reports_means_per_stream() {
+        let mut monitor = DriftMonitor::new(DriftConfig::default());
+        let (stable, shifted) = (trace().expert("stable").build(), trace().expert("shifted").build());
+        for i in 0..200 {
+            monitor.observe(&stable, 0.7);
+            monitor.observe(&shifted, if i < 100 { 0.9 } else { 0.2 });
+            // Switching depths is not drift: each depth is its own stream
+            let depth = if i % 2 == 0 { BitDepth::INT4 } else { BitDepth::FP16 };
+            monitor.observe(&trace().expert("switching").bit_depth(depth).build(), if i % 2 == 0 { 0.5 } else { 0.9 });
+        }
+        let history = monitor.history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].expert_id, "shifted");
+        assert_eq!((history[0].bit_depth, history[0].hardware_class.as_str()), (BitDepth::INT8, "cpu"));
+        assert!((history[0].mean_before - 0.9).abs() < 0.05);
+        assert!((history[0].mean_after - 0.2).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_q_learning_reexplores_after_drift() {
+        set_global_seed(1);
+        // INT8 was the best depth until its accuracy collapses on every expert
+        let config = SimulatorConfig {
+            drift: vec![DriftEvent {
+                start_step: 4000,
+                accuracy_shift: [0.0, -0.3, 0.0],
+                ..DriftEvent::default()
+            }],
+            episode_length: 10_000, // drift is scheduled within an episode
+            ..SimulatorConfig::default()
+        };
+        let run = |detect: bool| {
+            let mut sim = WorkloadSimulator::new(config.clone()).unwrap();
+            sim.reset(7).unwrap();
+            let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+            let q_config = QLearningConfig {
+                exploration_rate: 0.5,
+                exploration_decay: 0.999,
+                ..QLearningConfig::default()
+            };
+            let mut policy = QLearningPolicy::with_config(sim.gate(), sim.registry(), reward, q_config);
+            if detect {
+                policy.set_drift_detection(DriftConfig::default());
+            }
+            let rewards = sim.run_policy(&mut policy, 6000).unwrap();
+            let after = rewards[4500..].iter().sum::<f32>() / 1500.0;
+            (after, policy.drift_history().to_vec())
+        };
+        let (baseline, _) = run(false);
+        let (recovered, history) = run(true);
+        assert!(history.iter().any(|a| a.step > 4000 && a.mean_after < a.mean_before));
+        assert!(recovered > baseline, "with detection {} vs without {}", recovered, baseline);
+    }
+}
+
+#[cfg(test)]
+mod exploration_tests {
+    use crate::exploration::{
+        Boltzmann, EpsilonGreedy, ExplorationConfig, ExplorationContext, ExplorationStrategy, LevyFlight, Ucb,
+    };
+    use crate::policy_engine::{BitDepth, QLearningConfig, QLearningPolicy};
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rng::set_global_seed;
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // `probabilities` must describe what `select` samples, since it is logged as the propensity
+    fn assert_matches_sampling(strategy: &dyn ExplorationStrategy, values: &[f32]) {
+        let context = ExplorationContext {
+            state_key: "s",
+            values,
+        };
+        let probs = strategy.probabilities(&context);
+        assert!((probs.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        let mut rng = StdRng::seed_from_u64(5);
+        let draws = 20_000;
+        let mut counts = vec![0usize; values.len()];
+        for _ in 0..draws {
+            counts[strategy.select(&context, &mut rng)] += 1;
+        }
+        for (p, n) in probs.iter().zip(&counts) {
+            assert!((p - *n as f32 / draws as f32).abs() < 0.015, "{:?} vs {:?}", probs, counts);
+        }
+    }
+
+    #[test]
+    fn test_propensities_match_sampling() {
+        let values = [0.2, 0.9, 0.5];
+        assert_matches_sampling(&EpsilonGreedy { epsilon: 0.3, ..EpsilonGreedy::default() }, &values);
+        assert_matches_sampling(&Boltzmann { temperature: 0.3, ..Boltzmann::default() }, &values);
+        assert_matches_sampling(&LevyFlight { epsilon: 0.6, ..LevyFlight::default() }, &values);
+        let ladder = [0.0, 0.1, 0.2, 0.3, 0.9, 0.1, 0.0];
+        assert_matches_sampling(&LevyFlight { epsilon: 1.0, exponent: 1.5, ..LevyFlight::default() }, &ladder);
+    }
+
+    #[test]
+    fn test_levy_flights_are_heavy_tailed() {
+        let ladder = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let context = ExplorationContext {
+            state_key: "s",
+            values: &ladder,
+        };
+        let levy = LevyFlight { epsilon: 1.0, ..LevyFlight::default() };
+        let probs = levy.probabilities(&context);
+        // Mostly short hops around the greedy rung, yet every rung stays reachable
+        assert!(probs[2] > probs[1] && probs[4] > probs[6] && probs[6] > probs[9]);
+        assert!(probs.iter().all(|p| *p > 0.01));
+        assert!(probs[2] + probs[4] > 0.4);
+    }
+
+    #[test]
+    fn test_ucb_tries_every_action_then_exploits() {
+        let mut ucb = Ucb::new(0.1);
+        let values = [0.1, 0.8, 0.3];
+        let context = ExplorationContext {
+            state_key: "s",
+            values: &values,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut chosen = Vec::new();
+        for _ in 0..20 {
+            let action = ucb.select(&context, &mut rng);
+            ucb.observe("s", action);
+            chosen.push(action);
+        }
+        assert_eq!(&chosen[..3], &[0, 1, 2]);
+        assert!(chosen[3..].iter().filter(|&&a| a == 1).count() > 12);
+        assert_eq!(ucb.probabilities(&context).iter().filter(|p| **p == 1.0).count(), 1);
+    }
+
+    #[test]
+    fn test_strategies_learn_on_simulator() {
+        let strategies: Vec<ExplorationConfig> = [
+            r#"{"strategy":"epsilon_greedy","epsilon":0.5,"decay":0.9995}"#,
+            r#"{"strategy":"boltzmann","temperature":0.5,"decay":0.9995}"#,
+            r#"{"strategy":"ucb","c":0.5}"#,
+            r#"{"strategy":"levy_flight","epsilon":0.5,"decay":0.9995,"exponent":1.5}"#,
+        ]
+        .iter()
+        .map(|json| serde_json::from_str(json).unwrap())
+        .collect();
+
+        let mut int4_sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        int4_sim.reset(7).unwrap();
+        let int4: f32 = (0..1000).map(|_| int4_sim.step(BitDepth::INT4).unwrap().reward).sum::<f32>() / 1000.0;
+        for exploration in strategies {
+            set_global_seed(1);
+            let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+            sim.reset(7).unwrap();
+            let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+            let config = QLearningConfig {
+                exploration: Some(exploration.clone()),
+                ..QLearningConfig::default()
+            };
+            let mut policy = QLearningPolicy::with_config(sim.gate(), sim.registry(), reward, config);
+            let rewards = sim.run_policy(&mut policy, 10_000).unwrap();
+            let learned = rewards[9000..].iter().sum::<f32>() / 1000.0;
+            assert!(learned > int4 + 0.02, "{:?}: learned {} vs INT4 {}", exploration, learned, int4);
+        }
+    }
+}
+
+#[cfg(test)]
+mod dqn_tests {
+    use crate::checkpoint::PolicyCheckpoint;
+    use crate::dqn::{DqnConfig, DqnPolicy, PrioritizedReplay, Transition};
+    use crate::policy_engine::{BitDepth, ExpertId, HardwareProfile};
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rng::set_global_seed;
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn transition(action: usize) -> Transition {
+        Transition {
+            state: vec![0.0],
+            action,
+            reward: 0.0,
+            next_state: vec![0.0],
+        }
+    }
+
+    #[test]
+    fn test_replay_samples_in_proportion_to_priority() {
+        let mut replay = PrioritizedReplay::new(4);
+        for action in 0..6 {
+            replay.push(transition(action));
+        }
+        // Full buffers overwrite the oldest slots
+        assert_eq!(replay.len(), 4);
+
+        // Priorities (|δ| + ε)^α with α = 1: 1, 2, 3, 4
+        replay.update_priorities(&[0, 1, 2, 3], &[1.0, 2.0, 3.0, 4.0], 1.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut counts = [0usize; 4];
+        for _ in 0..2000 {
+            let (slots, weights) = replay.sample(5, 1.0, &mut rng);
+            for (&slot, &weight) in slots.iter().zip(&weights) {
+                counts[slot] += 1;
+                // Importance weights undo the sampling bias: rarer transitions weigh more
+                let expected = 1.0 / (slot + 1) as f32 / slots.iter().map(|s| 1.0 / (s + 1) as f32).fold(0.0, f32::max);
+                assert!((weight - expected).abs() < 1e-4, "{} vs {}", weight, expected);
+            }
+        }
+        let total: usize = counts.iter().sum();
+        for (slot, n) in counts.iter().enumerate() {
+            let p = (slot + 1) as f32 / 10.0;
+            assert!((*n as f32 / total as f32 - p).abs() < 0.02, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_dqn_learns_on_simulator() {
+        set_global_seed(1);
+        let mut int4_sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        int4_sim.reset(7).unwrap();
+        let int4: f32 = (0..1000).map(|_| int4_sim.step(BitDepth::INT4).unwrap().reward).sum::<f32>() / 1000.0;
+
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(7).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut policy = DqnPolicy::with_config(sim.gate(), sim.registry(), reward, DqnConfig::default()).unwrap();
+        let rewards = sim.run_policy(&mut policy, 4000).unwrap();
+        let learned = rewards[3000..].iter().sum::<f32>() / 1000.0;
+        assert!(policy.train_steps() > 0 && policy.last_loss().is_some_and(f32::is_finite));
+        assert!(learned > int4 + 0.02, "learned {} vs INT4 {}", learned, int4);
+    }
+
+    #[test]
+    fn test_dqn_checkpoint_round_trip() {
+        set_global_seed(2);
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(3).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let config = DqnConfig {
+            hidden_size: 16,
+            ..DqnConfig::default()
+        };
+        let mut trained = DqnPolicy::with_config(sim.gate(), sim.registry(), reward.clone(), config).unwrap();
+        sim.run_policy(&mut trained, 300).unwrap();
+        let bytes = trained.save_checkpoint().unwrap();
+
+        // A default-sized policy takes the checkpoint's network shape and learned state
+        let mut restored = DqnPolicy::new(sim.gate(), sim.registry(), reward).unwrap();
+        restored.load_checkpoint(&bytes).unwrap();
+        assert_eq!(restored.config().hidden_size, 16);
+        assert_eq!(restored.train_steps(), trained.train_steps());
+        let registry = sim.registry();
+        let experts: Vec<ExpertId> = registry.borrow().active().map(|e| e.id.clone()).collect();
+        let hardware = HardwareProfile::new("cpu");
+        for expert in &experts {
+            assert_eq!(
+                trained.depth_values(expert, &hardware).unwrap(),
+                restored.depth_values(expert, &hardware).unwrap()
+            );
+        }
+        assert!(restored.load_checkpoint(b"not a checkpoint").is_err());
+    }
+}
+
+#[cfg(test)]
+mod ensemble_tests {
+    use crate::policy_engine::BitDepth;
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rl_optimize_bit_depth::{majority_distribution, PolicyKind, PolicySetup, RLOptimizer, VotingMode};
+    use crate::rng::set_global_seed;
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+
+    fn optimizer_on(sim: &WorkloadSimulator, setup: &str) -> RLOptimizer {
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut optimizer = RLOptimizer::new(sim.gate(), sim.registry(), reward).unwrap();
+        optimizer.set_policy_setup(serde_json::from_str(setup).unwrap());
+        optimizer
+    }
+
+    #[test]
+    fn test_shadows_are_scored_without_acting() {
+        set_global_seed(4);
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(7).unwrap();
+        let mut optimizer =
+            optimizer_on(&sim, r#"{"primary":"q_learning","shadows":["thompson","q_learning","lin_ucb"]}"#);
+        assert_eq!(optimizer.policy_setup().shadows, vec![PolicyKind::Thompson, PolicyKind::LinUcb]);
+        sim.run_optimizer(&mut optimizer, 2000).unwrap();
+
+        let report = optimizer.policy_report();
+        assert_eq!(report.voting, VotingMode::Primary);
+        assert_eq!(report.policies.len(), 3);
+        assert!(report.scored > 1000, "{:?}", report);
+        let primary = &report.policies[0];
+        assert!(primary.primary && primary.agreement == 1.0);
+        // On-policy, every importance weight is 1 and the estimate is the logged reward
+        let logged = report.logged_reward.unwrap();
+        assert!((primary.estimated_reward.unwrap() - logged).abs() < 1e-3, "{:?}", report);
+        for shadow in &report.policies[1..] {
+            assert!(!shadow.primary && shadow.decisions > 1000 && shadow.scored == report.scored);
+            assert!(shadow.agreement > 0.0 && shadow.agreement < 1.0, "{:?}", shadow);
+            assert!(shadow.estimated_reward.is_some_and(|r| (0.0..=1.5).contains(&r)), "{:?}", shadow);
+        }
+        // Policies outside the setup neither learn nor appear in the report
+        assert!(report.policies.iter().all(|p| p.policy != PolicyKind::Ppo));
+
+        // Switching replaces the primary and leaves the shadows as they are
+        optimizer.set_policy(PolicyKind::Thompson);
+        assert_eq!(optimizer.policy(), PolicyKind::Thompson);
+        assert_eq!(optimizer.policy_setup().shadows, vec![PolicyKind::LinUcb]);
+        optimizer.set_policy(PolicyKind::LinUcb);
+        assert!(optimizer.policy_setup().shadows.is_empty());
+    }
+
+    #[test]
+    fn test_voting_modes_learn_and_log_propensities() {
+        let mut int4_sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        int4_sim.reset(7).unwrap();
+        let int4: f32 = (0..1000).map(|_| int4_sim.step(BitDepth::INT4).unwrap().reward).sum::<f32>() / 1000.0;
+        for voting in ["majority", "average", "weighted"] {
+            set_global_seed(1);
+            let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+            sim.reset(7).unwrap();
+            let setup = format!(r#"{{"primary":"thompson","shadows":["lin_ucb","q_learning"],"voting":"{}"}}"#, voting);
+            let mut optimizer = optimizer_on(&sim, &setup);
+            let rewards = sim.run_optimizer(&mut optimizer, 2000).unwrap();
+            let learned = rewards[1000..].iter().sum::<f32>() / 1000.0;
+            assert!(learned > int4 + 0.02, "{}: learned {} vs INT4 {}", voting, learned, int4);
+
+            let result = sim.step(BitDepth::INT8).unwrap();
+            let probabilities = optimizer.bit_depth_probabilities(&result.trace);
+            assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-4, "{}: {:?}", voting, probabilities);
+            let report = optimizer.policy_report();
+            assert!(report.policies.iter().all(|p| p.decisions > 0 && p.estimated_reward.is_some()));
+        }
+        assert_eq!(PolicySetup::default().voting, VotingMode::Primary);
+        assert!(PolicySetup::default().shadows.is_empty());
+    }
+
+    #[test]
+    fn test_majority_distribution_matches_enumeration() {
+        let distributions = [[0.2, 0.5, 0.3], [0.6, 0.1, 0.3], [0.0, 0.5, 0.5], [0.25, 0.25, 0.5], [1.0, 0.0, 0.0]];
+        // Every joint vote, with the primary winning ties it takes part in
+        let mut expected = [0.0f32; 3];
+        for joint in 0..3usize.pow(distributions.len() as u32) {
+            let votes: Vec<usize> = (0..distributions.len()).map(|i| joint / 3usize.pow(i as u32) % 3).collect();
+            let probability: f32 = votes.iter().zip(&distributions).map(|(v, d)| d[*v]).product();
+            let mut counts = [0usize; 3];
+            votes.iter().for_each(|v| counts[*v] += 1);
+            let most = *counts.iter().max().unwrap();
+            let winner = if counts[votes[0]] == most { votes[0] } else { counts.iter().position(|c| *c == most).unwrap() };
+            expected[winner] += probability;
+        }
+        let computed = majority_distribution(&distributions);
+        for (c, e) in computed.iter().zip(&expected) {
+            assert!((c - e).abs() < 1e-5, "{:?} vs {:?}", computed, expected);
+        }
+
+        // Large ensembles stay cheap
+        let many = majority_distribution(&vec![[0.3, 0.4, 0.3]; 100]);
+        assert!((many.iter().sum::<f32>() - 1.0).abs() < 1e-3);
+        assert!(many[1] > 0.8, "{:?}", many);
+    }
+}
+
+#[cfg(test)]
+mod decision_tests {
+    use crate::checkpoint::PolicyCheckpoint;
+    use crate::policy_engine::{
+        BitDepth, BitPrecisionPolicy, QLearningConfig, QLearningPolicy, QuantizationDecision,
+    };
+    use crate::cost_model::CostModel;
+    use crate::reward::{LatencySloConfig, RewardConfig, WeightedSumReward};
+    use crate::rl_optimize_bit_depth::{PolicyKind, RLOptimizer};
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+    use crate::trace_buffer::InferenceTrace;
+    use std::collections::HashMap;
+    use super::trace;
+
+    #[test]
+    fn test_decisions_move_along_the_ladder() {
+        use BitDepth::*;
+        use QuantizationDecision::*;
+        assert_eq!(Up.apply(INT4), INT8);
+        assert_eq!(Up.apply(INT8), FP16);
+        assert_eq!(Up.apply(FP16), FP16);
+        assert_eq!(Down.apply(INT4), INT4);
+        assert_eq!(Step(2).apply(INT4), FP16);
+        assert_eq!(Step(-5).apply(FP16), INT4);
+        assert_eq!(Set(INT8).apply(FP16), INT8);
+        assert_eq!(Set(INT8).offset(INT4), 1);
+        for from in [INT4, INT8, FP16] {
+            for to in [INT4, INT8, FP16] {
+                assert_eq!(QuantizationDecision::between(from, to).apply(from), to);
+            }
+        }
+        assert_eq!(QuantizationDecision::between(FP16, INT4), Step(-2));
+
+        // Single-rung moves keep their plain JSON form
+        assert_eq!(serde_json::to_string(&Up).unwrap(), r#""Up""#);
+        let set: QuantizationDecision = serde_json::from_str(r#"{"Set":"FP16"}"#).unwrap();
+        assert_eq!(set, Set(FP16));
+        let step: QuantizationDecision = serde_json::from_str(r#"{"Step":-2}"#).unwrap();
+        assert_eq!(step, Step(-2));
+    }
+
+    #[test]
+    fn test_q_learning_actions_depend_on_current_depth() {
+        let sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut policy = QLearningPolicy::new(sim.gate(), sim.registry(), reward, 0.0);
+        // INT4 -> INT8 and INT8 -> FP16 are both "Up", but separate entries of the table
+        policy.update_policy(trace().bit_depth(BitDepth::INT4).build());
+        policy.update_policy(trace().build());
+        policy.update_policy(trace().bit_depth(BitDepth::FP16).build());
+        let table = policy.q_table();
+        assert!(table["e0:INT4:cpu"][1] != 0.0 && table["e0:INT4:cpu"][2] == 0.0);
+        assert!(table["e0:INT8:cpu"][2] != 0.0 && table["e0:INT8:cpu"][1] == 0.0);
+    }
+
+    // Optimizer acting on a greedy Q-table that sends every expert straight to FP16
+    fn greedy_fp16_optimizer(sim: &WorkloadSimulator, slo: Option<LatencySloConfig>) -> RLOptimizer {
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let config = QLearningConfig {
+            exploration_rate: 0.0,
+            min_exploration_rate: 0.0,
+            ..QLearningConfig::default()
+        };
+        let mut greedy = QLearningPolicy::with_config(sim.gate(), sim.registry(), reward.clone(), config);
+        let mut table = HashMap::new();
+        for expert in sim.registry().borrow().active() {
+            for depth in ["INT4", "INT8", "FP16"] {
+                for class in ["cpu", "gpu"] {
+                    table.insert(format!("{}:{}:{}", expert.id.0, depth, class), [0.0, 0.0, 1.0]);
+                }
+            }
+        }
+        greedy.set_q_table(table);
+
+        let mut optimizer = match slo {
+            Some(slo) => RLOptimizer::with_latency_slo(sim.gate(), sim.registry(), reward, slo).unwrap(),
+            None => RLOptimizer::new(sim.gate(), sim.registry(), reward).unwrap(),
+        };
+        optimizer.load_policy(PolicyKind::QLearning, &greedy.save_checkpoint().unwrap()).unwrap();
+        optimizer
+    }
+
+    fn int4_simulator() -> WorkloadSimulator {
+        WorkloadSimulator::new(SimulatorConfig {
+            initial_bit_depth: BitDepth::INT4,
+            ..SimulatorConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_optimizer_jumps_several_rungs() {
+        let mut sim = int4_simulator();
+        let observation = sim.reset(5).unwrap();
+        let mut optimizer = greedy_fp16_optimizer(&sim, None);
+        let result = sim.step(BitDepth::INT4).unwrap();
+        let decision = optimizer
+            .optimize_bit_depth(result.trace, &observation.input, &observation.hardware_profile)
+            .unwrap();
+        assert_eq!(decision, QuantizationDecision::Step(2));
+        assert_eq!(sim.registry().borrow().bit_depth(&observation.expert_id), Some(BitDepth::FP16));
+    }
+
+    #[test]
+    fn test_latency_slo_caps_the_planned_depth() {
+        let mut sim = int4_simulator();
+        let observation = sim.reset(5).unwrap();
+        let result = sim.step(BitDepth::INT4).unwrap();
+        let hardware = observation.hardware_profile.hardware_type.clone();
+        let num_params = sim.registry().borrow().get(&observation.expert_id).unwrap().num_params();
+
+        // Measured latencies of 1, 3 and 6 ms at INT4, INT8 and FP16 against a 4 ms budget
+        let cost_model = CostModel::default().shared();
+        for _ in 0..50 {
+            for (bit_depth, latency) in [(BitDepth::INT4, 1.0), (BitDepth::INT8, 3.0), (BitDepth::FP16, 6.0)] {
+                let measured = InferenceTrace {
+                    bit_depth,
+                    latency,
+                    ..result.trace.clone()
+                };
+                cost_model.borrow_mut().observe(&measured, num_params);
+            }
+        }
+        let slo = LatencySloConfig {
+            budgets: HashMap::from([(hardware, 4.0)]),
+            ..LatencySloConfig::default()
+        };
+        let mut optimizer = greedy_fp16_optimizer(&sim, Some(slo));
+        optimizer.set_cost_model(cost_model);
+
+        let decision = optimizer
+            .optimize_bit_depth(result.trace, &observation.input, &observation.hardware_profile)
+            .unwrap();
+        // The SLO overrode the policy's FP16, so the decision names the depth it imposed
+        assert_eq!(decision, QuantizationDecision::Set(BitDepth::INT8));
+        assert_eq!(sim.registry().borrow().bit_depth(&observation.expert_id), Some(BitDepth::INT8));
+    }
+}
+
+#[cfg(test)]
+mod sensitivity_tests {
+    use crate::policy_engine::BitDepth;
+    use crate::sensitivity::{analyze_sensitivity, hessian_diagonal, plan_bit_depths, TensorSensitivity};
+    use candle_core::{Device, Tensor};
+    use std::collections::HashMap;
+
+    fn sensitivity(name: &str, num_params: usize, losses: [f32; 3]) -> TensorSensitivity {
+        TensorSensitivity {
+            name: name.to_string(),
+            shape: vec![num_params],
+            num_params,
+            loss_impact: vec![
+                (BitDepth::INT4, losses[0]),
+                (BitDepth::INT8, losses[1]),
+                (BitDepth::FP16, losses[2]),
+            ],
+        }
+    }
 
     #[test]
-    fn test_rl_convergence() {
-        let mut zeta = ZetaVaultSynergy::new();
-        let input_tensor = Tensor::zeros((10, 10), candle_core::DType::F32, &Device::Cpu).unwrap();
-        let hardware_profile = HardwareProfile {
-            hardware_type: "cpu".to_string(),
-        };
+    fn test_hessian_diagonal_is_mean_squared_activation() {
+        let acts = Tensor::from_slice(&[1.0f32, 0.0, 3.0, 2.0], (2, 2), &Device::Cpu).unwrap();
+        assert_eq!(hessian_diagonal(&acts, 2).unwrap(), vec![5.0, 2.0]);
+        assert!(hessian_diagonal(&acts, 3).is_err());
+    }
+
+    #[test]
+    fn test_sensitivity_weights_errors_by_activation_energy() {
+        let values: Vec<f32> = (0..64).map(|i| ((i * 37 % 17) as f32 - 8.0) / 7.0).collect();
+        let mut weights = HashMap::new();
+        weights.insert("w".to_string(), Tensor::from_slice(&values, (16, 4), &Device::Cpu).unwrap());
+        weights.insert("bias".to_string(), Tensor::zeros(4, candle_core::DType::F32, &Device::Cpu).unwrap());
+
+        let report = analyze_sensitivity(&weights, &HashMap::new(), 4, true).unwrap();
+        assert_eq!(report.len(), 1, "1D tensors are not scored");
+        let losses: Vec<f32> = report[0].loss_impact.iter().map(|(_, l)| *l).collect();
+        assert!(losses[0] > losses[1] && losses[1] >= losses[2], "{:?}", losses);
+
+        // Inputs that never fire make quantization error on their weights free
+        let mut activations = HashMap::new();
+        activations.insert("w".to_string(), Tensor::zeros((8, 4), candle_core::DType::F32, &Device::Cpu).unwrap());
+        let silent = analyze_sensitivity(&weights, &activations, 4, true).unwrap();
+        assert!(silent[0].loss_impact.iter().all(|(_, l)| *l == 0.0));
+    }
+
+    #[test]
+    fn test_stacked_expert_weights_are_rejected() {
+        let mut weights = HashMap::new();
+        weights.insert("experts".to_string(), Tensor::zeros((2, 4, 4), candle_core::DType::F32, &Device::Cpu).unwrap());
+        let err = analyze_sensitivity(&weights, &HashMap::new(), 4, true).unwrap_err();
+        assert!(err.to_string().contains("experts"), "{}", err);
+    }
 
-        // Simulate workload with 5 experts
-        let experts = vec![
-            ExpertId("expert1".to_string()),
-            ExpertId("expert2".to_string()),
-            ExpertId("expert3".to_string()),
-            ExpertId("expert4".to_string()),
-            ExpertId("expert5".to_string()),
+    #[test]
+    fn test_knapsack_upgrades_most_sensitive_tensor_first() {
+        let sensitivities = [
+            sensitivity("a", 100, [10.0, 1.0, 0.0]),
+            sensitivity("b", 100, [1.0, 0.5, 0.0]),
         ];
-        let bit_depths = [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16];
-
-        // Run 1000 iterations
-        let mut q_values = vec![];
-        for _ in 0..1000 {
-            let trace = InferenceTrace {
-                expert_id: experts[rand::random::<usize>() % experts.len()].clone(),
-                bit_depth: bit_depths[rand::random::<usize>() % bit_depths.len()],
-                hardware_profile: hardware_profile.clone(),
-                accuracy: rand::random::<f32>(),
-                latency: rand::random::<f32>() * 0.2,
-                token_loss: rand::random::<f32>() * 0.05,
-                decision: QuantizationDecision::Hold,
-                input_size: 10,
+
+        let plan = plan_bit_depths(&sensitivities, 6.0).unwrap();
+        let depths: Vec<BitDepth> = plan.tensors.iter().map(|t| t.bit_depth).collect();
+        assert_eq!(depths, vec![BitDepth::INT8, BitDepth::INT4]);
+        assert_eq!(plan.average_bits, 6.0);
+        assert_eq!(plan.total_estimated_loss, 2.0);
+
+        let plan = plan_bit_depths(&sensitivities, 4.0).unwrap();
+        assert!(plan.tensors.iter().all(|t| t.bit_depth == BitDepth::INT4));
+
+        let plan = plan_bit_depths(&sensitivities, 16.0).unwrap();
+        assert!(plan.tensors.iter().all(|t| t.bits == 16));
+        assert!(plan.average_bits <= plan.target_bits);
+    }
+
+    #[test]
+    fn test_knapsack_rejects_infeasible_target() {
+        let sensitivities = [sensitivity("a", 10, [1.0, 0.5, 0.0])];
+        assert!(plan_bit_depths(&sensitivities, 3.0).is_err());
+        assert!(plan_bit_depths(&[], 3.0).unwrap().tensors.is_empty());
+
+        // Candidates must be listed cheapest first
+        let mut unsorted = sensitivity("b", 10, [1.0, 0.5, 0.0]);
+        unsorted.loss_impact.reverse();
+        assert!(plan_bit_depths(&[unsorted], 16.0).is_err());
+    }
+}
+
+#[cfg(test)]
+mod reward_tests {
+    use crate::policy_engine::BitDepth;
+    use crate::reward::{
+        compute_reward, LatencySloConfig, LatencySloReward, RewardConfig, RewardConstraint, RewardMetric,
+        RewardModel, RewardWeights, WeightedSumReward,
+    };
+    use crate::trace_buffer::InferenceTrace;
+    use std::collections::HashMap;
+    use super::trace;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn test_weighted_sum_and_constraints() {
+        let t = trace().bit_depth(BitDepth::INT4).latency(2.0).token_loss(1.0).build();
+        assert!(close(WeightedSumReward::from_lambdas(0.1, 0.05).reward(&t), 0.9 - 0.2 - 0.05));
+
+        // Model size is storage relative to FP16
+        let config = RewardConfig {
+            weights: RewardWeights {
+                accuracy: 0.0,
+                latency: 0.0,
+                token_loss: 0.0,
+                model_size: 1.0,
+                ..RewardWeights::default()
+            },
+            ..RewardConfig::default()
+        };
+        assert!(close(WeightedSumReward::new(config).reward(&t), -0.25));
+
+        // Every violated bound subtracts its penalty
+        let config = RewardConfig {
+            constraints: vec![
+                RewardConstraint {
+                    metric: RewardMetric::Latency,
+                    min: None,
+                    max: Some(1.5),
+                    penalty: 10.0,
+                },
+                RewardConstraint {
+                    metric: RewardMetric::Accuracy,
+                    min: Some(0.95),
+                    max: None,
+                    penalty: 1.0,
+                },
+            ],
+            ..RewardConfig::default()
+        };
+        let constrained = WeightedSumReward::new(config);
+        assert!(close(constrained.reward(&t), 0.65 - 11.0));
+        assert!(close(constrained.reward(&InferenceTrace { accuracy: 0.99, latency: 1.0, ..t }), 0.99 - 0.1 - 0.05));
+    }
+
+    #[test]
+    fn test_normalization_standardizes_after_two_samples() {
+        let config = RewardConfig {
+            weights: RewardWeights {
+                accuracy: 0.0,
+                token_loss: 0.0,
+                latency: 1.0,
+                ..RewardWeights::default()
+            },
+            normalize: true,
+            ..RewardConfig::default()
+        };
+        let mut model = WeightedSumReward::new(config);
+        model.observe(&trace().latency(1.0).build());
+        assert!(close(model.reward(&trace().latency(3.0).build()), -3.0), "one sample leaves metrics raw");
+
+        // Latencies 1 and 3: mean 2, sample std √2
+        model.observe(&trace().latency(3.0).build());
+        assert!(close(model.reward(&trace().latency(3.0).build()), -1.0 / 2f32.sqrt()));
+        assert!(close(model.reward(&trace().latency(2.0).build()), 0.0));
+    }
+
+    #[test]
+    fn test_latency_slo_dual_ascent() {
+        let base = WeightedSumReward::from_lambdas(0.0, 0.0).shared();
+        let mut slo = LatencySloReward::new(
+            base,
+            LatencySloConfig {
+                budgets: HashMap::from([("cpu".to_string(), 1.0)]),
+                percentile: 0.5,
+                window: 4,
+                dual_learning_rate: 1.0,
+                max_multiplier: 3.0,
+                ..LatencySloConfig::default()
+            },
+        );
+
+        // λ grows by (p50 - budget) / budget per trace while the SLO is violated, up to its cap
+        for _ in 0..2 {
+            slo.observe(&trace().latency(2.0).build());
+        }
+        assert!(close(slo.multiplier("cpu"), 2.0));
+        assert!(close(slo.reward(&trace().latency(2.0).build()), 0.9 - 2.0 * (2.0 - 1.0)));
+        for _ in 0..5 {
+            slo.observe(&trace().latency(2.0).build());
+        }
+        assert!(close(slo.multiplier("cpu"), 3.0));
+
+        // The window slides: latencies [2, 0.5, 0.5, 0.5] have a median under budget
+        for _ in 0..3 {
+            slo.observe(&trace().latency(0.5).build());
+        }
+        let status = slo.status("cpu").unwrap();
+        assert!(close(status.observed_percentile, 0.5));
+        assert!(close(status.violation_rate, 0.25));
+        for _ in 0..10 {
+            slo.observe(&trace().latency(0.5).build());
+        }
+        assert_eq!(slo.multiplier("cpu"), 0.0, "the multiplier never goes negative");
+
+        // Classes without a budget are unconstrained and untracked
+        slo.observe(&trace().hardware("gpu").latency(50.0).build());
+        assert!(close(slo.reward(&trace().hardware("gpu").latency(50.0).build()), 0.9));
+        assert!(slo.status("gpu").is_none());
+        assert_eq!(slo.statuses().len(), 1);
+    }
+
+    #[test]
+    fn test_latency_slo_is_kept_per_hardware_class() {
+        let base = WeightedSumReward::from_lambdas(0.0, 0.0).shared();
+        let mut slo = LatencySloReward::new(
+            base,
+            LatencySloConfig {
+                budgets: HashMap::from([("cuda".to_string(), 1.0)]),
+                percentile: 0.5,
+                dual_learning_rate: 1.0,
+                ..LatencySloConfig::default()
+            },
+        );
+        // Device-specific types share the budget and multiplier of their class
+        slo.observe(&trace().hardware("cuda:0").latency(2.0).build());
+        slo.observe(&trace().hardware("gpu-a100").latency(2.0).build());
+        assert!(close(slo.multiplier("gpu"), 2.0));
+        assert!(close(slo.multiplier("cuda:1"), 2.0));
+        let statuses = slo.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!((statuses[0].hardware_class.as_str(), statuses[0].budget), ("gpu", 1.0));
+    }
+
+    #[test]
+    fn test_compute_reward_from_json() {
+        let t = serde_json::to_string(&trace().latency(2.0).build()).unwrap();
+        let reward = compute_reward(&t, r#"{"weights":{"accuracy":1.0,"latency":0.5,"tokenLoss":0.0}}"#).unwrap();
+        assert!(close(reward, 0.9 - 1.0));
+    }
+}
+
+#[cfg(test)]
+mod kv_cache_tests {
+    use crate::kv_cache::{EvictionEvent, EvictionHook, KvCacheConfig, KvPrecision, KvScaleGranularity, QuantizedKvCache};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const HEADS: usize = 2;
+    const HEAD_DIM: usize = 8;
+
+    // [token][head][head_dim] activations with one outlier channel
+    fn tokens(count: usize, offset: usize) -> Vec<f32> {
+        (0..count * HEADS * HEAD_DIM)
+            .map(|i| {
+                let i = i + offset * HEADS * HEAD_DIM;
+                let outlier = if i % HEAD_DIM == 3 { 8.0 } else { 1.0 };
+                outlier * ((i as f32 * 0.37).sin() + 0.1)
+            })
+            .collect()
+    }
+
+    // One head's slice of [token][head][head_dim] data
+    fn head(data: &[f32], head: usize) -> Vec<f32> {
+        data.chunks(HEADS * HEAD_DIM)
+            .flat_map(|token| token[head * HEAD_DIM..(head + 1) * HEAD_DIM].to_vec())
+            .collect()
+    }
+
+    fn config(precision: KvPrecision) -> KvCacheConfig {
+        KvCacheConfig {
+            block_tokens: 4,
+            ..KvCacheConfig::new(2, HEADS, HEAD_DIM, precision)
+        }
+    }
+
+    struct Recorder(Rc<RefCell<Vec<EvictionEvent>>>);
+
+    impl EvictionHook for Recorder {
+        fn on_evict(&mut self, event: &EvictionEvent) {
+            self.0.borrow_mut().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_per_format() {
+        for precision in [KvPrecision::INT8, KvPrecision::INT4, KvPrecision::FP8] {
+            for granularity in [KvScaleGranularity::PerToken, KvScaleGranularity::PerChannel] {
+                let mut cache = QuantizedKvCache::new(KvCacheConfig {
+                    key_granularity: granularity,
+                    value_granularity: granularity,
+                    ..config(precision)
+                })
+                .unwrap();
+                let keys = tokens(8, 0);
+                let values = tokens(8, 100);
+                cache.append(0, &keys, &values).unwrap();
+                assert_eq!(cache.len(0), Ok(8));
+                assert_eq!(cache.len(1), Ok(0));
+
+                for h in 0..HEADS {
+                    let (read_keys, read_values) = cache.read(0, h).unwrap();
+                    for (original, restored) in [(head(&keys, h), read_keys), (head(&values, h), read_values)] {
+                        assert_eq!(original.len(), restored.len());
+                        let max_abs = original.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+                        for (o, r) in original.iter().zip(&restored) {
+                            let bound = match precision {
+                                KvPrecision::INT8 => max_abs / 254.0,
+                                KvPrecision::INT4 => max_abs / 14.0,
+                                KvPrecision::FP8 => o.abs() / 16.0 + max_abs * 1e-3,
+                            };
+                            assert!((o - r).abs() <= bound + 1e-6, "{:?} {:?}: {} vs {}", precision, granularity, o, r);
+                        }
+                    }
+                }
+                let stats = cache.memory_stats();
+                assert_eq!(stats.tokens, 8);
+                assert!(stats.compression_ratio > 1.0, "{:?}: {:?}", precision, stats);
+            }
+        }
+    }
+
+    #[test]
+    fn test_residual_tail_stays_full_precision() {
+        let mut cache = QuantizedKvCache::new(config(KvPrecision::INT4)).unwrap();
+        let keys = tokens(6, 0);
+        cache.append(1, &keys, &keys).unwrap();
+        // One block of 4 quantized tokens and a 2-token f32 tail
+        let (read_keys, _) = cache.read(1, 1).unwrap();
+        let original = head(&keys, 1);
+        assert_eq!(read_keys[4 * HEAD_DIM..], original[4 * HEAD_DIM..]);
+        assert_ne!(read_keys[..4 * HEAD_DIM], original[..4 * HEAD_DIM]);
+
+        // Completing the block quantizes the tail
+        cache.append(1, &tokens(2, 6), &tokens(2, 6)).unwrap();
+        assert_eq!(cache.len(1), Ok(8));
+        let (read_keys, _) = cache.read(1, 1).unwrap();
+        assert_ne!(read_keys[4 * HEAD_DIM..6 * HEAD_DIM], original[4 * HEAD_DIM..]);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_eviction_drops_oldest_blocks_and_notifies() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut cache = QuantizedKvCache::new(KvCacheConfig {
+            max_tokens: Some(6),
+            ..config(KvPrecision::INT8)
+        })
+        .unwrap();
+        cache.add_eviction_hook(Box::new(Recorder(events.clone())));
+
+        let all = tokens(14, 0);
+        let split = 10 * HEADS * HEAD_DIM;
+        cache.append(0, &all[..split], &all[..split]).unwrap();
+        assert_eq!(cache.len(0), Ok(6));
+        cache.append(0, &all[split..], &all[split..]).unwrap();
+        assert_eq!(cache.len(0), Ok(6));
+
+        let positions: Vec<(usize, usize, usize)> =
+            events.borrow().iter().map(|e| (e.layer, e.tokens, e.first_position)).collect();
+        assert_eq!(positions, vec![(0, 4, 0), (0, 4, 4)]);
+
+        // What remains is tokens 8..14: one quantized block and the exact tail
+        let (read_keys, _) = cache.read(0, 0).unwrap();
+        let expected = head(&all, 0)[8 * HEAD_DIM..].to_vec();
+        assert_eq!(read_keys.len(), expected.len());
+        assert_eq!(read_keys[4 * HEAD_DIM..], expected[4 * HEAD_DIM..]);
+        assert!(read_keys.iter().zip(&expected).all(|(r, e)| (r - e).abs() < 0.1));
+
+        assert_eq!(cache.evict_oldest_block(0), Ok(4));
+        assert_eq!(cache.evict_oldest_block(0), Ok(0), "the f32 tail is never evicted");
+        assert_eq!(cache.len(0), Ok(2));
+    }
+
+    #[test]
+    fn test_invalid_config_and_indices() {
+        for (layers, heads, dim, block) in [(0, 2, 8, 4), (2, 0, 8, 4), (2, 2, 0, 4), (2, 2, 8, 0)] {
+            let config = KvCacheConfig {
+                block_tokens: block,
+                ..KvCacheConfig::new(layers, heads, dim, KvPrecision::INT8)
+            };
+            assert!(QuantizedKvCache::new(config).is_err());
+        }
+
+        let mut cache = QuantizedKvCache::new(config(KvPrecision::INT8)).unwrap();
+        assert!(cache.len(2).is_err());
+        assert!(cache.read(2, 0).is_err());
+        assert!(cache.read(0, HEADS).is_err());
+        assert!(cache.evict_oldest_block(2).is_err());
+        assert!(cache.append(2, &tokens(1, 0), &tokens(1, 0)).is_err());
+        assert!(cache.append(0, &tokens(1, 0)[1..], &tokens(1, 0)[1..]).is_err());
+        assert!(cache.append(0, &tokens(1, 0), &tokens(2, 0)).is_err());
+        assert!(cache.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod qmatmul_tests {
+    use crate::qmatmul::{
+        available_backends, dot_f32, dot_f32_portable, dot_i4_i8, dot_i4_i8_portable, dot_i8, dot_i8_portable,
+        gemm_i8_i32, matmul_i4_f32, matmul_i8, KernelBackend, QuantizedMatrix, QuantizedMatrixI4, QuantizedMatrixI8,
+    };
+
+    // Lengths around every kernel's vector width, so both the SIMD body and the scalar tail run
+    const LENGTHS: [usize; 14] = [0, 1, 3, 4, 7, 8, 15, 16, 17, 31, 32, 33, 65, 257];
+
+    fn pattern(len: usize, seed: usize) -> Vec<f32> {
+        (0..len).map(|i| (((i + seed) * 37 % 101) as f32 - 50.0) / 25.0).collect()
+    }
+
+    fn reference(x: &[f32], weights: &[f32], m: usize, rows: usize, cols: usize) -> Vec<f32> {
+        (0..m * rows)
+            .map(|idx| {
+                let (i, j) = (idx / rows, idx % rows);
+                dot_f32_portable(&x[i * cols..(i + 1) * cols], &weights[j * cols..(j + 1) * cols])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_simd_kernels_match_portable() {
+        let backends = available_backends();
+        assert_eq!(backends.last(), Some(&KernelBackend::Portable));
+        for backend in backends {
+            for len in LENGTHS {
+                // Full i8 range, including -128 whose square is the largest product
+                let a: Vec<i8> = (0..len).map(|i| (i * 97 % 256) as u8 as i8).collect();
+                let b: Vec<i8> = (0..len).map(|i| (255 - i * 31 % 256) as u8 as i8).collect();
+                let saturated = vec![i8::MIN; len];
+                assert_eq!(dot_i8(backend, &a, &b), dot_i8_portable(&a, &b), "{:?} len {}", backend, len);
+                assert_eq!(
+                    dot_i8(backend, &saturated, &saturated),
+                    dot_i8_portable(&saturated, &saturated),
+                    "{:?} len {}",
+                    backend,
+                    len
+                );
+
+                // Every nibble value against the full i8 range
+                let packed: Vec<u8> = (0..len).map(|i| (i * 53 % 256) as u8).collect();
+                let codes: Vec<i8> = (0..2 * len).map(|i| (i * 89 % 256) as u8 as i8).collect();
+                assert_eq!(
+                    dot_i4_i8(backend, &packed, &codes),
+                    dot_i4_i8_portable(&packed, &codes),
+                    "{:?} len {}",
+                    backend,
+                    len
+                );
+
+                let (x, y) = (pattern(len, 1), pattern(len, 7));
+                let expected = dot_f32_portable(&x, &y);
+                let tolerance = 1e-5 * (1.0 + x.iter().zip(&y).map(|(p, q)| (p * q).abs()).sum::<f32>());
+                assert!((dot_f32(backend, &x, &y) - expected).abs() <= tolerance, "{:?} len {}", backend, len);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "differ in length")]
+    fn test_dot_rejects_mismatched_lengths() {
+        dot_i8(KernelBackend::Portable, &[1, 2, 3], &[1, 2]);
+    }
+
+    #[test]
+    fn test_int8_matmul_matches_f32() {
+        let (m, rows, cols) = (3, 5, 37);
+        let weights = pattern(rows * cols, 3);
+        let x = pattern(m * cols, 11);
+        let quantized = QuantizedMatrixI8::from_f32(&weights, rows, cols).unwrap();
+        let out = matmul_i8(&x, &quantized, m).unwrap();
+        for (o, r) in out.iter().zip(reference(&x, &weights, m, rows, cols)) {
+            assert!((o - r).abs() < 0.05 * (1.0 + r.abs()), "{} vs {}", o, r);
+        }
+
+        assert!(QuantizedMatrixI8::from_f32(&weights[1..], rows, cols).is_err());
+        assert!(QuantizedMatrixI8::from_f32(&[], 0, 0).is_err());
+        assert!(matmul_i8(&x[1..], &quantized, m).is_err());
+        let codes = vec![1i8; m * cols];
+        assert!(gemm_i8_i32(&codes, &quantized, m, &mut vec![0; m * rows - 1]).is_err());
+        assert!(gemm_i8_i32(&codes[1..], &quantized, m, &mut vec![0; m * rows]).is_err());
+    }
+
+    #[test]
+    fn test_int4_matmul_matches_f32() {
+        let (m, rows, cols, group_size) = (2, 4, 48, 16);
+        let weights = pattern(rows * cols, 5);
+        let x = pattern(m * cols, 13);
+        let quantized = QuantizedMatrixI4::from_f32(&weights, rows, cols, group_size).unwrap();
+        assert_eq!(quantized.scales.len(), rows * cols / group_size);
+        let out = matmul_i4_f32(&x, &quantized, m).unwrap();
+        let scale = x.iter().map(|v| v.abs()).sum::<f32>() * 2.0 / 14.0;
+        for (o, r) in out.iter().zip(reference(&x, &weights, m, rows, cols)) {
+            assert!((o - r).abs() <= scale, "{} vs {}", o, r);
+        }
+
+        // Odd, zero and non-dividing group sizes and short inputs are rejected
+        for group_size in [0, 3, 32] {
+            assert!(QuantizedMatrixI4::from_f32(&weights, rows, cols, group_size).is_err(), "{}", group_size);
+        }
+        assert!(QuantizedMatrixI4::from_f32(&weights[2..], rows, cols, 16).is_err());
+        assert!(matmul_i4_f32(&x[1..], &quantized, m).is_err());
+    }
+
+    #[test]
+    fn test_quantized_matrix_handle() {
+        let (m, rows, cols) = (2, 3, 32);
+        let weights = pattern(rows * cols, 2);
+        let x = pattern(m * cols, 9);
+        let exact = reference(&x, &weights, m, rows, cols);
+        for (bit_depth, tolerance) in [(16, 1e-5), (8, 0.02), (4, 0.2)] {
+            let matrix = QuantizedMatrix::new(weights.clone(), rows, cols, bit_depth, 16).unwrap();
+            assert_eq!((matrix.rows(), matrix.cols(), matrix.bit_depth()), (rows, cols, bit_depth));
+            let out = matrix.matmul(x.clone(), m).unwrap();
+            assert_eq!(out.len(), m * rows);
+            let close = out.iter().zip(&exact).all(|(o, r)| (o - r).abs() <= tolerance * (1.0 + r.abs()));
+            assert!(close, "{}: {:?} vs {:?}", bit_depth, out, exact);
+            assert!(matrix.matmul_f32(&x[1..], m).is_err());
+        }
+
+        assert!(QuantizedMatrix::build(weights.clone(), rows, cols, 2, 16).is_err());
+        assert!(QuantizedMatrix::build(weights[1..].to_vec(), rows, cols, 16, 16).is_err());
+    }
+}
+
+#[cfg(test)]
+mod ppo_tests {
+    use crate::policy_engine::{
+        invoke_ppo_policy, BitDepth, BitPrecisionPolicy, ExpertId, HardwareProfile, PPOConfig, PPOPolicy,
+    };
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rng::{seeded_rng, set_global_seed};
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+    use crate::trace_buffer::InferenceTrace;
+
+    #[test]
+    fn test_bit_depth_from_bits() {
+        for depth in [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16] {
+            assert_eq!(BitDepth::from_bits(depth.bits()), Some(depth));
+        }
+        assert_eq!(BitDepth::from_bits(1), None);
+    }
+
+    #[test]
+    fn test_reported_current_depth_drives_the_state() {
+        let sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        let mut policy = PPOPolicy::new(sim.gate(), sim.registry(), reward).unwrap();
+        let expert = ExpertId("e0".to_string());
+        let hardware = HardwareProfile::new("cpu");
+        let mut draws = |current: Option<BitDepth>| {
+            policy.set_rng(seeded_rng(5));
+            (0..300)
+                .map(|_| policy.select_bit_depth_from(&expert, current, &hardware))
+                .collect::<Vec<_>>()
+        };
+        let unknown = draws(None);
+        assert_eq!(unknown, draws(None));
+        assert_eq!(unknown, draws(Some(BitDepth::INT8)), "INT8 is the fallback depth");
+        assert_ne!(draws(Some(BitDepth::INT4)), draws(Some(BitDepth::FP16)));
+    }
+
+    #[test]
+    fn test_ppo_learns_only_from_depths_it_sampled() {
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(3).unwrap();
+        let depths = [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16];
+        let traces: Vec<InferenceTrace> = (0..48).map(|i| sim.step(depths[i % 3]).unwrap().trace).collect();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+        // Identical initial weights; only `rollout_size` decides whether a policy ever trains
+        let build = |rollout_size: usize| {
+            set_global_seed(9);
+            let config = PPOConfig {
+                rollout_size,
+                minibatch_size: 8,
+                ..PPOConfig::default()
             };
-            zeta.infer(input_tensor.clone(), hardware_profile.clone());
-            q_values.push(trace.accuracy - 0.1 * trace.latency - 0.05 * trace.token_loss);
+            PPOPolicy::with_config(sim.gate(), sim.registry(), reward.clone(), config).unwrap()
+        };
+        let probe = &traces[0];
+
+        // Depths chosen elsewhere carry no behaviour probability, so nothing is trained on them
+        let (mut off_policy, mut frozen) = (build(8), build(usize::MAX));
+        for trace in &traces {
+            off_policy.update_policy(trace.clone());
+            frozen.update_policy(trace.clone());
+        }
+        assert_eq!(off_policy.bit_depth_probabilities(probe), frozen.bit_depth_probabilities(probe));
+
+        // Its own draws are trained on
+        let (mut on_policy, mut frozen) = (build(8), build(usize::MAX));
+        for trace in &traces {
+            let bit_depth = on_policy.select_bit_depth(&trace.expert_id, &trace.hardware_profile);
+            let trace = InferenceTrace { bit_depth, ..trace.clone() };
+            on_policy.update_policy(trace.clone());
+            frozen.update_policy(trace);
+        }
+        assert_ne!(on_policy.bit_depth_probabilities(probe), frozen.bit_depth_probabilities(probe));
+    }
+
+    #[test]
+    fn test_invoke_ppo_policy_takes_ts_state() {
+        let state = r#"{"expertId":"e0","currentBitDepth":16,"hardwareClass":"gpu","context":{"batch":4}}"#;
+        assert!([4, 8, 16].contains(&invoke_ppo_policy(state).unwrap()));
+        let legacy = r#"{"expertId":"e0","hardwareClass":"cpu"}"#;
+        assert!([4, 8, 16].contains(&invoke_ppo_policy(legacy).unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod policy_loader_tests {
+    use crate::bandit::LinUcbPolicy;
+    use crate::checkpoint::{checkpoint_policy, PolicyCheckpoint};
+    use crate::dqn::{DqnConfig, DqnPolicy};
+    use crate::offline::{parse_trace_log, train_offline_policy};
+    use crate::policy_engine::BitDepth;
+    use crate::reward::{RewardConfig, WeightedSumReward};
+    use crate::rl_optimize_bit_depth::{
+        load_policy, policy_bit_depth_probabilities, policy_select_bit_depth, policy_update, save_policy,
+    };
+    use crate::simulator::{SimulatorConfig, WorkloadSimulator};
+
+    #[test]
+    fn test_offline_q_table_loads_through_tagged_loader() {
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(4).unwrap();
+        let depths = [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16];
+        let traces: Vec<String> = (0..300)
+            .map(|i| serde_json::to_string(&sim.step(depths[i % 3]).unwrap().trace).unwrap())
+            .collect();
+        let trace = traces[0].clone();
+
+        let output: serde_json::Value =
+            serde_json::from_str(&train_offline_policy(&traces.join("\n"), "{}").unwrap()).unwrap();
+        let checkpoint = serde_json::to_vec(&output["checkpoint"]).unwrap();
+        assert_eq!(load_policy(checkpoint).unwrap(), "q_learning");
+
+        // The shared Q-learning policy now holds the trained table
+        let saved: serde_json::Value = serde_json::from_slice(&save_policy("q_learning").unwrap()).unwrap();
+        assert_eq!(saved["state"], output["checkpoint"]["state"]);
+        let probabilities = policy_bit_depth_probabilities("q_learning", &trace).unwrap();
+        assert_eq!(probabilities.len(), 3);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+
+        // The loaded policies keep selecting and learning
+        for policy in ["q_learning", "ppo"] {
+            for trace in &traces[..20] {
+                assert!([4, 8, 16].contains(&policy_select_bit_depth(policy, trace).unwrap()));
+                policy_update(policy, trace).unwrap();
+            }
+        }
+        let updated: serde_json::Value = serde_json::from_slice(&save_policy("q_learning").unwrap()).unwrap();
+        assert_ne!(updated["state"], output["checkpoint"]["state"]);
+    }
+
+    #[test]
+    fn test_trace_log_errors_name_the_file_line() {
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(6).unwrap();
+        let trace = serde_json::to_string(&sim.step(BitDepth::INT8).unwrap().trace).unwrap();
+
+        let log = format!("{}\n\n{}\n", trace, trace);
+        assert_eq!(parse_trace_log(&log).unwrap().len(), 2);
+        let error = parse_trace_log(&format!("{}\n\nnot json\n", trace)).unwrap_err();
+        assert!(error.starts_with("Line 3:"), "{}", error);
+    }
+
+    #[test]
+    fn test_checkpoints_are_tagged_with_their_policy() {
+        let mut sim = WorkloadSimulator::new(SimulatorConfig::default()).unwrap();
+        sim.reset(5).unwrap();
+        let reward = WeightedSumReward::new(RewardConfig::default()).shared();
+
+        let bandit = LinUcbPolicy::new(sim.gate(), sim.registry(), reward.clone());
+        let bandit_bytes = bandit.save_checkpoint().unwrap();
+        assert_eq!(checkpoint_policy(&bandit_bytes).unwrap(), "lin_ucb");
+        assert_eq!(load_policy(bandit_bytes).unwrap(), "lin_ucb");
+
+        let config = DqnConfig {
+            hidden_size: 8,
+            ..DqnConfig::default()
+        };
+        let mut dqn = DqnPolicy::with_config(sim.gate(), sim.registry(), reward, config).unwrap();
+        sim.run_policy(&mut dqn, 100).unwrap();
+        let dqn_bytes = dqn.save_checkpoint().unwrap();
+        assert_eq!(checkpoint_policy(&dqn_bytes).unwrap(), "dqn");
+        assert_eq!(load_policy(dqn_bytes.clone()).unwrap(), "dqn");
+        assert_eq!(checkpoint_policy(&save_policy("dqn").unwrap()).unwrap(), "dqn");
+
+        assert!(checkpoint_policy(b"not a checkpoint").is_err());
+        assert!(checkpoint_policy(br#"{"state":{}}"#).is_err());
+    }
+}
+
+#[cfg(test)]
+mod gating_tests {
+    use crate::bandit::LinUcbPolicy;
+    use crate::expert_registry::ExpertRegistry;
+    use crate::gating::{GatingFunction, SoftmaxTopKGate};
+    use crate::policy_engine::{BitDepth, BitPrecisionPolicy, ExpertId, HardwareProfile};
+    use crate::reward::WeightedSumReward;
+    use candle_core::{DType, Device, Tensor};
+    use std::rc::Rc;
+
+    fn experts(n: usize) -> Vec<ExpertId> {
+        (0..n).map(|i| ExpertId(format!("e{}", i))).collect()
+    }
+
+    // Router whose logits for an all-ones input are 1, 2 and 3
+    fn gate(top_k: usize) -> SoftmaxTopKGate {
+        let router = Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0], (3, 3), &Device::Cpu).unwrap();
+        SoftmaxTopKGate::new(experts(3), router, top_k).unwrap()
+    }
+
+    #[test]
+    fn test_top_k_routing_renormalizes_weights() {
+        let input = Tensor::ones(3, DType::F32, &Device::Cpu).unwrap();
+        let routed = gate(2).route(&input).unwrap();
+        let ids: Vec<&str> = routed.iter().map(|(id, _)| id.0.as_str()).collect();
+        assert_eq!(ids, ["e2", "e1"]);
+        let e = std::f32::consts::E;
+        assert!((routed[0].1 - e / (e + 1.0)).abs() < 1e-5);
+        assert!((routed.iter().map(|(_, w)| w).sum::<f32>() - 1.0).abs() < 1e-5);
+
+        // Tokens are averaged: a second token favouring e0 pulls it into the top 2
+        let tokens = Tensor::from_vec(vec![1.0f32, 1.0, 1.0, 10.0, 0.0, 0.0], (2, 3), &Device::Cpu).unwrap();
+        let routed = gate(2).route(&tokens).unwrap();
+        assert_eq!(routed[0].0 .0, "e0");
+
+        let mismatched = Tensor::zeros((3, 2), DType::F32, &Device::Cpu).unwrap();
+        assert!(SoftmaxTopKGate::new(experts(3), mismatched, 1).is_err());
+    }
+
+    #[test]
+    fn test_policies_skip_retired_experts() {
+        let mut registry = ExpertRegistry::new(1, true);
+        for id in experts(3) {
+            registry.register(id, 0, vec![2, 2], vec![0.1, -0.2, 0.3, -0.4], BitDepth::INT8).unwrap();
+        }
+        let registry = registry.shared();
+        let reward = WeightedSumReward::from_lambdas(0.1, 0.05).shared();
+        let policy = LinUcbPolicy::new(Rc::new(gate(2)), registry.clone(), reward);
+        let input = Tensor::ones(3, DType::F32, &Device::Cpu).unwrap();
+        let cpu = HardwareProfile::new("cpu");
+
+        let selected: Vec<String> = policy.select_experts(&input, &cpu).into_iter().map(|(id, _)| id.0).collect();
+        assert_eq!(selected, ["e2", "e1"]);
+        registry.borrow_mut().retire(&ExpertId("e2".to_string())).unwrap();
+        let selected: Vec<String> = policy.select_experts(&input, &cpu).into_iter().map(|(id, _)| id.0).collect();
+        assert_eq!(selected, ["e1"]);
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use crate::expert_registry::{ExpertRegistry, ExpertStatus};
+    use crate::policy_engine::{BitDepth, ExpertId};
+    use candle_core::{Device, Tensor};
+    use super::trace;
+
+    fn id(name: &str) -> ExpertId {
+        ExpertId(name.to_string())
+    }
+
+    #[test]
+    fn test_register_requantize_and_retire() {
+        let weights: Vec<f32> = (0..32).map(|i| (i as f32 - 16.0) / 8.0).collect();
+        let mut registry = ExpertRegistry::new(2, true);
+        registry.register(id("e0"), 0, vec![4, 8], weights.clone(), BitDepth::INT4).unwrap();
+        assert!(registry.register(id("e0"), 0, vec![4, 8], weights.clone(), BitDepth::INT4).is_err());
+        assert!(registry.register(id("e1"), 0, vec![3, 8], weights.clone(), BitDepth::INT4).is_err());
+
+        let error = |registry: &ExpertRegistry| {
+            let entry = registry.get(&id("e0")).unwrap();
+            entry
+                .effective_weights()
+                .iter()
+                .zip(&weights)
+                .map(|(q, w)| (q - w).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let int4 = error(&registry);
+        assert_eq!(registry.get(&id("e0")).unwrap().quantized.as_ref().unwrap().blocks.len(), 2);
+        registry.requantize(&id("e0"), BitDepth::INT8).unwrap();
+        assert_eq!(registry.bit_depth(&id("e0")), Some(BitDepth::INT8));
+        assert!(error(&registry) < int4);
+        registry.requantize(&id("e0"), BitDepth::FP16).unwrap();
+        assert!(registry.get(&id("e0")).unwrap().quantized.is_none());
+        assert_eq!(error(&registry), 0.0);
+
+        assert!(registry.requantize(&id("missing"), BitDepth::INT8).is_err());
+        registry.retire(&id("e0")).unwrap();
+        assert!(!registry.is_active(&id("e0")));
+        assert_eq!(registry.get(&id("e0")).unwrap().status, ExpertStatus::Retired);
+        assert!(registry.requantize(&id("e0"), BitDepth::INT8).is_err());
+        assert_eq!(registry.active().count(), 0);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_usage_statistics_are_running_means() {
+        let mut registry = ExpertRegistry::new(1, true);
+        registry.register(id("e0"), 0, vec![1, 2], vec![0.5, -0.5], BitDepth::INT8).unwrap();
+        registry.register(id("e1"), 0, vec![1, 2], vec![0.5, -0.5], BitDepth::INT8).unwrap();
+        registry.record_usage(&trace().latency(1.0).build());
+        registry.record_usage(&trace().expert("e1").latency(5.0).build());
+        registry.record_usage(&trace().latency(3.0).build());
+        registry.record_usage(&trace().expert("unknown").latency(9.0).build());
+
+        let usage = registry.get(&id("e0")).unwrap().usage;
+        assert_eq!(usage.invocations, 2);
+        assert!((usage.mean_latency - 2.0).abs() < 1e-6);
+        assert!((usage.mean_accuracy - 0.9).abs() < 1e-6);
+        assert_eq!(usage.last_used, 3);
+        assert_eq!(registry.get(&id("e1")).unwrap().usage.last_used, 2);
+    }
+
+    #[test]
+    fn test_load_safetensors_parses_layers() {
+        let w1 = Tensor::from_vec(vec![0.25f32; 8], (2, 4), &Device::Cpu).unwrap();
+        let w2 = Tensor::from_vec(vec![-0.5f32; 8], (4, 2), &Device::Cpu).unwrap();
+        let bias = Tensor::from_vec(vec![0.0f32; 4], 4, &Device::Cpu).unwrap();
+        let data = safetensors::serialize(
+            [("layers.3.experts.1.w1", &w1), ("layers.7.experts.0.w2", &w2), ("layers.3.bias", &bias)],
+            &None,
+        )
+        .unwrap();
+
+        let mut registry = ExpertRegistry::new(1, true);
+        let ids: Vec<String> = registry.load_safetensors(&data, BitDepth::INT8).unwrap().into_iter().map(|id| id.0).collect();
+        assert_eq!(ids, ["layers.3.experts.1.w1", "layers.7.experts.0.w2"]);
+        let entry = registry.get(&id("layers.7.experts.0.w2")).unwrap();
+        assert_eq!((entry.layer, entry.shape.clone()), (7, vec![4, 2]));
+        assert_eq!(entry.bit_depth, BitDepth::INT8);
+        assert!(registry.get(&id("layers.3.bias")).is_none());
+    }
+
+    #[test]
+    fn test_load_safetensors_registers_nothing_on_a_name_clash() {
+        let w = Tensor::from_vec(vec![0.25f32; 8], (2, 4), &Device::Cpu).unwrap();
+        let data = safetensors::serialize([("layers.0.w1", &w), ("layers.1.w1", &w)], &None).unwrap();
+
+        let mut registry = ExpertRegistry::new(1, true);
+        registry.register(id("layers.1.w1"), 1, vec![2, 4], vec![0.0; 8], BitDepth::FP16).unwrap();
+        assert!(registry.load_safetensors(&data, BitDepth::INT8).is_err());
+        assert_eq!(registry.len(), 1);
+        assert!(registry.get(&id("layers.0.w1")).is_none());
+    }
+}
+
+#[cfg(test)]
+mod bandit_tests {
+    use crate::bandit::{context_features, LinUcbPolicy, ThompsonSamplingPolicy};
+    use crate::checkpoint::PolicyCheckpoint;
+    use crate::expert_registry::ExpertRegistry;
+    use crate::gating::SoftmaxTopKGate;
+    use crate::policy_engine::{BitDepth, BitPrecisionPolicy, ExpertId, ExpertStats, HardwareProfile};
+    use crate::reward::WeightedSumReward;
+    use candle_core::{Device, Tensor};
+    use std::rc::Rc;
+    use super::trace;
+
+    fn policies() -> (LinUcbPolicy, ThompsonSamplingPolicy) {
+        let gate = Rc::new(SoftmaxTopKGate::random(vec![ExpertId("e0".to_string())], 4, 1).unwrap());
+        let registry = ExpertRegistry::new(1, true).shared();
+        // Reward is the accuracy alone
+        let reward = WeightedSumReward::from_lambdas(0.0, 0.0).shared();
+        (
+            LinUcbPolicy::new(gate.clone(), registry.clone(), reward.clone()),
+            ThompsonSamplingPolicy::new(gate, registry, reward),
+        )
+    }
+
+    #[test]
+    fn test_posterior_update_matches_closed_form() {
+        let (mut lin_ucb, mut thompson) = policies();
+        let x = context_features(&HardwareProfile::new("cpu"), &ExpertStats::default(), 4);
+        let norm: f32 = x.iter().map(|v| v * v).sum();
+        for bound in lin_ucb.upper_bounds(&x) {
+            assert!((bound - norm.sqrt()).abs() < 1e-4);
+        }
+
+        // With A = I + xxᵀ and b = r·x: θ̂ᵀx = r·s and xᵀA⁻¹x = s, where s = |x|² / (1 + |x|²)
+        lin_ucb.update_policy(trace().accuracy(0.8).input_size(4).build());
+        thompson.update_policy(trace().accuracy(0.8).input_size(4).build());
+        let s = norm / (1.0 + norm);
+        let bounds = lin_ucb.upper_bounds(&x);
+        assert!((bounds[1] - (0.8 * s + s.sqrt())).abs() < 1e-4, "{:?}", bounds);
+        assert!((bounds[0] - norm.sqrt()).abs() < 1e-4 && (bounds[2] - norm.sqrt()).abs() < 1e-4);
+        let means = thompson.expected_rewards(&x);
+        assert!((means[1] - 0.8 * s).abs() < 1e-4);
+        assert_eq!((means[0], means[2]), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_bandits_learn_the_better_arm() {
+        let (mut lin_ucb, mut thompson) = policies();
+        for _ in 0..50 {
+            for (bit_depth, accuracy) in [(BitDepth::INT4, 0.2), (BitDepth::INT8, 0.8), (BitDepth::FP16, 0.5)] {
+                lin_ucb.update_policy(trace().bit_depth(bit_depth).accuracy(accuracy).input_size(4).build());
+                thompson.update_policy(trace().bit_depth(bit_depth).accuracy(accuracy).input_size(4).build());
+            }
+        }
+        let probe = trace().accuracy(0.0).input_size(4).build();
+        assert_eq!(lin_ucb.bit_depth_probabilities(&probe), [0.0, 1.0, 0.0]);
+        assert!(thompson.bit_depth_probabilities(&probe)[1] > 0.9);
+
+        // The posterior survives a checkpoint round trip
+        let (mut restored, _) = policies();
+        restored.load_checkpoint(&lin_ucb.save_checkpoint().unwrap()).unwrap();
+        let x = context_features(&HardwareProfile::new("cpu"), &ExpertStats::default(), 4);
+        assert_eq!(restored.upper_bounds(&x), lin_ucb.upper_bounds(&x));
+    }
+
+    #[test]
+    fn test_selection_context_counts_tokens() {
+        let gate = Rc::new(SoftmaxTopKGate::random(vec![ExpertId("e0".to_string())], 4, 1).unwrap());
+        let mut registry = ExpertRegistry::new(1, true);
+        registry.register(ExpertId("e0".to_string()), 0, vec![1], vec![0.0], BitDepth::FP16).unwrap();
+        let reward = WeightedSumReward::from_lambdas(0.0, 0.0).shared();
+        let mut lin_ucb = LinUcbPolicy::new(gate, registry.shared(), reward);
+        // INT4 wins on 4-token requests, FP16 on 16-token ones
+        for _ in 0..50 {
+            for (input_size, best) in [(4, BitDepth::INT4), (16, BitDepth::FP16)] {
+                for bit_depth in [BitDepth::INT4, BitDepth::INT8, BitDepth::FP16] {
+                    let accuracy = if bit_depth == best { 0.9 } else { 0.1 };
+                    lin_ucb.update_policy(trace().bit_depth(bit_depth).accuracy(accuracy).input_size(input_size).build());
+                }
+            }
+        }
+        // 4 tokens of hidden size 4: 16 elements, but the context is built from 4 as in `update`
+        let input = Tensor::ones((4, 4), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let selected = lin_ucb.select_experts(&input, &HardwareProfile::new("cpu"));
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].1, BitDepth::INT4);
+    }
+}
+
+#[cfg(test)]
+mod guardrail_tests {
+    use crate::expert_registry::ExpertRegistry;
+    use crate::guardrails::{GuardReason, GuardrailConfig, Guardrails};
+    use crate::policy_engine::{BitDepth, ExpertId};
+    use std::collections::HashMap;
+    use super::trace;
+
+    fn id() -> ExpertId {
+        ExpertId("e0".to_string())
+    }
+
+    // Expert e0 at `bit_depth`, with every request inside the exploration budget
+    fn guardrails(bit_depth: BitDepth, config: GuardrailConfig) -> Guardrails {
+        let mut registry = ExpertRegistry::new(1, true);
+        registry.register(id(), 0, vec![1, 2], vec![0.5, -0.5], bit_depth).unwrap();
+        Guardrails::new(
+            registry.shared(),
+            GuardrailConfig {
+                exploration_fraction: 1.0,
+                ..config
+            },
+        )
+    }
+
+    #[test]
+    fn test_accuracy_miss_rolls_back_for_the_cooldown() {
+        let guard = guardrails(
+            BitDepth::INT4,
+            GuardrailConfig {
+                accuracy_floor: Some(0.8),
+                rollback_cooldown: 2,
+                ..GuardrailConfig::default()
+            },
+        );
+        guard.observe(&trace().build());
+        guard.observe(&trace().bit_depth(BitDepth::INT4).accuracy(0.5).build());
+
+        assert_eq!(guard.check(&id(), BitDepth::INT4), BitDepth::INT8);
+        assert_eq!(guard.check(&id(), BitDepth::INT4), BitDepth::INT8);
+        // Cooldown over: the policy may propose INT4 again
+        assert_eq!(guard.check(&id(), BitDepth::INT4), BitDepth::INT4);
+        let log = guard.audit_log();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|o| o.reasons == [GuardReason::Rollback] && o.applied == BitDepth::INT8));
+
+        // A miss at the last good depth has nothing to roll back to
+        guard.clear_audit_log();
+        guard.observe(&trace().accuracy(0.5).build());
+        assert_eq!(guard.check(&id(), BitDepth::INT4), BitDepth::INT4);
+        assert!(guard.audit_log().is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit_and_minimum_depth() {
+        let guard = guardrails(
+            BitDepth::INT4,
+            GuardrailConfig {
+                expert_min_bit_depth: HashMap::from([("e0".to_string(), BitDepth::INT8)]),
+                ..GuardrailConfig::default()
+            },
+        );
+        assert_eq!(guard.check(&id(), BitDepth::FP16), BitDepth::INT8);
+        assert_eq!(guard.check(&id(), BitDepth::INT4), BitDepth::INT8);
+        let reasons: Vec<Vec<GuardReason>> = guard.audit_log().into_iter().map(|o| o.reasons).collect();
+        assert_eq!(reasons, [vec![GuardReason::RateLimit], vec![GuardReason::MinBitDepth]]);
+
+        // Outside the exploration budget the current depth is kept
+        let frozen = Guardrails::new(
+            ExpertRegistry::new(1, true).shared(),
+            GuardrailConfig {
+                exploration_fraction: 0.0,
+                ..GuardrailConfig::default()
+            },
+        );
+        frozen.observe(&trace().build());
+        assert_eq!(frozen.check(&id(), BitDepth::FP16), BitDepth::INT8);
+        assert_eq!(frozen.audit_log()[0].reasons, [GuardReason::ExplorationBudget]);
+    }
+}
+
+#[cfg(test)]
+mod ope_tests {
+    use crate::ope::{compare_policies, evaluate_policy, OpeConfig};
+    use crate::policy_engine::{BitDepth, BitPrecisionPolicy, ExpertId, HardwareProfile};
+    use crate::reward::WeightedSumReward;
+    use crate::trace_buffer::InferenceTrace;
+    use candle_core::Tensor;
+    use super::trace;
+
+    // Fixed target distribution over INT4, INT8, FP16
+    struct FixedPolicy([f32; 3]);
+
+    impl BitPrecisionPolicy for FixedPolicy {
+        fn select_experts(&self, _: &Tensor, _: &HardwareProfile) -> Vec<(ExpertId, BitDepth)> {
+            Vec::new()
         }
 
-        // Check convergence (mock: verify q_values stabilize)
-        let last_100_avg = q_values[q_values.len() - 100..].iter().sum::<f32>() / 100.0;
-        assert!(last_100_avg > 0.5, "Q-values did not converge to expected reward");
+        fn update_policy(&mut self, _: InferenceTrace) {}
+
+        fn bit_depth_probabilities(&self, _: &InferenceTrace) -> [f32; 3] {
+            self.0
+        }
+    }
+
+    fn log() -> Vec<InferenceTrace> {
+        vec![
+            trace().bit_depth(BitDepth::INT4).propensity(0.5).accuracy(0.2).build(),
+            trace().bit_depth(BitDepth::INT8).propensity(0.5).accuracy(0.8).build(),
+            trace().bit_depth(BitDepth::INT8).propensity(0.25).accuracy(0.6).build(),
+            trace().bit_depth(BitDepth::FP16).propensity(0.25).accuracy(1.0).build(),
+            trace().bit_depth(BitDepth::INT8).accuracy(0.0).build(),
+        ]
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn test_estimators_on_a_known_log() {
+        // Reward is the accuracy alone; the target always runs INT8, so the weights are 0, 2, 4, 0
+        let reward = WeightedSumReward::from_lambdas(0.0, 0.0);
+        let report = evaluate_policy(&FixedPolicy([0.0, 1.0, 0.0]), &log(), &reward, &OpeConfig::default());
+        assert_eq!((report.samples, report.skipped), (4, 1));
+        assert!(close(report.logged_value, 0.65));
+        assert!(close(report.ips.value, (2.0 * 0.8 + 4.0 * 0.6) / 4.0));
+        assert!(close(report.snips.value, (2.0 * 0.8 + 4.0 * 0.6) / 6.0));
+        // Direct method predicts the INT8 mean 0.7, corrected by 2·(0.8 - 0.7) and 4·(0.6 - 0.7)
+        assert!(close(report.doubly_robust.value, 0.7 + (0.2 - 0.4) / 4.0));
+        assert!(close(report.effective_sample_size, 36.0 / 20.0));
+        assert!(report.ips.lower < report.ips.value && report.ips.value < report.ips.upper);
+
+        let clipped = OpeConfig {
+            max_weight: Some(3.0),
+            ..OpeConfig::default()
+        };
+        let report = evaluate_policy(&FixedPolicy([0.0, 1.0, 0.0]), &log(), &reward, &clipped);
+        assert!(close(report.ips.value, (2.0 * 0.8 + 3.0 * 0.6) / 4.0));
+    }
+
+    #[test]
+    fn test_logging_policy_recovers_logged_value() {
+        let reward = WeightedSumReward::from_lambdas(0.0, 0.0);
+        // Evaluating the uniform logging policy itself: every weight is 1
+        let traces: Vec<InferenceTrace> = [(BitDepth::INT4, 0.2), (BitDepth::INT8, 0.8), (BitDepth::FP16, 0.5)]
+            .into_iter()
+            .map(|(d, a)| trace().bit_depth(d).propensity(1.0 / 3.0).accuracy(a).build())
+            .collect();
+        let uniform = FixedPolicy([1.0 / 3.0; 3]);
+        let report = evaluate_policy(&uniform, &traces, &reward, &OpeConfig::default());
+        assert!(close(report.ips.value, report.logged_value));
+        assert!(close(report.snips.value, report.logged_value));
+        assert!(close(report.effective_sample_size, 3.0));
+
+        let comparison = compare_policies(&uniform, &FixedPolicy([0.0, 1.0, 0.0]), &traces, &reward, &OpeConfig::default());
+        assert!(close(comparison.snips_difference.value, 0.8 - 0.5));
+        assert!(close(comparison.ips_difference.value, 0.8 - 0.5));
+    }
+
+    #[test]
+    fn test_direct_method_pools_a_hardware_class() {
+        let reward = WeightedSumReward::from_lambdas(0.0, 0.0);
+        let on = |hardware: &str, bit_depth, accuracy| {
+            trace()
+                .hardware(hardware)
+                .bit_depth(bit_depth)
+                .propensity(0.5)
+                .accuracy(accuracy)
+                .build()
+        };
+        let traces = vec![
+            on("gpu-a100", BitDepth::INT4, 0.1),
+            on("cuda:0", BitDepth::INT8, 0.8),
+            on("cpu", BitDepth::INT8, 0.2),
+        ];
+        // The a100 trace borrows the INT8 mean of its class (0.8), not the all-hardware INT8 mean
+        let report = evaluate_policy(&FixedPolicy([0.0, 1.0, 0.0]), &traces, &reward, &OpeConfig::default());
+        assert!(close(report.doubly_robust.value, (0.8 + 0.8 + 0.2) / 3.0));
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 use crossbeam_queue::SegQueue;
 use serde::{Deserialize, Serialize};
+use crate::policy_engine::{BitDepth, ExpertId, HardwareProfile, QuantizationDecision};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceTrace {
@@ -11,8 +12,15 @@ pub struct InferenceTrace {
     pub token_loss: f32,
     pub decision: QuantizationDecision,
     pub input_size: usize,
+    #[serde(default)]
+    pub memory_usage: f32, // peak memory in MB, as in the TS `RLReward.metadata.memoryUsage`
+    #[serde(default)]
+    pub energy_usage: f32, // joules
+    #[serde(default)]
+    pub propensity: Option<f32>, // probability the logging policy gave `bit_depth`, for off-policy evaluation
 }
 
+#[derive(Default)]
 pub struct InferenceTraceBuffer {
     queue: SegQueue<InferenceTrace>,
 }